@@ -0,0 +1,34 @@
+#![feature(test)]
+
+extern crate test;
+
+use std::path::Path;
+use test::Bencher;
+use tao::{
+    embed::Module,
+    src::SourceMap,
+    vm::BufferIo,
+};
+
+fn compile_example(name: &str) -> Module {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+    let mut srcs = SourceMap::default();
+    let code = std::fs::read_to_string(dir.join(name)).unwrap();
+    let file = srcs.add(name, code);
+    Module::compile(&mut srcs, file, Some(dir.as_path())).unwrap_or_else(|errors| panic!("{:?}", errors))
+}
+
+// Interpreting brainfuck matches on instructions and on the front of the program and tape in every step
+#[bench]
+fn brainfuck(b: &mut Bencher) {
+    let main = compile_example("brainfuck.tao").def("main").unwrap();
+    b.iter(|| main.eval().unwrap());
+}
+
+// Parser combinators match on lists of characters and on the `Maybe`s produced by each parser
+#[bench]
+fn parse(b: &mut Bencher) {
+    let main = compile_example("parse.tao").def("main").unwrap();
+    let input = "(12 + 3) * 4 - 100 / 5\n".repeat(8) + "-(7 % 3) + 2 * (1 + 2 * (3 + 4))\nq\n";
+    b.iter(|| main.run(&mut BufferIo::new(&input)).unwrap());
+}
@@ -110,15 +110,16 @@ function handle_errors(errors) {
   for (let i = 0; i < errors.length; i++) {
     const err = errors[i];
 
-    for (let k = 0; k < err.src.error.primary_spans.length; k++) {
-      const span = err.src.error.primary_spans[k].Range;
-
-      let start = editor.session.doc.indexToPosition(span[0]);
-      let end = editor.session.doc.indexToPosition(span[1]);
+    for (let k = 0; k < err.spans.length; k++) {
+      const span = err.spans[k];
+      if (!span.primary) {
+        continue;
+      }
 
+      // Diagnostic lines and columns start at 1
       marker_ids.push(
         editor.session.addMarker(
-          new Range(start.row, start.column, end.row, end.column),
+          new Range(span.start.line - 1, span.start.column - 1, span.end.line - 1, span.end.column - 1),
           "error",
           "line",
           true
@@ -126,7 +127,7 @@ function handle_errors(errors) {
       );
     }
 
-    span.innerText += err.msg;
+    span.innerText += err.rendered;
 
     span.innerHTML += "\n\n";
   }
@@ -1,24 +1,31 @@
-use serde::Serialize;
-use tao::{error::ErrorInSrc, run_module};
+use tao::{embed::Module, src::SourceMap, vm::{BufferIo, Limits}};
 use wasm_bindgen::prelude::*;
 
-#[derive(Serialize)]
-pub struct Error<'a> {
-    pub msg: String,
-    pub src: ErrorInSrc<'a>,
-}
-
 #[wasm_bindgen]
 pub fn run(src: &str) -> Result<String, JsValue> {
-    match run_module(src) {
-        Ok(val) => Ok(val.to_string()),
+    let mut srcs = SourceMap::default();
+    let file = srcs.add("main.tao", src);
+    let mut io = BufferIo::default();
+    // Scripts run in the browser, so runaway recursion or allocation must not hang the page
+    let limits = Limits {
+        fuel: Some(100_000_000),
+        max_call_depth: Some(100_000),
+        max_locals: Some(1_000_000),
+        max_list_elements: Some(10_000_000),
+    };
+    let res = Module::compile(&mut srcs, file, None)
+        .and_then(|module| module.def("main"))
+        .and_then(|main| main.with_limits(limits).run(&mut io));
+    match res {
+        Ok(val) => Ok(format!(
+            "{}{}",
+            io.output(),
+            val.map(|val| val.to_string()).unwrap_or_default(),
+        )),
         Err(errs) => Err(JsValue::from_serde(
             &errs
                 .iter()
-                .map(|err| Error {
-                    msg: err.in_source(&src).to_string(),
-                    src: err.in_source(&src),
-                })
+                .map(|err| err.diagnostic(&srcs))
                 .collect::<Vec<_>>(),
         )
         .unwrap()),
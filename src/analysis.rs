@@ -0,0 +1,276 @@
+use std::path::Path;
+use internment::LocalIntern;
+use crate::{
+    ast,
+    embed,
+    error::{Error, Diagnostic},
+    hir::{self, Expr, Pat, TypeBinding},
+    lex,
+    module::ModuleTree,
+    node::SrcNode,
+    src::{SourceMap, FileId, Loc, Span},
+    vm::Natives,
+};
+
+type Ident = LocalIntern<String>;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SymbolKind {
+    Function,
+    Constant,
+    Data,
+    Variant,
+    TypeAlias,
+    Class,
+    Member,
+    Instance,
+    Module,
+}
+
+// A declaration within a file, for outlines
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    // The whole declaration, and the name within it
+    pub span: Span,
+    pub name_span: Span,
+    pub children: Vec<Symbol>,
+}
+
+// The result of analysing a file for editor tooling. Unlike compilation, analysis carries on past errors so that the
+// parts of the file that are valid may still be inspected.
+pub struct Analysis {
+    srcs: SourceMap,
+    file: FileId,
+    errors: Vec<Error>,
+    symbols: Vec<Symbol>,
+    modules: ModuleTree,
+    hir: hir::Program,
+}
+
+impl Analysis {
+    // Analyse `code`, loading any submodules it declares from `dir`
+    pub fn new(name: &str, code: &str, dir: Option<&Path>) -> Self {
+        let mut srcs = SourceMap::default();
+        let file = srcs.add(name, code);
+
+        let (root, mut errors) = match lex::lex(srcs.code(file), file) {
+            Ok(tokens) => ast::parse_module(&tokens),
+            Err(errors) => (SrcNode::new(ast::Module::default(), Span::none()), errors),
+        };
+        let symbols = root.decls
+            .iter()
+            .filter_map(|decl| symbol(decl))
+            .collect();
+
+        let prelude = crate::parse_prelude(&mut srcs)
+            .unwrap_or_else(|errors| panic!("The prelude failed to parse: {:?}", errors));
+        let (modules, ast, mut module_errors) = ModuleTree::load(&mut srcs, root, dir, prelude);
+        errors.append(&mut module_errors);
+        let (hir, mut hir_errors) = hir::Program::new_root_partial(&ast, &Natives::default());
+        errors.append(&mut hir_errors);
+        // Warnings about a program that only partially compiled would be misleading
+        if errors.len() == 0 {
+            errors.append(&mut hir.warnings());
+        }
+
+        Self {
+            srcs,
+            file,
+            errors,
+            symbols,
+            modules,
+            hir,
+        }
+    }
+
+    pub fn srcs(&self) -> &SourceMap {
+        &self.srcs
+    }
+
+    pub fn file(&self) -> FileId {
+        self.file
+    }
+
+    // The errors in the file, and in any submodules it loaded
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.errors
+            .iter()
+            .filter(|err| !err.is_poisoned())
+            .map(|err| err.diagnostic(&self.srcs))
+            .collect()
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    // Find the location in the file at the given line and column, both starting at 0
+    pub fn loc(&self, line: usize, column: usize) -> Loc {
+        let before = self.srcs
+            .code(self.file)
+            .lines()
+            .take(line)
+            .map(|line| line.chars().count() + 1)
+            .sum::<usize>();
+        Loc::at(before + column)
+    }
+
+    // Find the file, start and end (as lines and columns starting at 0) of a span
+    pub fn resolve(&self, span: Span) -> Option<(&str, (usize, usize), (usize, usize))> {
+        let file = span.file()?;
+        let (start, end) = span.in_context(self.srcs.code(file))?;
+        Some((self.srcs.name(file), start, end))
+    }
+
+    // Describe the type of the innermost expression or binding at `loc`, along with its span
+    pub fn hover(&self, loc: Loc) -> Option<(Span, String)> {
+        let describe = |ty: &SrcNode<crate::ty::Type>| embed::describe(&self.hir.data_ctx, ty);
+
+        let mut candidates = Vec::new();
+        for def in self.hir.root().defs() {
+            // Instance members are hidden definitions
+            if !def.name.contains('#') {
+                candidates.push((def.name.span(), format!("{} of {}", *def.name, describe(def.body.ty()))));
+            }
+            for expr in def.body.visit() {
+                match &**expr {
+                    Expr::Global(name, _) | Expr::Local(name) =>
+                        candidates.push((expr.span(), format!("{} of {}", name, describe(expr.ty())))),
+                    Expr::Func(param, _) => {
+                        binding_types(param, &mut candidates, &describe);
+                        candidates.push((expr.span(), format!("{}", describe(expr.ty()))));
+                    },
+                    Expr::Match(_, arms) => {
+                        arms
+                            .iter()
+                            .for_each(|(binding, _, _)| binding_types(binding, &mut candidates, &describe));
+                        candidates.push((expr.span(), format!("{}", describe(expr.ty()))));
+                    },
+                    _ => candidates.push((expr.span(), format!("{}", describe(expr.ty())))),
+                }
+            }
+        }
+
+        candidates
+            .into_iter()
+            .filter(|(span, _)| span.file() == Some(self.file) && span.contains(loc))
+            .min_by_key(|(span, _)| span.len())
+    }
+
+    // Find the declaration of the definition, constructor, data type or class used at `loc`
+    pub fn definition(&self, loc: Loc) -> Option<Span> {
+        self.modules.definition(self.file, loc)
+    }
+}
+
+// Find the types of the names bound by a pattern
+fn binding_types(
+    binding: &TypeBinding,
+    candidates: &mut Vec<(Span, String)>,
+    describe: &impl Fn(&SrcNode<crate::ty::Type>) -> embed::TypeDesc,
+) {
+    if let Some(name) = &binding.binding {
+        candidates.push((name.span(), format!("{} of {}", **name, describe(binding.ty()))));
+    }
+    match &*binding.pat {
+        Pat::Wildcard | Pat::Literal(_) | Pat::Range(_, _) => {},
+        Pat::List(items) | Pat::Tuple(items) | Pat::Or(items) => items
+            .iter()
+            .for_each(|item| binding_types(item, candidates, describe)),
+        Pat::ListFront(items, tail) => {
+            items
+                .iter()
+                .for_each(|item| binding_types(item, candidates, describe));
+            if let Some(tail) = tail {
+                candidates.push((tail.span(), format!("{} of {}", **tail, describe(binding.ty()))));
+            }
+        },
+        Pat::Record(fields) => fields
+            .iter()
+            .for_each(|(_, field)| binding_types(field, candidates, describe)),
+        Pat::Deconstruct(_, _, inner) => binding_types(inner, candidates, describe),
+    }
+}
+
+fn symbol(decl: &SrcNode<ast::Decl>) -> Option<Symbol> {
+    let leaf = |name: &SrcNode<Ident>, kind: SymbolKind, span: Span| Symbol {
+        name: format!("{}", **name),
+        kind,
+        span,
+        name_span: name.span(),
+        children: Vec::new(),
+    };
+
+    Some(match &**decl {
+        ast::Decl::Def(def) => leaf(
+            &def.name,
+            if let ast::Expr::Func(_, _, _) = &*def.body { SymbolKind::Function } else { SymbolKind::Constant },
+            decl.span(),
+        ),
+        ast::Decl::TypeAlias(alias) => leaf(&alias.name, SymbolKind::TypeAlias, decl.span()),
+        ast::Decl::Data(data) => Symbol {
+            children: match &*data.data_ty {
+                ast::DataType::Sum(variants) => variants
+                    .iter()
+                    .map(|(name, ty)| leaf(
+                        name,
+                        SymbolKind::Variant,
+                        ty.as_ref().map_or(name.span(), |ty| name.span().union(ty.span())),
+                    ))
+                    .collect(),
+                ast::DataType::Product(_) => Vec::new(),
+            },
+            ..leaf(&data.name, SymbolKind::Data, decl.span())
+        },
+        ast::Decl::Class(class) => Symbol {
+            children: class.members
+                .iter()
+                .map(|(name, ty)| leaf(name, SymbolKind::Member, name.span().union(ty.span())))
+                .collect(),
+            ..leaf(&class.name, SymbolKind::Class, decl.span())
+        },
+        ast::Decl::Instance(instance) => Symbol {
+            name: format!("instance {}", *instance.class),
+            children: instance.members
+                .iter()
+                .map(|(name, body)| leaf(name, SymbolKind::Member, name.span().union(body.span())))
+                .collect(),
+            ..leaf(&instance.class, SymbolKind::Instance, decl.span())
+        },
+        ast::Decl::Mod(name) => leaf(name, SymbolKind::Module, decl.span()),
+        ast::Decl::Use(_) | ast::Decl::Error { .. } => return None,
+    })
+}
+
+#[test]
+fn analysis() {
+    let code = "data Shape = | Circle Num | Square Num\n\ndef area = |s| match s {\n    | Circle r => r * r * 3\n    | Square w => w * w\n}\n\ndef main = area(Circle 2)\n\ndef broken = 1 + true\n";
+    let analysis = Analysis::new("main.tao", code, None);
+
+    // Errors do not prevent the rest of the file from being analysed
+    let diagnostics = analysis.diagnostics();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].spans[0].start.line, 10);
+
+    let names = analysis.symbols()
+        .iter()
+        .map(|symbol| symbol.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["Shape", "area", "main", "broken"]);
+    assert_eq!(analysis.symbols()[0].children.len(), 2);
+    assert_eq!(analysis.symbols()[1].kind, SymbolKind::Function);
+
+    // `r` in `r * r * 3`
+    let (_, hover) = analysis.hover(analysis.loc(3, 18)).unwrap();
+    assert_eq!(hover, "r of Num");
+    // `area` in `main`
+    let (_, hover) = analysis.hover(analysis.loc(7, 12)).unwrap();
+    assert_eq!(hover, "area of (Shape -> Num)");
+
+    let span = analysis.definition(analysis.loc(7, 17)).unwrap();
+    assert_eq!(analysis.resolve(span).map(|(_, start, _)| start), Some((0, 15)));
+    let span = analysis.definition(analysis.loc(7, 12)).unwrap();
+    assert_eq!(analysis.resolve(span).map(|(_, start, _)| start), Some((2, 4)));
+}
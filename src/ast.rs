@@ -11,7 +11,7 @@ use crate::{
 
 type Ident = LocalIntern<String>;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Literal {
     Boolean(bool),
     Char(char),
@@ -36,6 +36,12 @@ impl Path {
     }
 }
 
+impl From<Ident> for Path {
+    fn from(ident: Ident) -> Self {
+        Path(vec![ident])
+    }
+}
+
 impl fmt::Debug for Path {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         for part in self.0.iter() {
@@ -111,6 +117,12 @@ pub enum Pat {
     Tuple(Vec<SrcNode<Binding>>),
     Record(Vec<(SrcNode<Ident>, SrcNode<Binding>)>),
     Deconstruct(SrcNode<Ident>, SrcNode<Binding>),
+    // Numbers or characters between two literals, inclusive of both
+    Range(Literal, Literal),
+    // Any of several patterns, which must all bind the same names
+    Or(Vec<SrcNode<Binding>>),
+    // A pattern that failed to parse
+    Error,
 }
 
 #[derive(Debug)]
@@ -128,6 +140,8 @@ pub enum Type {
     Record(Vec<(SrcNode<Ident>, SrcNode<Self>)>),
     Func(SrcNode<Self>, SrcNode<Self>),
     Data(SrcNode<Ident>, Vec<SrcNode<Self>>),
+    // A type that failed to parse
+    Error,
 }
 
 #[derive(Debug)]
@@ -138,7 +152,8 @@ pub enum Expr {
     Unary(SrcNode<UnaryOp>, SrcNode<Self>),
     Binary(SrcNode<BinaryOp>, SrcNode<Self>, SrcNode<Self>),
     If(SrcNode<Self>, SrcNode<Self>, SrcNode<Self>),
-    Match(SrcNode<Self>, Vec<((SrcNode<Binding>, Option<SrcNode<Type>>), SrcNode<Self>)>),
+    // Each arm has a pattern (with an optional type), an optional guard and a body
+    Match(SrcNode<Self>, Vec<((SrcNode<Binding>, Option<SrcNode<Type>>), Option<SrcNode<Self>>, SrcNode<Self>)>),
     Func(SrcNode<Binding>, Option<SrcNode<Type>>, SrcNode<Self>),
     Apply(SrcNode<Self>, SrcNode<Self>),
     Access(SrcNode<Self>, SrcNode<Ident>),
@@ -149,6 +164,8 @@ pub enum Expr {
     Update(SrcNode<Self>, SrcNode<Ident>, SrcNode<Self>),
     Constructor(SrcNode<Ident>, SrcNode<Self>),
     //Do(Vec<SrcNode<DoStatement>>),
+    // An expression that failed to parse
+    Error,
 }
 
 #[derive(Debug)]
@@ -180,8 +197,8 @@ fn type_name_parser() -> Parser<impl Pattern<Error, Input=node::Node<Token>, Out
 }
 
 fn path_parser(pat: Parser<impl Pattern<Error, Input=node::Node<Token>, Output=Ident>, Error>) -> Parser<impl Pattern<Error, Input=node::Node<Token>, Output=SrcNode<Path>>, Error> {
-    just(Token::Separator)
-        .padding_for(ident_parser())
+    ident_parser()
+        .padded_by(just(Token::Separator))
         .repeated()
         .then(pat)
         .map_with_span(|(mut init, end), span| {
@@ -190,6 +207,17 @@ fn path_parser(pat: Parser<impl Pattern<Error, Input=node::Node<Token>, Output=I
         })
 }
 
+// A type name that may be qualified by the path of the module it lives in, such as `foo::Bar`
+fn qualified_type_name_parser() -> Parser<impl Pattern<Error, Input=node::Node<Token>, Output=Ident>, Error> {
+    path_parser(type_name_parser())
+        .map(|path| LocalIntern::new(path
+            .parts()
+            .iter()
+            .map(|part| part.as_str())
+            .collect::<Vec<_>>()
+            .join("::")))
+}
+
 fn number_parser() -> Parser<impl Pattern<Error, Input=node::Node<Token>, Output=f64>, Error> {
     permit_map(|token: node::Node<_>| match &*token {
         Token::Number(x) => Some(x.parse().unwrap()),
@@ -228,6 +256,12 @@ fn nested_parser<'a, O: 'a>(
     })
 }
 
+// Parses the placeholder left behind by error recovery
+fn error_parser() -> Parser<impl Pattern<Error, Input=node::Node<Token>, Output=()>, Error> {
+    just(Token::Error).to(())
+        .or(nested_parser(just(Token::Error).to(()), Delimiter::Brace))
+}
+
 fn type_parser() -> Parser<impl Pattern<Error, Input=node::Node<Token>, Output=SrcNode<Type>>, Error> {
     recursive(|ty| {
         let ty = ty.link();
@@ -238,7 +272,7 @@ fn type_parser() -> Parser<impl Pattern<Error, Input=node::Node<Token>, Output=S
             recursive(move |atom| {
                 let atom = atom.link();
 
-                let ident = type_name_parser()
+                let ident = qualified_type_name_parser()
                     .map_with_span(|ident, span| SrcNode::new(Type::Data(SrcNode::new(ident, span), Vec::new()), span));
 
                 let list = nested_parser(
@@ -266,6 +300,9 @@ fn type_parser() -> Parser<impl Pattern<Error, Input=node::Node<Token>, Output=S
                 let unknown = just(Token::QuestionMark)
                     .map(|_| Type::Unknown);
 
+                let error = error_parser()
+                    .map(|_| Type::Error);
+
                 let paren_ty = nested_parser(
                     ty.clone(),
                     Delimiter::Paren,
@@ -284,13 +321,14 @@ fn type_parser() -> Parser<impl Pattern<Error, Input=node::Node<Token>, Output=S
                         .or(tuple)
                         .or(record)
                         .or(unknown)
+                        .or(error)
                         .map_with_span(|ty, span| SrcNode::new(ty, span)));
 
                 atom
             })
         };
 
-        let data = type_name_parser()
+        let data = qualified_type_name_parser()
             .map_with_span(|name, span| SrcNode::new(name, span))
             .then(atom.clone().repeated())
             .map(|(data, params)| Type::Data(data, params))
@@ -332,8 +370,30 @@ fn binding_parser() -> Parser<impl Pattern<Error, Input=node::Node<Token>, Outpu
             let wildcard = just(Token::Wildcard)
                 .map_with_span(|pat, span| SrcNode::new(Pat::Wildcard, span));
 
+            // A string prefix, such as `"GET " ++ rest`, is the front of a list of characters
+            let prefix = string_parser()
+                .map_with_span(|s, span| (s, span))
+                .then(just(Token::Op(Op::Join))
+                    .padding_for(ident_parser()
+                        .map_with_span(|ident, span| Some(SrcNode::new(ident, span)))
+                        .or(just(Token::Wildcard).to(None))))
+                .map_with_span(|((s, s_span), tail), span| SrcNode::new(Pat::ListFront(
+                    s
+                        .chars()
+                        .map(|c| SrcNode::new(Binding::Unbound(Pat::Literal(Literal::Char(c))), s_span))
+                        .collect(),
+                    tail,
+                ), span))
+                .boxed();
+
             let litr = litr_parser()
-                .map_with_span(|pat, span| SrcNode::new(Pat::Literal(pat), span));
+                .then(just(Token::Op(Op::Range))
+                    .padding_for(litr_parser())
+                    .or_not())
+                .map_with_span(|(litr, end), span| SrcNode::new(match end {
+                    Some(end) => Pat::Range(litr, end),
+                    None => Pat::Literal(litr),
+                }, span));
 
             let tuple = nested_parser(
                 binding.clone().separated_by(just(Token::Comma)),
@@ -373,7 +433,7 @@ fn binding_parser() -> Parser<impl Pattern<Error, Input=node::Node<Token>, Outpu
                 .map_with_span(|(items, tail), span| SrcNode::new(Pat::ListFront(items, tail), span))
                 .boxed();
 
-            let deconstruct = type_name_parser()
+            let deconstruct = qualified_type_name_parser()
                 .map_with_span(|pat, span| SrcNode::new(pat, span))
                 .then(binding.or_not())
                 .map_with_span(|(data, inner), span| SrcNode::new(
@@ -382,13 +442,18 @@ fn binding_parser() -> Parser<impl Pattern<Error, Input=node::Node<Token>, Outpu
                 ))
                 .boxed();
 
+            let error = error_parser()
+                .map_with_span(|_, span| SrcNode::new(Pat::Error, span));
+
             wildcard
+                .or(prefix)
                 .or(litr)
                 .or(deconstruct)
                 .or(tuple)
                 .or(record)
                 .or(list)
                 .or(list_front)
+                .or(error)
         });
 
         // Bound
@@ -437,27 +502,53 @@ fn expr_parser() -> Parser<impl Pattern<Error, Input=node::Node<Token>, Output=S
                 .padding_for(type_parser())
                 .or_not());
 
+        // The pattern of an arm may have several alternatives, separated by pipes
+        let arm_binding = binding_parser()
+            .then(just(Token::Pipe)
+                .padding_for(binding_parser())
+                .repeated())
+            .map_with_span(|(first, mut rest), span| if rest.len() == 0 {
+                first
+            } else {
+                rest.insert(0, first);
+                SrcNode::new(Binding::Unbound(Pat::Or(rest)), span)
+            })
+            .then(just(Token::Of)
+                .padding_for(type_parser())
+                .or_not());
+
         let arm_list = nested_parser(
             just(Token::Pipe)
                 .or_not()
-                .padding_for(binding.clone()
+                .padding_for(arm_binding
+                    .then(just(Token::If)
+                        .padding_for(expr.clone())
+                        .or_not())
                     .padded_by(just(Token::RMap))
                     .then(expr.clone())
+                    .map(|((binding, guard), body)| (binding, guard, body))
                     .separated_by(just(Token::Pipe))),
             Delimiter::Brace,
-        );
+        )
+            // Arms that failed to parse become a single arm that matches anything
+            .or(error_parser()
+                .map_with_span(|_, span| vec![(
+                    (SrcNode::new(Binding::Unbound(Pat::Error), span), None),
+                    None,
+                    SrcNode::new(Expr::Error, span),
+                )]));
 
         let litr = litr_parser()
             .map_with_span(|litr, span| SrcNode::new(Expr::Literal(litr), span));
 
-        let ident = ident_parser()
-            .map_with_span(|ident, span| SrcNode::new(Expr::Path(Path(vec![ident])), span));
+        let path = path_parser(ident_parser())
+            .map_with_span(|path, span| SrcNode::new(Expr::Path(path.into_inner()), span));
 
         let intrinsic = intrinsic_parser()
             .then(paren_expr_list.clone().or_not())
             .map_with_span(|(ident, args), span| SrcNode::new(Expr::Intrinsic(ident, args.unwrap_or(Vec::new())), span));
 
-        let constructor = type_name_parser()
+        let constructor = qualified_type_name_parser()
             .map_with_span(|ident, span| SrcNode::new(ident, span))
             .then(expr.clone().or_not())
             .map_with_span(|(data, expr), span| SrcNode::new(
@@ -473,7 +564,7 @@ fn expr_parser() -> Parser<impl Pattern<Error, Input=node::Node<Token>, Output=S
             .or(just(Token::Return).padding_for(expr.clone()).map(DoStatement::Return));
 
         let atom = litr
-            .or(ident)
+            .or(path)
             .or(intrinsic)
             .or(constructor)
             // Parenthesised expression
@@ -553,6 +644,8 @@ fn expr_parser() -> Parser<impl Pattern<Error, Input=node::Node<Token>, Output=S
                     },
                 })
                 .map(|(_, expr)| expr))
+            .or(error_parser()
+                .map_with_span(|_, span| SrcNode::new(Expr::Error, span)))
             .boxed();
 
         let application = atom
@@ -687,9 +780,11 @@ fn expr_parser() -> Parser<impl Pattern<Error, Input=node::Node<Token>, Output=S
 }
 
 pub fn parse_expr(tokens: &[node::Node<Token>]) -> Result<SrcNode<Expr>, Vec<Error>> {
-    expr_parser()
-        .padded_by(end())
-        .parse(tokens.iter().cloned())
+    let mut errors = Vec::new();
+    match parse_recovering(expr_parser(), tokens.to_vec(), &mut errors) {
+        Some(expr) if errors.len() == 0 => Ok(expr),
+        _ => Err(errors),
+    }
 }
 
 fn data_type_parser() -> Parser<impl Pattern<Error, Input=node::Node<Token>, Output=SrcNode<DataType>>, Error> {
@@ -733,6 +828,8 @@ pub enum DataType {
 #[derive(Debug)]
 pub struct Def {
     pub generics: Vec<SrcNode<Ident>>,
+    // Classes that generic parameters must be instances of
+    pub given: Vec<(SrcNode<Ident>, SrcNode<Ident>)>,
     pub name: SrcNode<Ident>,
     pub ty: SrcNode<Type>,
     pub body: SrcNode<Expr>,
@@ -742,6 +839,7 @@ impl Def {
     pub fn main(body: SrcNode<Expr>) -> Self {
         Self {
             generics: Vec::new(),
+            given: Vec::new(),
             name: SrcNode::new(LocalIntern::new("main".to_string()), Span::none()),
             ty: SrcNode::new(Type::Unknown, Span::none()),
             body,
@@ -763,11 +861,38 @@ pub struct Data {
     pub data_ty: SrcNode<DataType>,
 }
 
+#[derive(Debug)]
+pub struct Class {
+    pub name: SrcNode<Ident>,
+    pub param: SrcNode<Ident>,
+    pub members: Vec<(SrcNode<Ident>, SrcNode<Type>)>,
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    pub class: SrcNode<Ident>,
+    pub generics: Vec<SrcNode<Ident>>,
+    pub given: Vec<(SrcNode<Ident>, SrcNode<Ident>)>,
+    pub ty: SrcNode<Type>,
+    pub members: Vec<(SrcNode<Ident>, SrcNode<Expr>)>,
+}
+
 #[derive(Debug)]
 pub enum Decl {
     Def(Def),
     TypeAlias(TypeAlias),
     Data(Data),
+    Class(Class),
+    Instance(Instance),
+    // A submodule, loaded from a file of the same name
+    Mod(SrcNode<Ident>),
+    // Bring an item (or module) from another module into scope
+    Use(SrcNode<Path>),
+    // A declaration that failed to parse, along with the name it declares (if known)
+    Error {
+        name: Option<SrcNode<Ident>>,
+        is_type: bool,
+    },
 }
 
 #[derive(Default, Debug)]
@@ -775,120 +900,319 @@ pub struct Module {
     pub decls: Vec<SrcNode<Decl>>,
 }
 
-fn module_parser() -> Parser<impl Pattern<Error, Input=node::Node<Token>, Output=SrcNode<Module>>, Error> {
-    recursive(|module| {
-        let module = module.link();
-
-        let generics = type_name_parser()
-            .map_with_span(|ident, span| SrcNode::new(ident, span))
-            .repeated();
-
-        let binding = binding_parser()
-            .then(just(Token::Of)
-                .padding_for(type_parser())
-                .or_not());
-
-        // let typeparams = just(Token::Given)
-        //     .padding_for(generics)
-        //     .or_not()
-        //     .map(|gs| gs.unwrap_or_default());
-
-        let def = just(Token::Def)
-            // Name
+fn decl_parser() -> Parser<impl Pattern<Error, Input=node::Node<Token>, Output=SrcNode<Decl>>, Error> {
+    let generics = type_name_parser()
+        .map_with_span(|ident, span| SrcNode::new(ident, span))
+        .repeated();
+
+    let binding = binding_parser()
+        .then(just(Token::Of)
+            .padding_for(type_parser())
+            .or_not());
+
+    // Class constraints on generic parameters, such as `given Show A, Eq B`
+    let given = just(Token::Given)
+        .padding_for(qualified_type_name_parser().map_with_span(|ident, span| SrcNode::new(ident, span))
+            .then(type_name_parser().map_with_span(|ident, span| SrcNode::new(ident, span)))
+            .separated_by(just(Token::Comma)))
+        .or_not()
+        .map(|given| given.unwrap_or_default());
+
+    let def = just(Token::Def)
+        // Name
+        .padding_for(ident_parser().map_with_span(|ident, span| SrcNode::new(ident, span)))
+        // Generic parameters
+        .then(generics.clone())
+        .then(given.clone())
+        // Optional type annotation
+        .then(just(Token::Of)
+            .padding_for(type_parser())
+            .or_not())
+        .padded_by(just(Token::Op(Op::Eq)))
+        .then(expr_parser())
+        .map_with_span(|((((name, generics), given), ty), body), span| Decl::Def(Def {
+            generics,
+            given,
+            ty: ty.unwrap_or_else(|| SrcNode::new(Type::Unknown, name.span())),
+            name,
+            body,
+        }))
+        .boxed();
+
+    let func = just(Token::Fn)
+        // Name
+        .padding_for(ident_parser().map_with_span(|ident, span| SrcNode::new(ident, span)))
+        // Generic parameters
+        .then(generics.clone())
+        .then(given.clone())
+        // Optional type annotation
+        .then(just(Token::Of)
+            .padding_for(type_parser())
+            .or_not())
+        // Parameters
+        .then(just(Token::Pipe)
+            .padding_for(binding.separated_by(just(Token::Comma)))
+            .padded_by(just(Token::Pipe))
+            .then(expr_parser())
+            .reduce_right(|(param, param_ty), body| {
+                let span = param
+                    .span()
+                    .union(param_ty
+                        .as_ref()
+                        .map(|t| t.span())
+                        .unwrap_or(Span::none()))
+                    .union(body.span());
+                SrcNode::new(Expr::Func(param, param_ty, body), span)
+            }))
+        .map_with_span(|((((name, generics), given), ty), body), span| Decl::Def(Def {
+            generics,
+            given,
+            ty: ty.unwrap_or_else(|| SrcNode::new(Type::Unknown, name.span())),
+            name,
+            body,
+        }))
+        .boxed();
+
+    let type_alias = just(Token::Type)
+        // Name
+        .padding_for(type_name_parser().map_with_span(|ident, span| SrcNode::new(ident, span)))
+        // Generic parameters
+        .then(generics.clone())
+        .padded_by(just(Token::Op(Op::Eq)))
+        .then(type_parser())
+        .map_with_span(|((name, generics), ty), span| Decl::TypeAlias(TypeAlias {
+            generics,
+            name,
+            ty,
+        }))
+        .boxed();
+
+    let data = just(Token::Data)
+        // Name
+        .padding_for(type_name_parser().map_with_span(|ident, span| SrcNode::new(ident, span)))
+        // Generic parameters
+        .then(generics.clone())
+        .padded_by(just(Token::Op(Op::Eq)))
+        .then(data_type_parser())
+        .map_with_span(|((name, generics), data_ty), span| Decl::Data(Data {
+            generics,
+            name,
+            data_ty,
+        }))
+        .boxed();
+
+    let class = just(Token::Class)
+        // Name
+        .padding_for(type_name_parser().map_with_span(|ident, span| SrcNode::new(ident, span)))
+        // Class parameter
+        .then(type_name_parser().map_with_span(|ident, span| SrcNode::new(ident, span)))
+        // Members
+        .then(just(Token::Member)
+            .padding_for(ident_parser().map_with_span(|ident, span| SrcNode::new(ident, span)))
+            .padded_by(just(Token::Of))
+            .then(type_parser())
+            .repeated())
+        .map_with_span(|((name, param), members), span| Decl::Class(Class {
+            name,
+            param,
+            members,
+        }))
+        .boxed();
+
+    let instance = just(Token::Instance)
+        // Class
+        .padding_for(qualified_type_name_parser().map_with_span(|ident, span| SrcNode::new(ident, span)))
+        // Generic parameters
+        .then(generics)
+        .then(given)
+        // Instance type
+        .then(just(Token::Of)
+            .padding_for(type_parser()))
+        // Members
+        .then(just(Token::Member)
             .padding_for(ident_parser().map_with_span(|ident, span| SrcNode::new(ident, span)))
-            // Generic parameters
-            .then(generics.clone())
-            // Optional type annotation
-            .then(just(Token::Of)
-                .padding_for(type_parser())
-                .or_not())
             .padded_by(just(Token::Op(Op::Eq)))
             .then(expr_parser())
-            .map_with_span(|(((name, generics), ty), body), span| Decl::Def(Def {
-                generics,
-                ty: ty.unwrap_or_else(|| SrcNode::new(Type::Unknown, name.span())),
-                name,
-                body,
-            }))
-            .boxed();
+            .repeated())
+        .map_with_span(|((((class, generics), given), ty), members), span| Decl::Instance(Instance {
+            class,
+            generics,
+            given,
+            ty,
+            members,
+        }))
+        .boxed();
+
+    let module = just(Token::Mod)
+        .padding_for(ident_parser().map_with_span(|ident, span| SrcNode::new(ident, span)))
+        .map(|name| Decl::Mod(name))
+        .boxed();
+
+    let import = just(Token::Use)
+        .padding_for(path_parser(ident_parser().or(type_name_parser())))
+        .map(|path| Decl::Use(path))
+        .boxed();
+
+    def
+        .or(func)
+        .or(type_alias)
+        .or(data)
+        .or(class)
+        .or(instance)
+        .or(module)
+        .or(import)
+        .map_with_span(|decl, span| SrcNode::new(decl, span))
+}
 
-        let func = just(Token::Fn)
-            // Name
-            .padding_for(ident_parser().map_with_span(|ident, span| SrcNode::new(ident, span)))
-            // Generic parameters
-            .then(generics.clone())
-            // Optional type annotation
-            .then(just(Token::Of)
-                .padding_for(type_parser())
-                .or_not())
-            // Parameters
-            .then(just(Token::Pipe)
-                .padding_for(binding.separated_by(just(Token::Comma)))
-                .padded_by(just(Token::Pipe))
-                .then(expr_parser())
-                .reduce_right(|(param, param_ty), body| {
-                    let span = param
-                        .span()
-                        .union(param_ty
-                            .as_ref()
-                            .map(|t| t.span())
-                            .unwrap_or(Span::none()))
-                        .union(body.span());
-                    SrcNode::new(Expr::Func(param, param_ty, body), span)
-                }))
-            .map_with_span(|(((name, generics), ty), body), span| Decl::Def(Def {
-                generics,
-                ty: ty.unwrap_or_else(|| SrcNode::new(Type::Unknown, name.span())),
-                name,
-                body,
-            }))
-            .boxed();
+// Find the innermost token tree containing the span and replace its contents with an error token,
+// returning the span of the replaced tokens
+fn poison_tree(tokens: &mut [node::Node<Token>], span: Span) -> Option<Span> {
+    for token in tokens.iter_mut() {
+        let token_span = token.span();
+        if let Token::Tree(_, inner) = &mut **token {
+            if token_span.intersects(span) && token_span != span {
+                if let Some(poisoned) = poison_tree(inner, span) {
+                    return Some(poisoned);
+                } else if !matches!(inner.as_slice(), [t] if **t == Token::Error) {
+                    let inner_span = inner
+                        .iter()
+                        .fold(Span::none(), |a, t| a.union(t.span()));
+                    *inner = vec![SrcNode::new(Token::Error, inner_span)];
+                    return Some(inner_span);
+                }
+            }
+        }
+    }
+    None
+}
 
-        let type_alias = just(Token::Type)
-            // Name
-            .padding_for(type_name_parser().map_with_span(|ident, span| SrcNode::new(ident, span)))
-            // Generic parameters
-            .then(generics.clone())
-            .padded_by(just(Token::Op(Op::Eq)))
-            .then(type_parser())
-            .map_with_span(|((name, generics), ty), span| Decl::TypeAlias(TypeAlias {
-                generics,
-                name,
-                ty,
-            }))
-            .boxed();
+// Parse the tokens, replacing the contents of token trees that fail to parse with error nodes until
+// parsing succeeds or recovery is no longer possible
+fn parse_recovering<O>(
+    parser: Parser<impl Pattern<Error, Input=node::Node<Token>, Output=O>, Error>,
+    mut tokens: Vec<node::Node<Token>>,
+    errors: &mut Vec<Error>,
+) -> Option<O> {
+    let mut recovered = Vec::<Span>::new();
+    loop {
+        match parser.clone().padded_by(end()).parse(tokens.iter().cloned()) {
+            Ok(output) => break Some(output),
+            Err(errs) => {
+                let span = errs
+                    .iter()
+                    .fold(Span::none(), |a, e| a.union(e.primary_span()));
+                // Errors caused by earlier recovery are not worth reporting
+                if recovered.iter().any(|r| r.intersects(span)) {
+                    break None;
+                }
+                errors.extend(errs);
+                if let Some(poisoned) = poison_tree(&mut tokens, span) {
+                    recovered.push(poisoned);
+                } else {
+                    break None;
+                }
+            },
+        }
+    }
+}
 
-        let data = just(Token::Data)
-            // Name
-            .padding_for(type_name_parser().map_with_span(|ident, span| SrcNode::new(ident, span)))
-            // Generic parameters
-            .then(generics)
-            .padded_by(just(Token::Op(Op::Eq)))
-            .then(data_type_parser())
-            .map_with_span(|((name, generics), data_ty), span| Decl::Data(Data {
-                generics,
-                name,
-                data_ty,
-            }))
-            .boxed();
+pub fn parse_module(tokens: &[node::Node<Token>]) -> (SrcNode<Module>, Vec<Error>) {
+    let mut errors = Vec::new();
+
+    // Split the module into declarations, so that errors in one declaration do not affect others
+    let is_boundary = |token: &node::Node<Token>| match &**token {
+        Token::Def | Token::Fn | Token::Type | Token::Data | Token::Class | Token::Instance | Token::Mod | Token::Use => true,
+        _ => false,
+    };
+    let mut chunks = Vec::<&[node::Node<Token>]>::new();
+    let mut start = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        if is_boundary(token) && i > start {
+            chunks.push(&tokens[start..i]);
+            start = i;
+        }
+    }
+    if start < tokens.len() {
+        chunks.push(&tokens[start..]);
+    }
 
-        let decl = def
-            .or(func)
-            .or(type_alias)
-            .or(data)
-            .map_with_span(|decl, span| SrcNode::new(decl, span));
+    let decls = chunks
+        .into_iter()
+        .map(|chunk| {
+            let span = chunk
+                .iter()
+                .fold(Span::none(), |a, t| a.union(t.span()));
+            parse_recovering(decl_parser(), chunk.to_vec(), &mut errors)
+                .unwrap_or_else(|| {
+                    let name = chunk
+                        .get(1)
+                        .filter(|_| matches!(&*chunk[0], Token::Def | Token::Fn | Token::Type | Token::Data))
+                        .and_then(|token| match &**token {
+                            Token::Ident(name) | Token::TypeName(name) => Some(SrcNode::new(*name, token.span())),
+                            _ => None,
+                        });
+                    let is_type = match &*chunk[0] {
+                        Token::Type | Token::Data => true,
+                        _ => false,
+                    };
+                    SrcNode::new(Decl::Error { name, is_type }, span)
+                })
+        })
+        .collect();
 
-        decl
-            .repeated()
-            .map_with_span(|decls, span| SrcNode::new(Module { decls }, span))
-    })
+    let span = tokens
+        .iter()
+        .fold(Span::none(), |a, t| a.union(t.span()));
+
+    (SrcNode::new(Module { decls }, span), errors)
 }
 
-pub fn parse_module(tokens: &[node::Node<Token>]) -> Result<SrcNode<Module>, Vec<Error>> {
-    module_parser()
-        .padded_by(end())
-        .parse(tokens.iter().cloned())
+#[cfg(test)]
+fn parse_test(src: &str) -> (Vec<String>, Vec<Error>) {
+    let (module, errors) = parse_module(&crate::lex::lex(src, crate::src::FileId::default()).unwrap());
+    let decls = module.decls
+        .iter()
+        .map(|decl| match &**decl {
+            Decl::Def(def) => format!("def {}", **def.name),
+            Decl::TypeAlias(alias) => format!("type {}", **alias.name),
+            Decl::Data(data) => format!("data {}", **data.name),
+            Decl::Error { name: Some(name), .. } => format!("error {}", **name),
+            _ => format!("other"),
+        })
+        .collect();
+    (decls, errors)
 }
 
+#[test]
+fn recover_at_declarations() {
+    let (decls, errors) = parse_test("
+        def a = 1 + -> 2
+        fn b |x| x
+        data C = |
+        type E = ->
+        def f = 2
+    ");
+    assert_eq!(errors.len(), 3);
+    assert_eq!(decls, vec!["error a", "def b", "error C", "error E", "def f"]);
+}
 
+#[test]
+fn recover_in_trees() {
+    // Each broken tree is replaced by an error node, so the declaration itself survives
+    let (decls, errors) = parse_test("
+        def a = (1 + *) * [2, =, 3]
+        def b = 4
+    ");
+    assert_eq!(errors.len(), 2);
+    assert_eq!(decls, vec!["def a", "def b"]);
+}
 
+#[test]
+fn expected_message() {
+    let (_, errors) = parse_test("def a = =");
+    assert_eq!(errors.len(), 1);
+    let msg = errors[0].msg();
+    assert!(msg.starts_with("Expected "), "{}", msg);
+    assert!(msg.contains(", ") && msg.contains(" or "), "{}", msg);
+    assert!(msg.ends_with(", found '='"), "{}", msg);
+}
@@ -0,0 +1,11 @@
+use std::io;
+use tao::lsp::Server;
+
+// Editors start the server and communicate with it over stdin and stdout
+fn main() {
+    let (stdin, stdout) = (io::stdin(), io::stdout());
+    if let Err(err) = Server::default().serve(stdin.lock(), stdout.lock()) {
+        eprintln!("tao-lsp: {}", err);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,659 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::Path,
+    rc::Rc,
+};
+use internment::LocalIntern;
+use crate::{
+    ast,
+    error::Error,
+    hir::{self, data::DataCtx},
+    lex,
+    mir,
+    module::ModuleTree,
+    node::SrcNode,
+    src::{SourceMap, FileId, Span},
+    ty::{Type, Primitive},
+    vm::{self, Value, Vm, IoHandler, StdIo, Natives, NativeFn, Limits},
+};
+
+type Ident = LocalIntern<String>;
+
+// A description of a Tao type, as seen from Rust
+#[derive(Clone, Debug, PartialEq)]
+pub enum TypeDesc {
+    Num,
+    Bool,
+    Char,
+    Universe,
+    List(Box<TypeDesc>),
+    Tuple(Vec<TypeDesc>),
+    Record(Vec<(String, TypeDesc)>),
+    Func(Box<TypeDesc>, Box<TypeDesc>),
+    // A data type, by its (qualified) name, along with its type parameters
+    Data(String, Vec<TypeDesc>),
+    // A generic parameter that has not been instantiated
+    Param(String),
+}
+
+impl TypeDesc {
+    pub fn data(name: &str, params: Vec<TypeDesc>) -> Self {
+        TypeDesc::Data(name.to_string(), params)
+    }
+}
+
+impl fmt::Display for TypeDesc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |items: Vec<String>| items.join(", ");
+        match self {
+            TypeDesc::Num => write!(f, "Num"),
+            TypeDesc::Bool => write!(f, "Bool"),
+            TypeDesc::Char => write!(f, "Char"),
+            TypeDesc::Universe => write!(f, "Universe"),
+            TypeDesc::List(item) => write!(f, "[{}]", item),
+            TypeDesc::Tuple(items) if items.len() == 1 => write!(f, "({},)", items[0]),
+            TypeDesc::Tuple(items) => write!(f, "({})", join(items.iter().map(|item| format!("{}", item)).collect())),
+            TypeDesc::Record(fields) if fields.len() == 0 => write!(f, "{{}}"),
+            TypeDesc::Record(fields) => write!(f, "{{ {} }}", join(fields
+                .iter()
+                .map(|(name, field)| format!("{}: {}", name, field))
+                .collect())),
+            TypeDesc::Func(i, o) => write!(f, "({} -> {})", i, o),
+            TypeDesc::Data(name, params) => {
+                write!(f, "{}", name)?;
+                params
+                    .iter()
+                    .try_for_each(|param| write!(f, " {}", param))
+            },
+            TypeDesc::Param(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+// A Rust type that corresponds to a Tao type
+pub trait Typed {
+    fn ty() -> TypeDesc;
+}
+
+// A Rust type that may be given to Tao code
+pub trait IntoValue: Typed {
+    fn into_value(self) -> Value;
+}
+
+// A Rust type that may be produced by Tao code. `None` is produced if the value does not have the expected shape.
+pub trait FromValue: Typed + Sized {
+    fn from_value(val: Value) -> Option<Self>;
+}
+
+impl Typed for f64 { fn ty() -> TypeDesc { TypeDesc::Num } }
+impl Typed for bool { fn ty() -> TypeDesc { TypeDesc::Bool } }
+impl Typed for char { fn ty() -> TypeDesc { TypeDesc::Char } }
+// `Str` is an alias of `[Char]`
+impl Typed for String { fn ty() -> TypeDesc { TypeDesc::List(Box::new(TypeDesc::Char)) } }
+impl<T: Typed> Typed for Vec<T> { fn ty() -> TypeDesc { TypeDesc::List(Box::new(T::ty())) } }
+
+impl IntoValue for f64 { fn into_value(self) -> Value { Value::Number(self) } }
+impl IntoValue for bool { fn into_value(self) -> Value { Value::Boolean(self) } }
+impl IntoValue for char { fn into_value(self) -> Value { Value::Char(self) } }
+impl IntoValue for String {
+    fn into_value(self) -> Value { Value::make_list(self.chars().map(Value::Char)) }
+}
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value { Value::make_list(self.into_iter().map(T::into_value)) }
+}
+
+impl FromValue for f64 { fn from_value(val: Value) -> Option<Self> { val.into_number() } }
+impl FromValue for bool { fn from_value(val: Value) -> Option<Self> { val.into_boolean() } }
+impl FromValue for char { fn from_value(val: Value) -> Option<Self> { val.into_char() } }
+impl FromValue for String {
+    fn from_value(val: Value) -> Option<Self> {
+        val.into_list()?.iter().map(|c| c.clone().into_char()).collect()
+    }
+}
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(val: Value) -> Option<Self> {
+        val.into_list()?.iter().map(|x| T::from_value(x.clone())).collect()
+    }
+}
+
+// Tuples are lists of their fields
+macro_rules! impl_tuple {
+    ($($T:ident),*) => {
+        impl<$($T: Typed),*> Typed for ($($T,)*) {
+            fn ty() -> TypeDesc { TypeDesc::Tuple(vec![$($T::ty()),*]) }
+        }
+
+        impl<$($T: IntoValue),*> IntoValue for ($($T,)*) {
+            #[allow(non_snake_case)]
+            fn into_value(self) -> Value {
+                let ($($T,)*) = self;
+                Value::make_list(vec![$($T.into_value()),*].into_iter())
+            }
+        }
+
+        impl<$($T: FromValue),*> FromValue for ($($T,)*) {
+            #[allow(unused_mut, unused_variables)]
+            fn from_value(val: Value) -> Option<Self> {
+                let items = val.into_list()?;
+                let mut items = items.iter().cloned();
+                let tuple = ($($T::from_value(items.next()?)?,)*);
+                match items.next() {
+                    None => Some(tuple),
+                    Some(_) => None,
+                }
+            }
+        }
+
+        impl<$($T: IntoValue),*> Args for ($($T,)*) {
+            #[allow(non_snake_case)]
+            fn into_args(self) -> Vec<(TypeDesc, Value)> {
+                let ($($T,)*) = self;
+                vec![$(($T::ty(), $T.into_value())),*]
+            }
+        }
+    };
+}
+
+// The arguments of a call, given as a tuple. Tao functions are curried, so each is applied in turn.
+pub trait Args {
+    fn into_args(self) -> Vec<(TypeDesc, Value)>;
+}
+
+// A Rust function that may be registered as a native function. `A` is the tuple of the function's arguments.
+pub trait IntoNative<A> {
+    fn into_native(self) -> (Vec<TypeDesc>, TypeDesc, NativeFn);
+}
+
+macro_rules! impl_native {
+    ($($T:ident),*) => {
+        impl<F, R, $($T),*> IntoNative<($($T,)*)> for F
+        where
+            F: Fn($($T),*) -> Result<R, String> + 'static,
+            R: IntoValue,
+            $($T: FromValue),*
+        {
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn into_native(self) -> (Vec<TypeDesc>, TypeDesc, NativeFn) {
+                (vec![$($T::ty()),*], R::ty(), Rc::new(move |args: Vec<Value>| {
+                    let mut args = args.into_iter();
+                    $(
+                        let $T = args
+                            .next()
+                            .and_then($T::from_value)
+                            .ok_or_else(|| format!("Expected an argument of type {}", $T::ty()))?;
+                    )*
+                    self($($T),*).map(R::into_value)
+                }))
+            }
+        }
+    };
+}
+
+impl_tuple!();
+impl_tuple!(A);
+impl_tuple!(A, B);
+impl_tuple!(A, B, C);
+impl_tuple!(A, B, C, D);
+
+impl_native!();
+impl_native!(A);
+impl_native!(A, B);
+impl_native!(A, B, C);
+impl_native!(A, B, C, D);
+
+// A type-checked module whose definitions may be looked up and called from Rust
+pub struct Module {
+    pub(crate) hir: hir::Program,
+    natives: Natives,
+    warnings: Vec<Error>,
+}
+
+impl Module {
+    // Compile the module in `file`, loading any submodules it declares from `dir`
+    pub fn compile(srcs: &mut SourceMap, file: FileId, dir: Option<&Path>) -> Result<Self, Vec<Error>> {
+        Self::compile_with_natives(srcs, file, dir, Natives::default())
+    }
+
+    // Compile the module in `file`, allowing it to call the given native functions
+    pub fn compile_with_natives(
+        srcs: &mut SourceMap,
+        file: FileId,
+        dir: Option<&Path>,
+        natives: Natives,
+    ) -> Result<Self, Vec<Error>> {
+        let tokens = lex::lex(srcs.code(file), file)?;
+        let (root, mut errors) = ast::parse_module(&tokens);
+        let prelude = crate::parse_prelude(srcs)?;
+        let (_, ast, mut module_errors) = ModuleTree::load(srcs, root, dir, prelude);
+        errors.append(&mut module_errors);
+        // Type-check what could be parsed, even if parsing failed, to report as many errors as possible
+        match hir::Program::new_root_with_natives(&ast, &natives) {
+            Ok(hir) if errors.len() == 0 => Ok(Self { warnings: hir.warnings(), hir, natives }),
+            Ok(_) => Err(errors),
+            Err(mut hir_errors) => {
+                errors.append(&mut hir_errors);
+                Err(errors)
+            },
+        }
+    }
+
+    // Compile a module containing only the prelude, along with a `main` definition that evaluates the expression in
+    // `file`
+    pub fn compile_expr(srcs: &mut SourceMap, file: FileId) -> Result<Self, Vec<Error>> {
+        let tokens = lex::lex(srcs.code(file), file)?;
+        let prelude = crate::parse_prelude(srcs)?;
+        let (modules, ast, errors) = ModuleTree::load(srcs, SrcNode::new(ast::Module::default(), Span::none()), None, prelude);
+        if errors.len() > 0 {
+            return Err(errors);
+        }
+        let mut hir = hir::Program::new_root(&ast)?;
+        let mut main = ast::Def::main(ast::parse_expr(&tokens)?);
+        modules.resolve_def(&mut main)?;
+        hir
+            .insert_def(&main)
+            .map_err(|e| vec![e])?;
+        Ok(Self::from_hir(hir))
+    }
+
+    pub(crate) fn from_hir(hir: hir::Program) -> Self {
+        Self { warnings: hir.warnings(), hir, natives: Natives::default() }
+    }
+
+    // Problems found in the module that did not prevent it from compiling
+    pub fn warnings(&self) -> &[Error] {
+        &self.warnings
+    }
+
+    // The definitions declared in the module and its submodules, along with their types, sorted by name
+    pub fn defs(&self) -> Vec<(String, TypeDesc)> {
+        let mut defs = self.hir
+            .root()
+            .defs()
+            // Hide the prelude and the hidden definitions of instance members
+            .filter(|def| !def.name.starts_with("prelude::") && !def.name.contains('#'))
+            .map(|def| (def.name.as_str().to_string(), describe(&self.hir.data_ctx, def.body.ty())))
+            .collect::<Vec<_>>();
+        defs.sort_by(|(a, _), (b, _)| a.cmp(b));
+        defs
+    }
+
+    // Find a monomorphic definition
+    pub fn def(&self, name: &str) -> Result<Def, Vec<Error>> {
+        self.def_instance(name, &[])
+    }
+
+    // Find a definition, instantiating its generic parameters with the given types
+    pub fn def_instance(&self, name: &str, params: &[TypeDesc]) -> Result<Def, Vec<Error>> {
+        let ident = Ident::new(name.to_string());
+        let def = self.hir
+            .root()
+            .def(ident)
+            .ok_or_else(|| vec![Error::custom(format!("No definition named '{}'", name))])?;
+
+        if params.len() != def.generics.len() {
+            return Err(vec![Error::custom(format!(
+                "Definition '{}' has {} type parameter(s) but {} were given",
+                name,
+                def.generics.len(),
+                params.len(),
+            ))
+                .with_span(def.name.span())]);
+        }
+
+        let params = params
+            .iter()
+            .map(|param| desc_to_type(&self.hir.data_ctx, param))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| vec![e])?;
+        let generics = def.generics
+            .iter()
+            .map(|gen| **gen)
+            .zip(params.iter().cloned())
+            .collect::<HashMap<_, _>>();
+        let ty = mir::substitute(def.body.ty().inner(), &generics, &|param| Err(vec![
+            Error::custom(format!("Type parameter '{}' of definition '{}' was not given", *param, name))
+                .with_span(def.name.span()),
+        ]))?;
+        let ty = describe(&self.hir.data_ctx, &ty);
+
+        let mir = mir::Program::from_hir(&self.hir, ident, params)?;
+        let prog = mir.compile(true)?;
+
+        Ok(Def {
+            name: name.to_string(),
+            ty,
+            mir,
+            prog,
+            natives: self.natives.clone(),
+            limits: Limits::default(),
+        })
+    }
+
+    // The constructors of the data type `ty`, in order, along with the types of the values that they wrap
+    fn variants(&self, ty: &TypeDesc) -> Result<Vec<(String, TypeDesc)>, Vec<Error>> {
+        let (id, params) = match desc_to_type(&self.hir.data_ctx, ty).map_err(|e| vec![e])? {
+            Type::Data(id, params) => (*id, params),
+            _ => return Err(vec![Error::custom(format!("{} is not a data type", ty))]),
+        };
+        let data = self.hir.data_ctx.get_data(id);
+        let generics = data.generics
+            .iter()
+            .map(|gen| **gen)
+            .zip(params.iter().map(|param| param.inner().clone()))
+            .collect::<HashMap<_, _>>();
+        data.variants
+            .iter()
+            .map(|(name, inner)| {
+                let inner = mir::substitute(inner, &generics, &|param| Err(vec![
+                    Error::custom(format!("Type parameter '{}' of data type {} was not given", *param, ty)),
+                ]))?;
+                Ok((name.as_str().to_string(), describe(&self.hir.data_ctx, &inner)))
+            })
+            .collect()
+    }
+
+    // Make a value of the data type `ty` with the named constructor, checking that it wraps a value of the right type
+    pub fn make_variant<T: IntoValue>(&self, ty: &TypeDesc, constructor: &str, inner: T) -> Result<Value, Vec<Error>> {
+        let variants = self.variants(ty)?;
+        let (idx, (_, inner_ty)) = variants
+            .iter()
+            .enumerate()
+            .find(|(_, (name, _))| name == constructor)
+            .ok_or_else(|| vec![Error::custom(format!("Data type {} has no constructor named '{}'", ty, constructor))])?;
+        if *inner_ty != T::ty() {
+            return Err(vec![Error::custom(format!(
+                "Constructor '{}' wraps a value of type {}, but was given {}",
+                constructor,
+                inner_ty,
+                T::ty(),
+            ))]);
+        }
+        // Data types with a single variant are represented by the inner value alone
+        Ok(if variants.len() == 1 {
+            inner.into_value()
+        } else {
+            Value::make_variant(idx, inner.into_value())
+        })
+    }
+
+    // Split a value of the data type `ty` into the name of its constructor and the value that it wraps
+    pub fn take_variant<T: FromValue>(&self, ty: &TypeDesc, val: Value) -> Result<(String, T), Vec<Error>> {
+        let mut variants = self.variants(ty)?;
+        let (idx, inner) = if variants.len() == 1 {
+            (0, val)
+        } else {
+            val
+                .into_variant()
+                .filter(|(idx, _)| *idx < variants.len())
+                .ok_or_else(|| vec![Error::custom(format!("Value is not of type {}", ty))])?
+        };
+        let (constructor, inner_ty) = variants.swap_remove(idx);
+        if inner_ty != T::ty() {
+            return Err(vec![Error::custom(format!(
+                "Constructor '{}' wraps a value of type {}, but {} was expected",
+                constructor,
+                inner_ty,
+                T::ty(),
+            ))]);
+        }
+        let inner = T::from_value(inner)
+            .ok_or_else(|| vec![Error::custom(format!("Value wrapped by '{}' could not be converted to {}", constructor, T::ty()))])?;
+        Ok((constructor, inner))
+    }
+}
+
+// Describe the Tao type `ty`
+pub(crate) fn describe(data_ctx: &DataCtx, ty: &Type) -> TypeDesc {
+    let node = |ty: &SrcNode<Type>| describe(data_ctx, ty);
+    match ty {
+        Type::Primitive(Primitive::Number) => TypeDesc::Num,
+        Type::Primitive(Primitive::Boolean) => TypeDesc::Bool,
+        Type::Primitive(Primitive::Char) => TypeDesc::Char,
+        Type::Primitive(Primitive::Universe) => TypeDesc::Universe,
+        Type::List(item) => TypeDesc::List(Box::new(node(item))),
+        Type::Tuple(items) => TypeDesc::Tuple(items.iter().map(node).collect()),
+        Type::Record(fields) => TypeDesc::Record(fields
+            .iter()
+            .map(|(name, field)| (name.as_str().to_string(), node(field)))
+            .collect()),
+        Type::Func(i, o) => TypeDesc::Func(Box::new(node(i)), Box::new(node(o))),
+        Type::GenParam(name) => TypeDesc::Param(name.as_str().to_string()),
+        Type::Data(id, params) => TypeDesc::Data(
+            data_ctx.get_data_name(**id).as_str().to_string(),
+            params.iter().map(node).collect(),
+        ),
+    }
+}
+
+// Find the Tao type described by `ty`
+pub(crate) fn desc_to_type(data_ctx: &DataCtx, ty: &TypeDesc) -> Result<Type, Error> {
+    let node = |ty: &TypeDesc| -> Result<SrcNode<Type>, Error> { Ok(SrcNode::new(desc_to_type(data_ctx, ty)?, Span::none())) };
+    Ok(match ty {
+        TypeDesc::Num => Type::Primitive(Primitive::Number),
+        TypeDesc::Bool => Type::Primitive(Primitive::Boolean),
+        TypeDesc::Char => Type::Primitive(Primitive::Char),
+        TypeDesc::Universe => Type::Primitive(Primitive::Universe),
+        TypeDesc::List(item) => Type::List(node(&**item)?),
+        TypeDesc::Tuple(items) => Type::Tuple(items.iter().map(node).collect::<Result<_, _>>()?),
+        TypeDesc::Record(fields) => Type::Record(fields
+            .iter()
+            .map(|(name, field)| Ok((SrcNode::new(Ident::new(name.clone()), Span::none()), node(field)?)))
+            .collect::<Result<_, _>>()?),
+        TypeDesc::Func(i, o) => Type::Func(node(&**i)?, node(&**o)?),
+        TypeDesc::Data(name, params) => {
+            let id = data_ctx
+                .get_data_by_name(Ident::new(name.clone()))
+                .ok_or_else(|| Error::custom(format!("No data type named '{}'", name)))?;
+            let generics = data_ctx.get_data(id).generics.len();
+            if generics != params.len() {
+                return Err(Error::custom(format!(
+                    "Data type '{}' has {} type parameter(s) but {} were given",
+                    name,
+                    generics,
+                    params.len(),
+                )));
+            }
+            Type::Data(SrcNode::new(id, Span::none()), params.iter().map(node).collect::<Result<_, _>>()?)
+        },
+        TypeDesc::Param(name) => return Err(Error::custom(format!("Type parameter '{}' must be instantiated with a concrete type", name))),
+    })
+}
+
+// A definition that has been compiled and may be called
+pub struct Def {
+    name: String,
+    ty: TypeDesc,
+    mir: mir::Program,
+    // The definition compiled as a pure value
+    prog: vm::Program,
+    natives: Natives,
+    limits: Limits,
+}
+
+impl Def {
+    pub fn ty(&self) -> &TypeDesc {
+        &self.ty
+    }
+
+    // Limit the resources that calls to the definition may use
+    pub fn with_limits(self, limits: Limits) -> Self {
+        Self {
+            limits,
+            ..self
+        }
+    }
+
+    fn vm(&self) -> Vm {
+        Vm::with_natives(self.natives.clone()).with_limits(self.limits)
+    }
+
+    // Evaluate the definition, without checking its type
+    pub fn eval(&self) -> Result<Value, Vec<Error>> {
+        self.vm()
+            .execute(&self.prog, &mut StdIo)
+            .map_err(|err| vec![err.into_error(&self.prog)])
+            // Pure programs always produce a value
+            .map(|val| val.unwrap())
+    }
+
+    // Evaluate the definition, converting its value
+    pub fn get<R: FromValue>(&self) -> Result<R, Vec<Error>> {
+        self.call(())
+    }
+
+    // Call the definition with the given arguments, performing any IO with the process' stdin and stdout
+    pub fn call<A: Args, R: FromValue>(&self, args: A) -> Result<R, Vec<Error>> {
+        self.call_with_io(args, &mut StdIo)
+    }
+
+    pub fn call_with_io<A: Args, R: FromValue>(&self, args: A, io: &mut dyn IoHandler) -> Result<R, Vec<Error>> {
+        let args = args.into_args();
+
+        // Check the arguments and return type against the definition's type before running anything
+        let mut ty = &self.ty;
+        for (idx, (arg_ty, _)) in args.iter().enumerate() {
+            match ty {
+                TypeDesc::Func(i, o) if **i == *arg_ty => ty = &**o,
+                TypeDesc::Func(i, _) => return Err(vec![Error::custom(format!(
+                    "Argument {} of '{}' must be of type {}, but was given {}",
+                    idx + 1,
+                    self.name,
+                    i,
+                    arg_ty,
+                ))]),
+                _ => return Err(vec![Error::custom(format!(
+                    "'{}' is of type {} and cannot be called with {} argument(s)",
+                    self.name,
+                    self.ty,
+                    args.len(),
+                ))]),
+            }
+        }
+        if *ty != R::ty() {
+            return Err(vec![Error::custom(format!(
+                "'{}' produces a value of type {}, but {} was expected",
+                self.name,
+                ty,
+                R::ty(),
+            ))]);
+        }
+
+        let mut vm = self.vm();
+        let runtime_err = |err: vm::RuntimeError| vec![err.into_error(&self.prog)];
+        let mut val = vm
+            .execute(&self.prog, io)
+            .map_err(runtime_err)?
+            .unwrap();
+        for (_, arg) in args {
+            val = vm
+                .call(&self.prog, val, arg, io)
+                .map_err(runtime_err)?;
+        }
+
+        R::from_value(val)
+            .ok_or_else(|| vec![Error::custom(format!("Value produced by '{}' could not be converted to {}", self.name, R::ty()))])
+    }
+
+    // Run the definition as the entry point of a program. If it is of type `Universe -> ((), Universe)` then it is
+    // applied to the universe, performing IO with `io`. Otherwise, its value is produced.
+    pub fn run(&self, io: &mut dyn IoHandler) -> Result<Option<Value>, Vec<Error>> {
+        let prog = self.mir.compile(false)?;
+        self.vm()
+            .execute(&prog, io)
+            .map_err(|err| vec![err.into_error(&prog)])
+    }
+}
+
+// Compile `code` as `main.tao`, keeping the source map so that errors can be displayed
+#[cfg(test)]
+pub(crate) fn compile_main(code: &str) -> (SourceMap, Result<Module, Vec<Error>>) {
+    let mut srcs = SourceMap::default();
+    let file = srcs.add("main.tao", code);
+    let module = Module::compile(&mut srcs, file, None);
+    (srcs, module)
+}
+
+#[test]
+fn call_defs() {
+    let src = "
+        data Shape =
+            | Circle Num
+            | Square Num
+        def area of Shape -> Num = |s| match s {
+            | Circle r => r * r * 3
+            | Square w => w * w
+        }
+        def grow of Shape -> Shape = |s| match s {
+            | Circle r => Square (r * 2)
+            | Square w => Circle (w / 2)
+        }
+        fn add_all |n of Num, xs| map(|x| x + n, xs)
+        def names = [\"foo\", \"bar\"]
+    ";
+    let module = compile_main(src).1.unwrap();
+
+    // Values of data types are built and taken apart by constructor name
+    struct Shape(Value);
+    impl Typed for Shape { fn ty() -> TypeDesc { TypeDesc::data("Shape", Vec::new()) } }
+    impl IntoValue for Shape { fn into_value(self) -> Value { self.0 } }
+    impl FromValue for Shape { fn from_value(val: Value) -> Option<Self> { Some(Shape(val)) } }
+    let square = |w: f64| Shape(module.make_variant(&Shape::ty(), "Square", w).unwrap());
+
+    let area = module.def("area").unwrap();
+    assert_eq!(area.call::<_, f64>((square(4.0),)).unwrap(), 16.0);
+    // Types are checked before calling
+    assert!(area.call::<_, f64>((4.0,)).is_err());
+    assert!(area.call::<_, bool>((square(4.0),)).is_err());
+
+    let grow = module.def("grow").unwrap();
+    let Shape(circle) = grow.call((square(4.0),)).unwrap();
+    assert_eq!(module.take_variant::<f64>(&Shape::ty(), circle.clone()).unwrap(), ("Circle".to_string(), 2.0));
+    let Shape(grown) = grow.call((Shape(circle),)).unwrap();
+    assert_eq!(module.take_variant::<f64>(&Shape::ty(), grown).unwrap(), ("Square".to_string(), 4.0));
+
+    // Constructors and the types of the values they wrap are checked
+    assert!(module.make_variant(&Shape::ty(), "Triangle", 1.0).is_err());
+    assert!(module.make_variant(&Shape::ty(), "Square", true).is_err());
+    assert!(module.make_variant(&TypeDesc::Num, "Square", 1.0).is_err());
+    let maybe = TypeDesc::data("prelude::Maybe", vec![TypeDesc::Bool]);
+    let just = module.make_variant(&maybe, "prelude::Just", true).unwrap();
+    assert!(module.take_variant::<f64>(&maybe, just.clone()).is_err());
+    assert_eq!(module.take_variant::<bool>(&maybe, just).unwrap(), ("prelude::Just".to_string(), true));
+
+    let add_all = module.def("add_all").unwrap();
+    assert_eq!(add_all.call::<_, Vec<f64>>((1.0, vec![1.0, 2.0])).unwrap(), vec![2.0, 3.0]);
+
+    assert_eq!(module.def("names").unwrap().get::<Vec<String>>().unwrap(), vec!["foo".to_string(), "bar".to_string()]);
+
+    // Generic definitions must be explicitly instantiated
+    assert!(module.def("prelude::len").is_err());
+    assert!(module.def_instance("prelude::len", &[TypeDesc::Bool, TypeDesc::Num]).is_err());
+    let len = module.def_instance("prelude::len", &[TypeDesc::Bool]).unwrap();
+    assert_eq!(len.call::<_, f64>((vec![true, false],)).unwrap(), 2.0);
+}
+
+#[test]
+fn natives() {
+    let src = "
+        def greeting = @greet(\"world\")
+        def checksum of Str -> Num = |s| @sum_chars(s) + 1
+        def oops = @fail(())
+    ";
+    let mut natives = Natives::default();
+    natives
+        .register_fn("greet", |name: String| -> Result<String, String> { Ok(format!("Hello, {}!", name)) })
+        .register_fn("sum_chars", |s: String| -> Result<f64, String> { Ok(s.chars().map(|c| c as u32 as f64).sum()) })
+        .register_fn("fail", |(): ()| -> Result<bool, String> { Err(format!("Oops")) });
+    let mut srcs = SourceMap::default();
+    let file = srcs.add("main.tao", src);
+    let module = Module::compile_with_natives(&mut srcs, file, None, natives.clone()).unwrap();
+
+    assert_eq!(module.def("greeting").unwrap().get::<String>().unwrap(), "Hello, world!");
+    assert_eq!(module.def("checksum").unwrap().call::<_, f64>(("ab".to_string(),)).unwrap(), 196.0);
+    assert!(module.def("oops").unwrap().get::<bool>().is_err());
+
+    // Calls to natives are type-checked
+    let file = srcs.add("bad.tao", "def bad = @sum_chars(5)");
+    assert!(Module::compile_with_natives(&mut srcs, file, None, natives).is_err());
+}
@@ -1,6 +1,6 @@
 use std::fmt;
 use crate::{
-    src::{Loc, Span},
+    src::{Loc, Span, FileId, SourceMap},
     lex::Token,
     node::SrcNode,
 };
@@ -15,6 +15,18 @@ pub struct Error {
     primary_spans: Vec<Span>,
     secondary_spans: Vec<Span>,
     hints: Vec<String>,
+    poisoned: bool,
+    // Things that the parser expected to find instead of `found`
+    expected: Vec<String>,
+    found: Option<String>,
+    // The call stack at the point of a runtime error, innermost first
+    frames: Vec<(String, Span)>,
+    // A stable identifier for the kind of error, such as `E0200`
+    code: Option<&'static str>,
+    // Edits that would fix the error: the span to replace, the replacement and a description
+    suggestions: Vec<(Span, String, String)>,
+    // Warnings are reported, but do not stop compilation
+    severity: Severity,
 }
 
 impl Error {
@@ -25,26 +37,103 @@ impl Error {
             primary_spans: Vec::new(),
             secondary_spans: Vec::new(),
             hints: Vec::new(),
+            poisoned: false,
+            expected: Vec::new(),
+            found: None,
+            frames: Vec::new(),
+            code: None,
+            suggestions: Vec::new(),
+            severity: Severity::Error,
         }
     }
 
-    pub fn in_source<'a>(&'a self, src: &'a str) -> ErrorInSrc<'a> {
+    pub fn warning(msg: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::custom(msg)
+        }
+    }
+
+    fn unexpected(msg: String, found: Option<String>) -> Self {
+        Self {
+            found,
+            ..Self::custom(msg).with_code("E0001")
+        }
+    }
+
+    /// An error caused by an earlier error that has already been reported. Poisoned errors are
+    /// used to abandon compilation of items that depend on broken items without emitting
+    /// duplicate diagnostics.
+    pub fn poisoned() -> Self {
+        Self {
+            poisoned: true,
+            ..Self::custom(format!("Item depends on an item that failed to compile"))
+        }
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+
+    pub fn in_source<'a>(&'a self, srcs: &'a SourceMap) -> ErrorInSrc<'a> {
         ErrorInSrc {
             error: self,
-            src,
+            srcs,
         }
     }
 
+    // Attach the spans of the error to the given file
+    pub fn in_file(mut self, file: FileId) -> Self {
+        self.primary_spans.iter_mut().for_each(|span| *span = span.in_file(file));
+        self.secondary_spans.iter_mut().for_each(|span| *span = span.in_file(file));
+        self
+    }
+
     pub fn at(mut self, span: Span) -> Self {
         // TODO: More span information
         self
     }
 
-    pub fn merge(mut self, mut other: Self) -> Self {
-        // TODO: Merge errors
+    pub fn merge(self, other: Self) -> Self {
+        // Prefer the error that occurred furthest into the input
+        if other.primary_span().later_than(self.primary_span()) {
+            other
+        } else if self.primary_span().later_than(other.primary_span()) {
+            self
+        } else {
+            other.expected
+                .into_iter()
+                .fold(self, |this, thing| this.expected(thing))
+        }
+    }
+
+    pub fn expected(mut self, thing: String) -> Self {
+        if !self.expected.contains(&thing) {
+            self.expected.push(thing);
+        }
         self
     }
 
+    // The primary spans of the error joined together. Spans in a different file to the first are left out.
+    pub fn primary_span(&self) -> Span {
+        self.primary_spans
+            .iter()
+            .fold(Span::none(), |a, s| a.try_union(*s).unwrap_or(a))
+    }
+
+    pub fn msg(&self) -> String {
+        if let Some((last, init)) = self.expected.split_last() {
+            let expected = if init.len() == 0 {
+                last.clone()
+            } else {
+                format!("{} or {}", init.join(", "), last)
+            };
+            format!("Expected {}, found {}", expected, self.found.as_deref().unwrap_or("end of input"))
+        } else {
+            self.msg.clone()
+        }
+    }
+
     pub fn with_span(mut self, span: Span) -> Self {
         self.primary_spans.push(span);
         self
@@ -59,6 +148,91 @@ impl Error {
         self.hints.push(hint);
         self
     }
+
+    pub fn with_frame(mut self, name: String, span: Span) -> Self {
+        self.frames.push((name, span));
+        self
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_suggestion(mut self, span: Span, replacement: String, msg: String) -> Self {
+        self.suggestions.push((span, replacement, msg));
+        self
+    }
+
+    pub fn code(&self) -> Option<&'static str> {
+        self.code
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    // Resolve the spans of the error against the source map, producing a self-contained diagnostic
+    pub fn diagnostic(&self, srcs: &SourceMap) -> Diagnostic {
+        let resolve = |span: Span| span
+            .file()
+            .and_then(|file| Some((file, span.in_context(srcs.code(file))?)))
+            .map(|(file, ((start_line, start_col), (end_line, end_col)))| DiagnosticSpan {
+                file: srcs.name(file).to_string(),
+                start: Position { line: start_line + 1, column: start_col + 1 },
+                end: Position { line: end_line + 1, column: end_col + 1 },
+                primary: false,
+            });
+
+        Diagnostic {
+            severity: self.severity,
+            code: self.code.map(|code| code.to_string()),
+            message: self.msg(),
+            spans: self.primary_spans
+                .iter()
+                .filter_map(|span| resolve(*span).map(|span| DiagnosticSpan { primary: true, ..span }))
+                .chain(self.secondary_spans.iter().filter_map(|span| resolve(*span)))
+                .collect(),
+            hints: self.hints.clone(),
+            suggestions: self.suggestions
+                .iter()
+                .filter_map(|(span, replacement, msg)| Some(Suggestion {
+                    message: msg.clone(),
+                    span: resolve(*span)?,
+                    replacement: replacement.clone(),
+                }))
+                .collect(),
+            rendered: self.in_source(srcs).to_string(),
+        }
+    }
+}
+
+// Find the candidate most similar to `name`, if any is similar enough to plausibly be a misspelling of it
+pub(crate) fn closest<'a>(name: &str, candidates: impl IntoIterator<Item=&'a str>) -> Option<&'a str> {
+    fn distance(a: &str, b: &str) -> usize {
+        let b = b.chars().collect::<Vec<_>>();
+        let mut row = (0..=b.len()).collect::<Vec<_>>();
+        for (i, ca) in a.chars().enumerate() {
+            let mut prev = row[0];
+            row[0] = i + 1;
+            for j in 0..b.len() {
+                let next = (prev + if ca == b[j] { 0 } else { 1 })
+                    .min(row[j] + 1)
+                    .min(row[j + 1] + 1);
+                prev = row[j + 1];
+                row[j + 1] = next;
+            }
+        }
+        row[b.len()]
+    }
+
+    candidates
+        .into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (distance(name, candidate), candidate))
+        .filter(|(dist, _)| *dist <= (name.chars().count() / 3).max(1))
+        .min_by_key(|(dist, _)| *dist)
+        .map(|(_, candidate)| candidate)
 }
 
 impl parze::error::Error<char> for Error {
@@ -67,22 +241,22 @@ impl parze::error::Error<char> for Error {
     type Context = ();
 
     fn unexpected_sym(c: &char, span: Span) -> Self {
-        Self::custom(format!("Unexpected character '{}'", c))
+        Self::unexpected(format!("Unexpected character '{}'", c), Some(format!("'{}'", c)))
             .with_span(span)
     }
 
     fn unexpected_end() -> Self {
-        Self::custom(format!("Unexpected end of input"))
+        Self::unexpected(format!("Unexpected end of input"), None)
     }
 
     fn expected_end(c: &char, span: Span) -> Self {
-        Self::custom(format!("Expected end of input, found '{}'", c))
+        Self::unexpected(format!("Expected end of input, found '{}'", c), Some(format!("'{}'", c)))
             .with_span(span)
+            .expected(format!("end of input"))
     }
 
-    fn expected(mut self, thing: Self::Thing) -> Self {
-        // TODO: Merge error messages
-        self
+    fn expected(self, thing: Self::Thing) -> Self {
+        self.expected(thing.to_string())
     }
 
     fn merge(self, other: Self) -> Self {
@@ -96,22 +270,22 @@ impl parze::error::Error<SrcNode<Token>> for Error {
     type Context = ();
 
     fn unexpected_sym(sym: &SrcNode<Token>, span: Span) -> Self {
-        Self::custom(format!("Unexpected token '{}'", **sym))
+        Self::unexpected(format!("Unexpected token '{}'", **sym), Some(format!("'{}'", **sym)))
             .with_span(span)
     }
 
     fn unexpected_end() -> Self {
-        Self::custom(format!("Unexpected end of input"))
+        Self::unexpected(format!("Unexpected end of input"), None)
     }
 
     fn expected_end(sym: &SrcNode<Token>, span: Span) -> Self {
-        Self::custom(format!("Expected end of input, found '{}'", **sym))
+        Self::unexpected(format!("Expected end of input, found '{}'", **sym), Some(format!("'{}'", **sym)))
             .with_span(span)
+            .expected(format!("end of input"))
     }
 
-    fn expected(mut self, thing: Self::Thing) -> Self {
-        // TODO: Merge error messages
-        self
+    fn expected(self, thing: Self::Thing) -> Self {
+        self.expected(thing.to_string())
     }
 
     fn merge(self, other: Self) -> Self {
@@ -122,100 +296,204 @@ impl parze::error::Error<SrcNode<Token>> for Error {
 #[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct ErrorInSrc<'a> {
     error: &'a Error,
-    src: &'a str,
+    srcs: &'a SourceMap,
 }
 
-impl<'a> fmt::Display for ErrorInSrc<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let highlight_spans = |f: &mut fmt::Formatter, spans: &[_]| {
-            let span_iter = spans
-                .iter()
-                .cloned()
-                .chain(self.error.primary_spans.iter().map(|s| (*s, true)))
-                .chain(self.error.secondary_spans.iter().map(|s| (*s, false)));
-
-            if let Some(((start_line, start_col), (end_line, end_col))) = span_iter
-                .clone()
-                .fold(Span::none(), |a, (s, _)| a.union(s))
-                .in_context(self.src)
-            {
-                writeln!(f, "-> line {}, column {}", start_line + 1, start_col + 1)?;
-
-                let lines = self.src.lines().collect::<Vec<_>>();
-
-                let mut char_pos = 0;
-                for (i, line) in lines.iter().enumerate() {
-                    if i >= start_line && i <= end_line {
-                        let line_span = Span::range(
-                            Loc::at(char_pos),
-                            Loc::at(char_pos + line.len()),
-                        );
-
-                        let any_intersects = span_iter
-                            .clone()
-                            .any(|(s, _)| s.intersects(line_span));
-
-                        let any_starts_or_ends = span_iter
-                            .clone()
-                            .any(|(s, _)| match s.in_context(self.src) {
-                                Some(((sl, _), (el, _))) => sl == i || el == i,
-                                _ => false,
-                            });
-
-                        // let any_wraps = span_iter
-                        //     .clone()
-                        //     .any(|(s, _)| match s.in_context(self.src) {
-                        //         Some(((sl, _), (el, _))) => sl != el && sl < i && el >= i,
-                        //         _ => false,
-                        //     });
-
-                        if any_intersects {
-                            writeln!(f, "{:>4} | {}", i + 1, line.replace("\t", " "))?;
-                        }
+impl<'a> ErrorInSrc<'a> {
+    // Display the lines of a file that the given spans cover, underlining the spans
+    fn highlight_spans(&self, f: &mut fmt::Formatter, file: FileId, spans: &[(Span, bool)]) -> fmt::Result {
+        let src = self.srcs.code(file);
+        let span_iter = spans.iter().copied();
+
+        if let Some(((start_line, start_col), (end_line, end_col))) = span_iter
+            .clone()
+            .fold(Span::none(), |a, (s, _)| a.union(s))
+            .in_context(src)
+        {
+            writeln!(f, "-> {}, line {}, column {}", self.srcs.name(file), start_line + 1, start_col + 1)?;
+
+            let lines = src.lines().collect::<Vec<_>>();
+
+            let mut char_pos = 0;
+            for (i, line) in lines.iter().enumerate() {
+                if i >= start_line && i <= end_line {
+                    let line_span = Span::range(
+                        Loc::at(char_pos),
+                        Loc::at(char_pos + line.chars().count()),
+                    ).in_file(file);
+
+                    let any_intersects = span_iter
+                        .clone()
+                        .any(|(s, _)| s.intersects(line_span));
+
+                    let any_starts_or_ends = span_iter
+                        .clone()
+                        .any(|(s, _)| match s.in_context(src) {
+                            Some(((sl, _), (el, _))) => sl == i || el == i,
+                            _ => false,
+                        });
+
+                    if any_intersects {
+                        writeln!(f, "{:>4} | {}", i + 1, line.replace("\t", " "))?;
+                    }
 
-                        // Underline
-                        if any_starts_or_ends {
-                            write!(f, "       ")?;
-                            for _ in 0..line.len() {
-                                if let Some((span, is_primary)) = span_iter
+                    // Underline
+                    if any_starts_or_ends {
+                        write!(f, "       ")?;
+                        for _ in 0..line.chars().count() {
+                            if let Some((span, is_primary)) = span_iter
+                                .clone()
+                                .find(|(s, is_primary)| s.contains(Loc::at(char_pos)) && *is_primary)
+                                .or_else(|| span_iter
                                     .clone()
-                                    .find(|(s, is_primary)| s.contains(Loc::at(char_pos)) && *is_primary)
-                                    .or_else(|| span_iter
-                                        .clone()
-                                        .find(|(s, _)| s.contains(Loc::at(char_pos))))
-                                {
-                                    write!(f, "{}", if is_primary { '^' } else { '-' })?;
-                                } else {
-                                    write!(f, " ")?;
-                                }
-
-                                char_pos += 1;
+                                    .find(|(s, _)| s.contains(Loc::at(char_pos))))
+                            {
+                                write!(f, "{}", if is_primary { '^' } else { '-' })?;
+                            } else {
+                                write!(f, " ")?;
                             }
-                            writeln!(f, "")?;
+
                             char_pos += 1;
-                        } else {
-                            char_pos += line.len() + 1;
                         }
+                        writeln!(f, "")?;
+                        char_pos += 1;
                     } else {
-                        char_pos += line.len() + 1;
+                        char_pos += line.chars().count() + 1;
                     }
+                } else {
+                    char_pos += line.chars().count() + 1;
                 }
             }
+        }
 
-            Ok(())
+        Ok(())
+    }
+}
+
+impl<'a> fmt::Display for ErrorInSrc<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.error.severity {
+            Severity::Error => "Error",
+            Severity::Warning => "Warning",
         };
+        match self.error.code {
+            Some(code) => writeln!(f, "{}[{}]: {}", kind, code, self.error.msg())?,
+            None => writeln!(f, "{}: {}", kind, self.error.msg())?,
+        }
 
-        writeln!(f, "Error: {}", self.error.msg)?;
-        highlight_spans(f, &[])?;
+        let spans = self.error.primary_spans
+            .iter()
+            .map(|s| (*s, true))
+            .chain(self.error.secondary_spans.iter().map(|s| (*s, false)))
+            .collect::<Vec<_>>();
+
+        // Show the spans of each file separately, in the order that the files were first mentioned
+        let mut files = Vec::new();
+        for file in spans.iter().filter_map(|(s, _)| s.file()) {
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+        for file in files {
+            let file_spans = spans
+                .iter()
+                .copied()
+                .filter(|(s, _)| s.file() == Some(file))
+                .collect::<Vec<_>>();
+            self.highlight_spans(f, file, &file_spans)?;
+        }
 
         for hint in self.error.hints.iter() {
             writeln!(f, "Hint: {}", hint)?;
         }
 
+        for (_, replacement, msg) in self.error.suggestions.iter() {
+            writeln!(f, "Suggestion: {}: '{}'", msg, replacement)?;
+        }
+
+        if self.error.frames.len() > 0 {
+            writeln!(f, "Stack trace (calls in tail position are not shown):")?;
+        }
+        for (name, span) in self.error.frames.iter() {
+            match span.file().and_then(|file| Some((file, span.in_context(self.srcs.code(file))?))) {
+                Some((file, ((line, col), _))) => {
+                    writeln!(f, "  at {} ({}, line {}, column {})", name, self.srcs.name(file), line + 1, col + 1)?;
+                    if let Some(src_line) = self.srcs.code(file).lines().nth(line) {
+                        writeln!(f, "{:>4} | {}", line + 1, src_line.trim())?;
+                    }
+                },
+                None => writeln!(f, "  at {}", name)?,
+            }
+        }
+
         Ok(())
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "lowercase"))]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+// A line and column in a source file, both starting at 1
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+// A span resolved to a file and a pair of positions. The end position is exclusive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct DiagnosticSpan {
+    pub file: String,
+    pub start: Position,
+    pub end: Position,
+    pub primary: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Suggestion {
+    pub message: String,
+    pub span: DiagnosticSpan,
+    pub replacement: String,
+}
+
+// An error in a form suitable for tools, which does not depend on the source map
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Option<String>,
+    pub message: String,
+    pub spans: Vec<DiagnosticSpan>,
+    pub hints: Vec<String>,
+    pub suggestions: Vec<Suggestion>,
+    // The diagnostic as it would be displayed to a user
+    pub rendered: String,
+}
+
+#[cfg(feature = "json")]
+impl Diagnostic {
+    // Render the diagnostic as a single line of JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Diagnostics can always be serialised")
+    }
+}
+
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
 pub enum Thing {
     Char(char),
@@ -248,3 +526,33 @@ impl fmt::Display for Thing {
         }
     }
 }
+
+#[test]
+fn json_diagnostics() {
+    let mut srcs = SourceMap::default();
+    let file = srcs.add("main.tao", "def x = { a: 1 }\ndef y = x.b\n");
+    let err = Error::custom(format!("No such field \"b\""))
+        .with_code("E0202")
+        .with_span(Span::range(Loc::at(27), Loc::at(28)).in_file(file))
+        .with_secondary_span(Span::range(Loc::at(8), Loc::at(16)).in_file(file))
+        .with_suggestion(Span::range(Loc::at(27), Loc::at(28)).in_file(file), format!("a"), format!("A field with a similar name exists"));
+
+    let diag = err.diagnostic(&srcs);
+    assert_eq!(diag.code.as_deref(), Some("E0202"));
+    assert_eq!(diag.spans.len(), 2);
+    assert!(diag.spans[0].primary && !diag.spans[1].primary);
+    assert_eq!(diag.spans[0].start, Position { line: 2, column: 11 });
+    assert_eq!(diag.spans[0].end, Position { line: 2, column: 12 });
+    assert_eq!(diag.spans[1].start, Position { line: 1, column: 9 });
+
+    #[cfg(feature = "json")]
+    {
+        let json = diag.to_json();
+        assert!(json.starts_with("{\"severity\":\"error\",\"code\":\"E0202\",\"message\":\"No such field \\\"b\\\"\",\"spans\":[{\"file\":\"main.tao\",\"start\":{\"line\":2,\"column\":11}"));
+        assert!(json.contains("\"suggestions\":[{\"message\":\"A field with a similar name exists\",\"span\":"));
+        assert!(!json.contains('\n'));
+    }
+
+    assert_eq!(closest("lenght", vec!["length", "len", "head"]), Some("length"));
+    assert_eq!(closest("foo", vec!["length", "head"]), None);
+}
@@ -0,0 +1,372 @@
+use crate::{
+    ast,
+    error::Error,
+    lex::{self, Delimiter, Op, Token},
+    node::SrcNode,
+    src::{SourceMap, FileId},
+};
+
+// Lay out the code in `file` canonically. Only whitespace changes: the line breaks in the source are kept (with runs
+// of blank lines collapsed), while indentation, the spacing between tokens and the placement of `match` arms are
+// made consistent.
+pub fn format(srcs: &SourceMap, file: FileId) -> Result<String, Vec<Error>> {
+    let code = srcs.code(file);
+    let tokens = lex::lex(code, file)?;
+    let (_, errors) = ast::parse_module(&tokens);
+    if errors.len() > 0 {
+        return Err(errors);
+    }
+
+    let mut printer = Printer {
+        newlines: code
+            .chars()
+            .enumerate()
+            .filter(|(_, c)| *c == '\n')
+            .map(|(i, _)| i)
+            .collect(),
+        output: String::new(),
+        indent: 0,
+        line: None,
+        tight: true,
+        opened: false,
+    };
+    printer.tokens(&lex::lex_with_comments(code, file)?, Block::Top, 0, false);
+    let output = if printer.output.len() == 0 {
+        printer.output
+    } else {
+        printer.output + "\n"
+    };
+
+    // Layout must never change the meaning of the code
+    match lex::lex(&output, file) {
+        Ok(formatted) if formatted == tokens => Ok(output),
+        _ => Err(vec![Error::custom(format!("Formatting changed the tokens of '{}', which is a bug in the formatter", srcs.name(file)))]),
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Block {
+    Top,
+    Paren,
+    Brack,
+    // A record, record type or record pattern
+    Record,
+    // The statements of a `do` block
+    Do,
+    // The arms of a `match`
+    Arms,
+}
+
+struct Printer {
+    // The offsets of the line breaks in the source
+    newlines: Vec<usize>,
+    output: String,
+    // The indentation of the line being written
+    indent: usize,
+    // The source line of the last token written
+    line: Option<usize>,
+    // Whether the next token should directly follow the last
+    tight: bool,
+    // Whether the last token written opened a delimiter
+    opened: bool,
+}
+
+impl Printer {
+    fn line_of(&self, pos: usize) -> usize {
+        match self.newlines.binary_search(&pos) {
+            Ok(line) | Err(line) => line,
+        }
+    }
+
+    fn newline(&mut self, indent: usize, blank: bool) {
+        if self.output.len() > 0 {
+            self.output.push('\n');
+            // Blocks never start with a blank line
+            if blank && !self.opened {
+                self.output.push('\n');
+            }
+        }
+        (0..indent).for_each(|_| self.output.push('\t'));
+        self.indent = indent;
+        self.tight = true;
+    }
+
+    // Tokens are separated by a space unless either side asks to be tight
+    fn write(&mut self, text: &str, tight_before: bool, tight_after: bool) {
+        if !self.tight && !tight_before {
+            self.output.push(' ');
+        }
+        self.output += text;
+        self.tight = tight_after;
+        self.opened = false;
+    }
+
+    // Write the tokens within a block. Lines that start a new item (a declaration, statement, arm or element) are
+    // indented at `base`, and lines that continue an item are indented once more.
+    fn tokens(&mut self, tokens: &[SrcNode<Token>], block: Block, base: usize, pattern: bool) {
+        let mut state = State {
+            block,
+            pattern,
+            expect_value: true,
+            params: false,
+            fn_params: false,
+            arm_pattern: block == Block::Arms,
+            let_pattern: false,
+            matches: 0,
+            decl: None,
+            prev: None,
+            prev2: None,
+        };
+
+        for (idx, token) in tokens.iter().enumerate() {
+            let (start, end) = token.span().bounds().unwrap_or((0, 0));
+            let line = self.line_of(start);
+
+            // Comments are indented like the code that follows them
+            let is_comment = matches!(&**token, Token::Comment(_));
+            let starts = tokens[idx..]
+                .iter()
+                .find(|token| !matches!(&***token, Token::Comment(_)))
+                .map_or(true, |next| state.starts(next));
+            let forced = !is_comment && state.breaks_before(token);
+            if forced || self.line.map_or(false, |prev| line > prev) {
+                let blank = self.line.map_or(false, |prev| line > prev + 1);
+                self.newline(base + if starts { 0 } else { 1 }, blank);
+            }
+            self.line = Some(line);
+
+            match &**token {
+                Token::Comment(text) => self.write(&format!("#{}", text.trim_end()), false, false),
+                Token::Tree(delim, inner) => {
+                    let call = *delim == Delimiter::Paren && matches!(
+                        state.prev,
+                        Some(Token::Ident(_)) | Some(Token::Intrinsic(_)) | Some(Token::Tree(_, _))
+                    );
+                    let child = match delim {
+                        Delimiter::Paren => Block::Paren,
+                        Delimiter::Brack => Block::Brack,
+                        Delimiter::Brace if state.prev2 == Some(&Token::Do) => Block::Do,
+                        Delimiter::Brace if state.matches > 0 && !state.expect_value => {
+                            state.matches -= 1;
+                            Block::Arms
+                        },
+                        Delimiter::Brace => Block::Record,
+                    };
+
+                    let open_indent = self.indent;
+                    self.write(&delim.left().to_string(), call, *delim != Delimiter::Brace);
+                    self.opened = true;
+                    self.tokens(inner, child, open_indent + 1, state.in_pattern());
+
+                    let close = self.line_of(end.saturating_sub(1));
+                    if inner.len() > 0 && (child == Block::Arms || self.line.map_or(false, |prev| close > prev)) {
+                        self.newline(open_indent, false);
+                    }
+                    self.write(&delim.right().to_string(), *delim != Delimiter::Brace || inner.len() == 0, false);
+                    self.line = Some(close);
+                    state.advance(token);
+                },
+                _ => {
+                    let (tight_before, tight_after) = state.spacing(token, &tokens[idx + 1..]);
+                    self.write(&text(token), tight_before, tight_after);
+                    self.line = Some(self.line_of(end.saturating_sub(1)));
+                    state.advance(token);
+                },
+            }
+        }
+    }
+}
+
+// What is known about the tokens already written within a block
+struct State<'a> {
+    block: Block,
+    // Whether the block is within a pattern
+    pattern: bool,
+    expect_value: bool,
+    // Whether we are between the pipes of a parameter list
+    params: bool,
+    // Whether the next pipe opens the parameters of a `fn` declaration
+    fn_params: bool,
+    arm_pattern: bool,
+    let_pattern: bool,
+    // The number of `match` expressions still waiting for their arms
+    matches: usize,
+    decl: Option<&'a Token>,
+    prev: Option<&'a Token>,
+    prev2: Option<&'a Token>,
+}
+
+impl<'a> State<'a> {
+    fn in_pattern(&self) -> bool {
+        self.pattern || self.params || self.arm_pattern || self.let_pattern
+    }
+
+    // Pipes within the pattern of an arm separate its alternatives
+    fn is_arm(&self, token: &Token) -> bool {
+        self.block == Block::Arms
+            && *token == Token::Pipe
+            && !self.params
+            && (self.prev.is_none() || (!self.expect_value && !self.arm_pattern))
+    }
+
+    // Whether a line beginning with the token starts a new item
+    fn starts(&self, token: &Token) -> bool {
+        match self.block {
+            Block::Top => is_decl(token),
+            Block::Arms => self.prev.is_none() || self.is_arm(token),
+            Block::Do => self.prev.is_none() || self.prev == Some(&Token::Semicolon),
+            Block::Paren | Block::Brack | Block::Record => self.prev.is_none() || self.prev == Some(&Token::Comma),
+        }
+    }
+
+    // Declarations and `match` arms always go on their own line
+    fn breaks_before(&self, token: &Token) -> bool {
+        match self.block {
+            Block::Top => self.prev.is_some() && is_decl(token),
+            Block::Arms => self.prev.is_none() || self.is_arm(token),
+            _ => false,
+        }
+    }
+
+    // Find whether the token should be written tightly against the tokens before and after it
+    fn spacing(&mut self, token: &Token, rest: &[SrcNode<Token>]) -> (bool, bool) {
+        match token {
+            Token::Comma | Token::Semicolon => (true, false),
+            Token::Dot | Token::Separator | Token::Op(Op::Range) => (true, true),
+            // Bindings and fields are spaced, but infix calls are not
+            Token::Colon => {
+                let next = rest.iter().find(|token| !matches!(&***token, Token::Comment(_)));
+                let binding = self.in_pattern()
+                    || matches!(next.map(|token| &**token), Some(Token::Op(Op::Ellipsis)))
+                    || (self.block == Block::Record
+                        && matches!(self.prev, Some(Token::Ident(_)))
+                        && matches!(self.prev2, None | Some(Token::Comma)));
+                (true, !binding)
+            },
+            Token::Op(Op::Not) => (false, true),
+            Token::Op(Op::Sub) if self.expect_value => (false, true),
+            Token::Pipe => if self.decl == Some(&Token::Data) {
+                (false, false)
+            } else if self.fn_params {
+                self.fn_params = false;
+                self.params = true;
+                (false, true)
+            } else if self.params {
+                self.params = false;
+                (true, false)
+            } else if self.is_arm(token) {
+                self.arm_pattern = true;
+                (false, false)
+            } else if self.arm_pattern {
+                (false, false)
+            } else {
+                self.params = true;
+                (false, true)
+            },
+            _ => (false, false),
+        }
+    }
+
+    fn advance(&mut self, token: &'a Token) {
+        match token {
+            // A guard ends the pattern of an arm
+            Token::RMap | Token::If => self.arm_pattern = false,
+            Token::Let => self.let_pattern = true,
+            Token::Op(Op::Eq) => self.let_pattern = false,
+            Token::Match => self.matches += 1,
+            token if self.block == Block::Top && is_decl(token) => {
+                self.decl = Some(token);
+                self.fn_params = *token == Token::Fn;
+                self.params = false;
+                self.let_pattern = false;
+                self.matches = 0;
+            },
+            _ => {},
+        }
+        self.expect_value = !matches!(
+            token,
+            Token::Number(_) | Token::Boolean(_) | Token::Char(_) | Token::String(_) | Token::Null
+                | Token::Ident(_) | Token::Intrinsic(_) | Token::TypeName(_) | Token::Tree(_, _)
+                | Token::QuestionMark | Token::Wildcard
+        );
+        self.prev2 = self.prev;
+        self.prev = Some(token);
+    }
+}
+
+fn is_decl(token: &Token) -> bool {
+    match token {
+        Token::Def | Token::Fn | Token::Type | Token::Data | Token::Class | Token::Instance | Token::Mod | Token::Use => true,
+        _ => false,
+    }
+}
+
+// Write a token as it would appear in the source
+fn text(token: &Token) -> String {
+    // `$` reverses the ident/type name
+    let name = |name: &str, upper: bool| if name.starts_with(|c: char| c.is_ascii_uppercase()) == upper {
+        name.to_string()
+    } else {
+        format!("${}", name)
+    };
+
+    match token {
+        Token::Char(c) => format!("'{}'", escape(*c)),
+        Token::String(s) => format!("\"{}\"", s.chars().map(escape).collect::<String>()),
+        Token::Ident(i) => name(i, false),
+        Token::Intrinsic(i) => format!("@{}", name(i, false)),
+        Token::TypeName(i) => name(i, true),
+        token => format!("{}", token),
+    }
+}
+
+fn escape(c: char) -> String {
+    match c {
+        '\\' => format!("\\\\"),
+        '"' => format!("\\\""),
+        '\x08' => format!("\\b"),
+        '\x0C' => format!("\\f"),
+        '\n' => format!("\\n"),
+        '\r' => format!("\\r"),
+        '\t' => format!("\\t"),
+        c => c.to_string(),
+    }
+}
+
+#[test]
+fn layout() {
+    let mut srcs = SourceMap::default();
+    let code = "def  x=1   # one\n\n\n\nfn f |a,b|match (a,b){ | (0,_)=>b\n|_ =>a}\ndef y = {a:1,b:- 2}.b:f(!x)";
+    let file = srcs.add("main.tao", code);
+    assert_eq!(
+        format(&srcs, file).unwrap(),
+        "def x = 1 # one\n\nfn f |a, b| match (a, b) {\n\t| (0, _) => b\n\t| _ => a\n}\ndef y = { a: 1, b: -2 }.b:f(!x)\n",
+    );
+
+    // Pipes between the alternatives of a pattern do not start new arms
+    let file = srcs.add("main.tao", "fn g |c|match c{|'a' .. 'z'|'_'=>true\n|_=>false}");
+    assert_eq!(
+        format(&srcs, file).unwrap(),
+        "fn g |c| match c {\n\t| 'a'..'z' | '_' => true\n\t| _ => false\n}\n",
+    );
+}
+
+#[test]
+fn idempotent() {
+    let root = env!("CARGO_MANIFEST_DIR");
+    let mut paths = std::fs::read_dir(format!("{}/examples", root))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "tao"))
+        .collect::<Vec<_>>();
+    paths.push(format!("{}/src/tao/prelude.tao", root).into());
+
+    for path in paths {
+        let mut srcs = SourceMap::default();
+        let name = path.display().to_string();
+        let file = srcs.add(name.clone(), std::fs::read_to_string(&path).unwrap());
+        let once = format(&srcs, file).unwrap_or_else(|errors| panic!("Failed to format {}: {:?}", name, errors));
+        let file = srcs.add(name.clone(), once.clone());
+        assert_eq!(format(&srcs, file).unwrap(), once, "Formatting {} again changed it", name);
+    }
+}
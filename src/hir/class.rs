@@ -0,0 +1,358 @@
+use std::collections::{HashMap, HashSet};
+use internment::LocalIntern;
+use crate::{
+    Error,
+    ty::Type,
+    src::Span,
+    node::SrcNode,
+    ast,
+};
+use super::{
+    data::DataCtx,
+    infer::{InferCtx, TypeId, Constraint},
+};
+
+type Ident = LocalIntern<String>;
+
+#[derive(Debug)]
+pub struct Class {
+    span: Span,
+    pub param: SrcNode<Ident>,
+    pub members: Vec<(SrcNode<Ident>, SrcNode<Type>)>,
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    span: Span,
+    pub class: SrcNode<Ident>,
+    pub generics: Vec<SrcNode<Ident>>,
+    pub given: Vec<(SrcNode<Ident>, SrcNode<Ident>)>,
+    pub ty: SrcNode<Type>,
+    // The names of the definitions that implement each member
+    pub members: HashMap<Ident, Ident>,
+}
+
+#[derive(Default, Debug)]
+pub struct ClassCtx {
+    classes: HashMap<Ident, Class>,
+    // The class that each member belongs to
+    members: HashMap<Ident, Ident>,
+    instances: Vec<Instance>,
+
+    // Classes and members that failed to compile
+    poisoned: HashSet<Ident>,
+}
+
+// Determine whether two types have the same outermost type constructor
+pub fn same_head(a: &Type, b: &Type) -> bool {
+    match (a, b) {
+        (Type::Primitive(a), Type::Primitive(b)) => a == b,
+        (Type::List(_), Type::List(_)) => true,
+        (Type::Tuple(a), Type::Tuple(b)) => a.len() == b.len(),
+        (Type::Record(a), Type::Record(b)) => a.len() == b.len() && a
+            .iter()
+            .zip(b.iter())
+            .all(|((a, _), (b, _))| **a == **b),
+        (Type::Func(_, _), Type::Func(_, _)) => true,
+        (Type::Data(a, _), Type::Data(b, _)) => **a == **b,
+        _ => false,
+    }
+}
+
+// Find the types that the type parameters of `pat` correspond to in `ty`
+fn match_type(pat: &Type, ty: &Type, bindings: &mut HashMap<Ident, Type>) {
+    match (pat, ty) {
+        (Type::GenParam(name), ty) => { bindings.insert(*name, ty.clone()); },
+        (Type::List(a), Type::List(b)) => match_type(a, b, bindings),
+        (Type::Tuple(a), Type::Tuple(b)) => a
+            .iter()
+            .zip(b.iter())
+            .for_each(|(a, b)| match_type(a, b, bindings)),
+        (Type::Record(a), Type::Record(b)) => a
+            .iter()
+            .zip(b.iter())
+            .for_each(|((_, a), (_, b))| match_type(a, b, bindings)),
+        (Type::Func(ai, ao), Type::Func(bi, bo)) => {
+            match_type(ai, bi, bindings);
+            match_type(ao, bo, bindings);
+        },
+        (Type::Data(_, a), Type::Data(_, b)) => a
+            .iter()
+            .zip(b.iter())
+            .for_each(|(a, b)| match_type(a, b, bindings)),
+        _ => {},
+    }
+}
+
+impl ClassCtx {
+    pub fn from_ast_module(module: &SrcNode<ast::Module>, data_ctx: &DataCtx) -> (Self, Vec<Error>) {
+        let mut this = Self::default();
+        let mut errors = Vec::new();
+
+        // Do a first pass through the classes to find their members
+        module.decls
+            .iter()
+            .filter_map(|decl| if let ast::Decl::Class(class) = &**decl { Some(class) } else { None })
+            .for_each(|class| {
+                if let Some(other) = this.classes.get(&*class.name) {
+                    errors.push(Error::custom(format!("Conflict between classes with the same name"))
+                        .with_code("E0101")
+                        .with_span(class.name.span())
+                        .with_secondary_span(other.span));
+                    return;
+                }
+
+                let mut members = Vec::new();
+                for (name, ty) in class.members.iter() {
+                    let mut infer = InferCtx::from_data_ctx(data_ctx);
+                    infer.insert_generic(*class.param, class.param.span());
+                    let ty = match ty
+                        .to_type_id(&mut infer, &|_| None)
+                        .and_then(|type_id| infer.reconstruct(type_id, ty.span()))
+                    {
+                        Ok(ty) => ty,
+                        Err(err) => {
+                            // Uses of the member should not produce further errors
+                            this.poisoned.insert(**name);
+                            if !err.is_poisoned() {
+                                errors.push(err);
+                            }
+                            continue;
+                        },
+                    };
+
+                    // Uses of a member must be able to determine which instance they refer to
+                    let mut uses_param = false;
+                    ty.visit(&mut |ty| if **ty == Type::GenParam(*class.param) {
+                        uses_param = true;
+                    });
+
+                    if !uses_param {
+                        errors.push(Error::custom(format!("Type of member '{}' must mention class parameter '{}'", **name, *class.param))
+                            .with_code("E0303")
+                            .with_span(ty.span())
+                            .with_secondary_span(class.param.span()));
+                        this.poisoned.insert(**name);
+                    } else if let Some(other) = this.members.get(&**name) {
+                        errors.push(Error::custom(format!("Member '{}' is already declared by class '{}'", **name, other))
+                            .with_code("E0101")
+                            .with_span(name.span())
+                            .with_secondary_span(this.classes[other].members
+                                .iter()
+                                .find(|(other_name, _)| **other_name == **name)
+                                .map(|(other_name, _)| other_name.span())
+                                .unwrap_or(Span::none())));
+                    } else {
+                        this.members.insert(**name, *class.name);
+                        members.push((name.clone(), ty));
+                    }
+                }
+
+                this.classes.insert(*class.name, Class {
+                    span: class.name.span(),
+                    param: class.param.clone(),
+                    members,
+                });
+            });
+
+        // Do a second pass to collect instances, now that all classes are known
+        module.decls
+            .iter()
+            .filter_map(|decl| if let ast::Decl::Instance(instance) = &**decl { Some(instance) } else { None })
+            .enumerate()
+            .for_each(|(idx, instance)| {
+                if let Err(err) = this.insert_instance(idx, instance, data_ctx) {
+                    if !err.is_poisoned() {
+                        errors.push(err);
+                    }
+                }
+            });
+
+        (this, errors)
+    }
+
+    // Check that a `given` clause refers to a class and one of the given generic parameters
+    pub fn check_given(&self, class: &SrcNode<Ident>, param: &SrcNode<Ident>, generics: &[SrcNode<Ident>]) -> Result<(), Error> {
+        if self.poisoned.contains(&**class) {
+            Err(Error::poisoned())
+        } else if !self.classes.contains_key(&**class) {
+            Err(Error::custom(format!("No such class '{}'", **class))
+                .with_code("E0100")
+                .with_span(class.span()))
+        } else if !generics.iter().any(|gen| **gen == **param) {
+            Err(Error::custom(format!("'{}' is not a type parameter", **param))
+                .with_code("E0304")
+                .with_span(param.span())
+                .with_hint(format!("Only type parameters may be constrained by classes")))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn insert_instance(&mut self, idx: usize, instance: &ast::Instance, data_ctx: &DataCtx) -> Result<(), Error> {
+        let class = if self.poisoned.contains(&*instance.class) {
+            return Err(Error::poisoned());
+        } else if let Some(class) = self.classes.get(&*instance.class) {
+            class
+        } else {
+            return Err(Error::custom(format!("No such class '{}'", *instance.class))
+                .with_code("E0100")
+                .with_span(instance.class.span()));
+        };
+
+        let mut infer = InferCtx::from_data_ctx(data_ctx);
+        // Add this instance's generic parameters to the infer context
+        instance
+            .generics
+            .iter()
+            .for_each(|name| infer.insert_generic(**name, name.span()));
+        let ty = instance.ty
+            .to_type_id(&mut infer, &|_| None)
+            .and_then(|type_id| infer.reconstruct(type_id, instance.ty.span()))?;
+
+        if let Type::GenParam(_) = &*ty {
+            return Err(Error::custom(format!("Instances may not be declared for type parameters"))
+                .with_code("E0304")
+                .with_span(instance.ty.span())
+                .with_hint(format!("Declare instances for specific types, such as '[{}]'", **ty)));
+        }
+
+        // Ensure that all generic parameters are in use
+        for gen in instance.generics.iter() {
+            let mut uses_gen = false;
+            ty.visit(&mut |ty| if **ty == Type::GenParam(**gen) {
+                uses_gen = true;
+            });
+            if !uses_gen {
+                return Err(Error::custom(format!("Type parameter '{}' must be mentioned by type '{}'", **gen, **ty))
+                    .with_code("E0206")
+                    .with_span(gen.span())
+                    .with_span(instance.ty.span())
+                    .with_hint(format!("Consider removing '{}' from the list of generics", **gen)));
+            }
+        }
+
+        for (given_class, param) in instance.given.iter() {
+            self.check_given(given_class, param, &instance.generics)?;
+        }
+
+        if let Some(other) = self.instances
+            .iter()
+            .find(|other| *other.class == *instance.class && same_head(&other.ty, &ty))
+        {
+            return Err(Error::custom(format!("Conflicting instances of class '{}'", *instance.class))
+                .with_code("E0101")
+                .with_span(instance.ty.span())
+                .with_secondary_span(other.span));
+        }
+
+        let mut members = HashMap::new();
+        for (name, _) in instance.members.iter() {
+            if !class.members.iter().any(|(member, _)| **member == **name) {
+                return Err(Error::custom(format!("'{}' is not a member of class '{}'", **name, *instance.class))
+                    .with_code("E0304")
+                    .with_span(name.span())
+                    .with_secondary_span(class.span));
+            } else if members.contains_key(&**name) {
+                return Err(Error::custom(format!("Member '{}' is implemented more than once", **name))
+                    .with_code("E0101")
+                    .with_span(name.span()));
+            } else {
+                members.insert(**name, Ident::new(format!("{}::{}#{}", *instance.class, **name, idx)));
+            }
+        }
+
+        if let Some((missing, _)) = class.members
+            .iter()
+            .find(|(member, _)| !members.contains_key(&**member))
+        {
+            return Err(Error::custom(format!("Instance of class '{}' does not implement member '{}'", *instance.class, **missing))
+                .with_code("E0301")
+                .with_span(instance.ty.span())
+                .with_secondary_span(missing.span())
+                .with_hint(format!("Add 'member {} = ...' to the instance", **missing)));
+        }
+
+        self.instances.push(Instance {
+            span: instance.ty.span(),
+            class: instance.class.clone(),
+            generics: instance.generics.clone(),
+            given: instance.given.clone(),
+            ty,
+            members,
+        });
+        Ok(())
+    }
+
+    pub fn class(&self, name: Ident) -> Option<&Class> {
+        self.classes.get(&name)
+    }
+
+    pub fn is_member(&self, name: Ident) -> bool {
+        self.members.contains_key(&name) || self.poisoned.contains(&name)
+    }
+
+    // Find the instance declared by the given instance declaration
+    pub fn declared_instance(&self, instance: &ast::Instance) -> Option<&Instance> {
+        self.instances
+            .iter()
+            .find(|inst| inst.span == instance.ty.span())
+    }
+
+    pub fn instances_of(&self, class: Ident) -> impl Iterator<Item=&Instance> + '_ {
+        self.instances
+            .iter()
+            .filter(move |instance| *instance.class == class)
+    }
+
+    pub fn get_member_type(
+        &self,
+        member: Ident,
+        infer: &mut InferCtx,
+        span: Span,
+    ) -> Result<Option<(Ident, TypeId, TypeId)>, Error> {
+        if self.poisoned.contains(&member) {
+            return Err(Error::poisoned());
+        }
+
+        let class_name = match self.members.get(&member) {
+            Some(class_name) => *class_name,
+            None => return Ok(None),
+        };
+        let class = &self.classes[&class_name];
+        let member_ty = &class.members
+            .iter()
+            .find(|(name, _)| **name == member)
+            .unwrap()
+            .1;
+
+        // The class parameter is free, but constrained to be an instance of the class
+        let (type_id, params) = infer.instantiate_ty(std::slice::from_ref(&class.param), member_ty, span);
+        let param_ty = params[0].1;
+        infer.add_constraint(Constraint::Class {
+            class: SrcNode::new(class_name, span),
+            ty: param_ty,
+        });
+
+        Ok(Some((class_name, type_id, param_ty)))
+    }
+
+    // Find the definition that implements a member for the given (concrete) type, along with the types of the
+    // instance's generic parameters
+    pub fn resolve_member(&self, class: Ident, member: Ident, ty: &Type) -> Option<(Ident, Vec<Type>)> {
+        let instance = self
+            .instances_of(class)
+            .find(|instance| same_head(&instance.ty, ty))?;
+
+        let mut bindings = HashMap::new();
+        match_type(&instance.ty, ty, &mut bindings);
+
+        Some((
+            *instance.members.get(&member)?,
+            instance.generics
+                .iter()
+                .map(|gen| bindings.remove(&**gen))
+                .collect::<Option<_>>()?,
+        ))
+    }
+}
@@ -1,4 +1,4 @@
-use std::{collections::HashMap, convert::TryFrom};
+use std::{collections::{HashMap, HashSet}, convert::TryFrom};
 use internment::LocalIntern;
 use crate::{
     Error,
@@ -38,6 +38,9 @@ pub struct DataCtx {
     data: HashMap<DataId, Data>,
     constructors: HashMap<Ident, (DataId, usize)>,
     type_aliases: HashMap<Ident, TypeAlias>,
+
+    // Types that failed to compile
+    poisoned: HashSet<Ident>,
 }
 
 impl DataCtx {
@@ -53,6 +56,7 @@ impl DataCtx {
                 .map(|alias| alias.span))
         {
             Err(Error::custom(format!("Conflict between types with the same name"))
+                .with_code("E0101")
                 .with_span(data.span)
                 .with_secondary_span(other_span))
         } else {
@@ -65,28 +69,34 @@ impl DataCtx {
         }
     }
 
-    pub fn from_ast_module(module: &SrcNode<ast::Module>) -> Result<Self, Vec<Error>> {
+    pub fn from_ast_module(module: &SrcNode<ast::Module>) -> (Self, Vec<Error>) {
         let mut this = Self::default();
+        let mut errors = Vec::new();
+
+        // Types that failed to parse have already been reported
+        module.decls
+            .iter()
+            .for_each(|decl| if let ast::Decl::Error { name: Some(name), is_type: true } = &**decl {
+                this.poisoned.insert(**name);
+            });
 
         // Do a first pass through the data types to build up their basic signatures
         module.decls
             .iter()
             .filter_map(|decl| if let ast::Decl::Data(data) = &**decl { Some(data) } else { None })
-            .try_for_each(|data| {
-                this.insert_data(*data.name, Data {
-                    span: data.name.span(),
-                    generics: data.generics.clone(),
-                    variants: Vec::new(),
-                })
-                    .map(|_| ())
-                    .map_err(|e| vec![e])
-            })?;
+            .for_each(|data| if let Err(err) = this.insert_data(*data.name, Data {
+                span: data.name.span(),
+                generics: data.generics.clone(),
+                variants: Vec::new(),
+            }) {
+                errors.push(err);
+            });
 
         // Do a pass to discover type aliases
         module.decls
             .iter()
             .filter_map(|decl| if let ast::Decl::TypeAlias(alias) = &**decl { Some(alias) } else { None })
-            .try_for_each(|alias| {
+            .for_each(|alias| {
                 let mut infer = InferCtx::from_data_ctx(&this);
                 // Add this type alias' generic parameters to the infer context
                 alias
@@ -94,70 +104,99 @@ impl DataCtx {
                     .iter()
                     .for_each(|name| infer.insert_generic(**name, name.span()));
                 // Turn the type alias into a concrete type
-                let type_id = alias.ty.to_type_id(&mut infer, &|_| None)?;
-                let ty = infer.reconstruct(type_id, alias.name.span())?;
-                this.type_aliases.insert(*alias.name, TypeAlias {
-                    span: alias.name.span(),
-                    generics: alias.generics.clone(),
-                    ty,
-                });
-                Ok(())
-            })
-            .map_err(|e| vec![e])?;
+                let ty = alias.ty
+                    .to_type_id(&mut infer, &|_| None)
+                    .and_then(|type_id| infer.reconstruct(type_id, alias.name.span()));
+                match ty {
+                    Ok(ty) => { this.type_aliases.insert(*alias.name, TypeAlias {
+                        span: alias.name.span(),
+                        generics: alias.generics.clone(),
+                        ty,
+                    }); },
+                    Err(err) => {
+                        // Uses of the alias should not produce further errors
+                        this.poisoned.insert(*alias.name);
+                        if !err.is_poisoned() {
+                            errors.push(err);
+                        }
+                    },
+                }
+            });
 
         // Do a second pass to properly collect datatype information
         module.decls
             .iter()
             .filter_map(|decl| if let ast::Decl::Data(data) = &**decl { Some(data) } else { None })
-            .try_for_each(|data| {
+            .for_each(|data| {
                 let id = this.data_names[&*data.name];
+                // Don't overwrite a previous declaration with the same name
+                if this.data[&id].span != data.name.span() {
+                    return;
+                }
+                let variant_ty = |ty: &SrcNode<ast::Type>, this: &Self| -> Result<SrcNode<Type>, Error> {
+                    let mut infer = InferCtx::from_data_ctx(this);
+                    // Add this datatype' generic parameters to the infer context
+                    data
+                        .generics
+                        .iter()
+                        .for_each(|name| infer.insert_generic(**name, name.span()));
+                    let type_id = ty.to_type_id(&mut infer, &|_| None)?;
+                    infer.reconstruct(type_id, data.name.span())
+                };
+                let mut poisoned = false;
                 let variants = match &*data.data_ty {
                     ast::DataType::Sum(variants) => variants
                         .iter()
                         .enumerate()
                         .map(|(variant, (name, ty))| {
                             this.constructors.insert(**name, (id, variant));
-                            Ok((name.clone(), ty
+                            (name.clone(), ty
                                 .as_ref()
-                                .map(|ty| {
-                                    let mut infer = InferCtx::from_data_ctx(&this);
-                                    // Add this datatype' generic parameters to the infer context
-                                    data
-                                        .generics
-                                        .iter()
-                                        .for_each(|name| infer.insert_generic(**name, name.span()));
-                                    let type_id = ty.to_type_id(&mut infer, &|_| None)?;
-                                    infer.reconstruct(type_id, data.name.span())
+                                .and_then(|ty| match variant_ty(ty, &this) {
+                                    Ok(ty) => Some(ty),
+                                    Err(err) => {
+                                        poisoned = true;
+                                        if !err.is_poisoned() {
+                                            errors.push(err);
+                                        }
+                                        None
+                                    },
                                 })
-                                .transpose()?
-                                .unwrap_or_else(|| SrcNode::new(Type::Tuple(Vec::new()), Span::none()))))
+                                .unwrap_or_else(|| SrcNode::new(Type::Tuple(Vec::new()), Span::none())))
                         })
-                        .collect::<Result<_, Error>>()?,
+                        .collect(),
                     ast::DataType::Product(ty) => {
                         this.constructors.insert(*data.name, (id, 0));
-                        let mut infer = InferCtx::from_data_ctx(&this);
-                        // Add this datatype' generic parameters to the infer context
-                        data
-                            .generics
-                            .iter()
-                            .for_each(|name| infer.insert_generic(**name, name.span()));
-                        let type_id = ty.to_type_id(&mut infer, &|_| None)?;
-                        vec![(data.name.clone(), infer.reconstruct(type_id, data.name.span())?)]
+                        let ty = match variant_ty(ty, &this) {
+                            Ok(ty) => ty,
+                            Err(err) => {
+                                poisoned = true;
+                                if !err.is_poisoned() {
+                                    errors.push(err);
+                                }
+                                SrcNode::new(Type::Tuple(Vec::new()), Span::none())
+                            },
+                        };
+                        vec![(data.name.clone(), ty)]
                     },
                 };
                 this.data.get_mut(&id).unwrap().variants = variants;
+                if poisoned {
+                    this.poisoned.insert(*data.name);
+                }
+            });
 
-                Ok(())
-            })
-            .map_err(|e| vec![e])?;
-
-        Ok(this)
+        (this, errors)
     }
 
     pub fn get_data_name(&self, id: DataId) -> Ident {
         *self.data_names_rev.get(&id).unwrap()
     }
 
+    pub fn get_data_by_name(&self, name: Ident) -> Option<DataId> {
+        self.data_names.get(&name).copied()
+    }
+
     pub fn get_data(&self, id: DataId) -> &Data {
         self.data.get(&id).unwrap()
     }
@@ -167,6 +206,7 @@ impl DataCtx {
             .get(&constructor)
             .copied()
             .ok_or_else(|| Error::custom(format!("No data type with constructor '{}' exists", constructor))
+                .with_code("E0100")
                 .with_span(span))
     }
 
@@ -178,6 +218,10 @@ impl DataCtx {
     ) -> Result<(DataId, usize, TypeId, Vec<(SrcNode<Ident>, TypeId)>, TypeId), Error> {
         let (data_id, variant) = self.get_data_id(constructor, span)?;
 
+        if self.poisoned.contains(&self.get_data_name(data_id)) {
+            return Err(Error::poisoned());
+        }
+
         let data = self.get_data(data_id);
 
         // let generics = data.generics
@@ -205,11 +249,14 @@ impl DataCtx {
     ) -> Result<TypeId, Error> {
         if let Some(ty_id) = infer.generic(**name) {
             Ok(ty_id)
+        } else if self.poisoned.contains(&**name) {
+            Err(Error::poisoned())
         } else if let Some(ty_info) = Primitive::try_from(name.as_str()).ok().map(TypeInfo::Primitive) {
             if params.len() == 0 {
                 Ok(infer.insert(ty_info, span))
             } else {
                 Err(Error::custom(format!("Primitive type '{}' cannot be parameterised", **name))
+                    .with_code("E0203")
                     .with_span(name.span())
                     .with_span(infer.span(params[0]))
                     .with_hint(format!("Remove all type parameters from '{}'", **name)))
@@ -219,6 +266,7 @@ impl DataCtx {
                 .get(&**name)
                 .map(|alias| if params.len() != alias.generics.len() {
                     Err(Error::custom(format!("Type '{}' expected {} parameters, found {}", **name, alias.generics.len(), params.len()))
+                        .with_code("E0203")
                         .with_span(span)
                         .with_secondary_span(alias.ty.span()))
                 } else {
@@ -235,6 +283,7 @@ impl DataCtx {
                 .map(|data_id| (*data_id, self.get_data(*data_id)))
                 .map(|(data_id, data)| if params.len() != data.generics.len() {
                     Err(Error::custom(format!("Data '{}' expected {} parameters, found {}", **name, data.generics.len(), params.len()))
+                        .with_code("E0203")
                         .with_span(span)
                         .with_secondary_span(data.span))
                 } else {
@@ -244,6 +293,7 @@ impl DataCtx {
                 res
             } else {
                 Err(Error::custom(format!("No such type '{}'", **name))
+                    .with_code("E0100")
                     .with_span(name.span()))
             }
         }
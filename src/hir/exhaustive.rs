@@ -0,0 +1,423 @@
+// Exhaustiveness and usefulness checking for patterns, following Maranget's "Warnings for pattern matching".
+//
+// Patterns are simplified into constructors applied to sub-patterns. A list of rows of patterns (the matrix) is then
+// checked against a vector of patterns: the vector is useful if it matches a value that no row matches. Arms are
+// exhaustive if a wildcard is not useful after them, and an arm is reachable if it is useful after the arms before it.
+
+use crate::ast::Literal;
+use super::{
+    data::{DataCtx, DataId},
+    Pat, TypeBinding, Ident,
+};
+use crate::ty::Type;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Ctor {
+    Bool(bool),
+    Num(f64),
+    Char(char),
+    // Numbers or characters between two values, inclusive of both. Rows may use these, but witnesses never do.
+    NumRange(f64, f64),
+    CharRange(char, char),
+    // A list of exactly this many items
+    List(usize),
+    // A list of at least this many items, of which only the first are inspected
+    ListFrom(usize),
+    Tuple(usize),
+    // The fields of a record, in order of name
+    Record(Vec<Ident>),
+    Variant(DataId, usize),
+}
+
+impl Ctor {
+    fn arity(&self) -> usize {
+        match self {
+            Ctor::Bool(_) | Ctor::Num(_) | Ctor::Char(_) | Ctor::NumRange(_, _) | Ctor::CharRange(_, _) => 0,
+            Ctor::List(len) | Ctor::ListFrom(len) | Ctor::Tuple(len) => *len,
+            Ctor::Record(fields) => fields.len(),
+            Ctor::Variant(_, _) => 1,
+        }
+    }
+
+    // Whether every value built with `other` is also built with this constructor
+    fn covers(&self, other: &Ctor) -> bool {
+        match (self, other) {
+            (Ctor::NumRange(start, end), Ctor::Num(x)) => start <= x && x <= end,
+            (Ctor::CharRange(start, end), Ctor::Char(c)) => start <= c && c <= end,
+            _ => self == other,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Pattern {
+    Wildcard,
+    Ctor(Ctor, Vec<Pattern>),
+    // A list starting with the given items. This is not a single constructor, since it matches lists of many lengths.
+    ListFront(Vec<Pattern>),
+    Or(Vec<Pattern>),
+}
+
+impl Pattern {
+    fn from_binding(binding: &TypeBinding) -> Self {
+        match &*binding.pat {
+            Pat::Wildcard => Pattern::Wildcard,
+            Pat::Literal(Literal::Boolean(x)) => Pattern::Ctor(Ctor::Bool(*x), Vec::new()),
+            Pat::Literal(Literal::Number(x)) => Pattern::Ctor(Ctor::Num(*x), Vec::new()),
+            Pat::Literal(Literal::Char(c)) => Pattern::Ctor(Ctor::Char(*c), Vec::new()),
+            // Strings are lists of characters
+            Pat::Literal(Literal::String(s)) => Pattern::Ctor(
+                Ctor::List(s.chars().count()),
+                s.chars().map(|c| Pattern::Ctor(Ctor::Char(c), Vec::new())).collect(),
+            ),
+            Pat::List(items) => Pattern::Ctor(Ctor::List(items.len()), items.iter().map(Self::from_binding).collect()),
+            Pat::ListFront(items, _) => Pattern::ListFront(items.iter().map(Self::from_binding).collect()),
+            Pat::Tuple(items) => Pattern::Ctor(Ctor::Tuple(items.len()), items.iter().map(Self::from_binding).collect()),
+            Pat::Record(fields) => {
+                let mut fields = fields.iter().collect::<Vec<_>>();
+                fields.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+                Pattern::Ctor(
+                    Ctor::Record(fields.iter().map(|(name, _)| **name).collect()),
+                    fields.iter().map(|(_, field)| Self::from_binding(field)).collect(),
+                )
+            },
+            Pat::Deconstruct(data, _, inner) => Pattern::Ctor(Ctor::Variant(data.0, data.1), vec![Self::from_binding(inner)]),
+            Pat::Range(Literal::Number(start), Literal::Number(end)) => Pattern::Ctor(Ctor::NumRange(*start, *end), Vec::new()),
+            Pat::Range(Literal::Char(start), Literal::Char(end)) => Pattern::Ctor(Ctor::CharRange(*start, *end), Vec::new()),
+            // Other ranges are rejected during type checking
+            Pat::Range(_, _) => Pattern::Wildcard,
+            Pat::Or(alts) => Pattern::Or(alts.iter().map(Self::from_binding).collect()),
+        }
+    }
+
+    fn describe(&self, data_ctx: &DataCtx) -> String {
+        let list = |items: &[Pattern]| items
+            .iter()
+            .map(|item| item.describe(data_ctx))
+            .collect::<Vec<_>>();
+        match self {
+            Pattern::Wildcard => format!("_"),
+            Pattern::Ctor(Ctor::Bool(x), _) => format!("{}", x),
+            Pattern::Ctor(Ctor::Num(x), _) => format!("{}", x),
+            Pattern::Ctor(Ctor::Char(c), _) => format!("'{}'", c),
+            Pattern::Ctor(Ctor::NumRange(start, end), _) => format!("{}..{}", start, end),
+            Pattern::Ctor(Ctor::CharRange(start, end), _) => format!("'{}'..'{}'", start, end),
+            Pattern::Ctor(Ctor::List(_), items) => format!("[{}]", list(items).join(", ")),
+            Pattern::Ctor(Ctor::ListFrom(_), items) | Pattern::ListFront(items) => {
+                let mut items = list(items);
+                items.push(format!("..."));
+                format!("[{}]", items.join(", "))
+            },
+            Pattern::Ctor(Ctor::Tuple(_), items) => format!("({})", list(items).join(", ")),
+            Pattern::Ctor(Ctor::Record(fields), items) => format!("{{ {} }}", fields
+                .iter()
+                .zip(list(items))
+                .map(|(name, item)| format!("{}: {}", **name, item))
+                .collect::<Vec<_>>()
+                .join(", ")),
+            Pattern::Ctor(Ctor::Variant(data, variant), inner) => {
+                let (name, ty) = &data_ctx.get_data(*data).variants[*variant];
+                match &inner[0] {
+                    // Variants without a payload are written alone
+                    Pattern::Wildcard if matches!(&**ty, Type::Tuple(items) if items.len() == 0) => format!("{}", **name),
+                    Pattern::Ctor(Ctor::Tuple(0), _) => format!("{}", **name),
+                    Pattern::Ctor(Ctor::Variant(_, _), _) => format!("{} ({})", **name, inner[0].describe(data_ctx)),
+                    inner => format!("{} {}", **name, inner.describe(data_ctx)),
+                }
+            },
+            Pattern::Or(alts) => list(alts).join(" | "),
+        }
+    }
+}
+
+type Row = Vec<Pattern>;
+
+// Specialise a row for values built with the given constructor, replacing its first pattern with the patterns of the
+// constructor's fields. Rows that cannot match such values are removed.
+fn specialize(ctor: &Ctor, row: &[Pattern]) -> Option<Row> {
+    let (head, tail) = row.split_first()?;
+    let mut fields = match head {
+        Pattern::Wildcard => vec![Pattern::Wildcard; ctor.arity()],
+        Pattern::Ctor(head, fields) if head.covers(ctor) => fields.clone(),
+        Pattern::Ctor(_, _) => return None,
+        Pattern::ListFront(items) if items.len() <= ctor.arity() => items
+            .iter()
+            .cloned()
+            .chain(std::iter::repeat(Pattern::Wildcard).take(ctor.arity() - items.len()))
+            .collect(),
+        Pattern::ListFront(_) => return None,
+        Pattern::Or(_) => unreachable!("Or-patterns are expanded before rows are specialised"),
+    };
+    fields.extend(tail.iter().cloned());
+    Some(fields)
+}
+
+// Keep the rows that match any value in their first column, removing that column
+fn default(rows: &[Row]) -> Vec<Row> {
+    rows
+        .iter()
+        .filter(|row| matches!(row.first(), Some(Pattern::Wildcard)))
+        .map(|row| row[1..].to_vec())
+        .collect()
+}
+
+// Lists are split into every length up to the longest pattern, and all longer lengths. Longer lists are all matched
+// by the same rows, since only their first items are inspected.
+fn list_ctors<'a>(heads: impl Iterator<Item=&'a Pattern>, min: usize) -> Vec<Ctor> {
+    let longest = heads.fold(min, |longest, head| match head {
+        Pattern::Ctor(Ctor::List(len), _) => longest.max(len + 1),
+        Pattern::ListFront(items) => longest.max(items.len()),
+        _ => longest,
+    });
+    (min..longest)
+        .map(Ctor::List)
+        .chain(std::iter::once(Ctor::ListFrom(longest)))
+        .collect()
+}
+
+// Values of a range that, between them, are matched by every combination of the rows that match any value of it: each
+// literal and end of a range within it, along with values that lie between them
+fn range_points<'a>(range: &Ctor, heads: impl Iterator<Item=&'a Pattern>) -> Vec<Ctor> {
+    match range {
+        Ctor::NumRange(start, end) => {
+            let mut points = vec![*start, *end];
+            for head in heads {
+                match head {
+                    Pattern::Ctor(Ctor::Num(x), _) => points.push(*x),
+                    Pattern::Ctor(Ctor::NumRange(a, b), _) => points.extend(&[*a, *b]),
+                    _ => {},
+                }
+            }
+            points.retain(|x| start <= x && x <= end);
+            points.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            points.dedup();
+            let between = points
+                .windows(2)
+                .map(|w| (w[0] + w[1]) / 2.0)
+                .collect::<Vec<_>>();
+            points.extend(between);
+            points.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            points.into_iter().map(Ctor::Num).collect()
+        },
+        Ctor::CharRange(start, end) => {
+            // The characters on either side of each one are enough to reach every gap
+            let mut points = vec![*start as u32, *end as u32];
+            for head in heads {
+                match head {
+                    Pattern::Ctor(Ctor::Char(c), _) => points.extend(&[*c as u32, *c as u32 + 1, (*c as u32).saturating_sub(1)]),
+                    Pattern::Ctor(Ctor::CharRange(a, b), _) => points.extend(&[
+                        (*a as u32).saturating_sub(1),
+                        *a as u32,
+                        *b as u32,
+                        *b as u32 + 1,
+                    ]),
+                    _ => {},
+                }
+            }
+            points.retain(|c| (*start as u32) <= *c && *c <= (*end as u32));
+            points.sort();
+            points.dedup();
+            points.into_iter().filter_map(std::char::from_u32).map(Ctor::Char).collect()
+        },
+        ctor => vec![ctor.clone()],
+    }
+}
+
+// Rebuild the value described by a witness for specialised rows
+fn unspecialize(ctor: Ctor, mut witness: Row) -> Row {
+    let rest = witness.split_off(ctor.arity());
+    std::iter::once(Pattern::Ctor(ctor, witness))
+        .chain(rest)
+        .collect()
+}
+
+// Find a value that `vector` matches but none of the rows do, if there is one
+fn useful(data_ctx: &DataCtx, rows: &[Row], vector: &[Pattern]) -> Option<Row> {
+    let (head, tail) = match vector.split_first() {
+        Some(split) => split,
+        None => return if rows.len() == 0 { Some(Vec::new()) } else { None },
+    };
+
+    // A row starting with an or-pattern is the same as a row for each of its alternatives
+    if rows.iter().any(|row| matches!(row.first(), Some(Pattern::Or(_)))) {
+        let rows = rows
+            .iter()
+            .flat_map(|row| match row.split_first() {
+                Some((Pattern::Or(alts), rest)) => alts
+                    .iter()
+                    .map(|alt| std::iter::once(alt.clone()).chain(rest.iter().cloned()).collect::<Row>())
+                    .collect(),
+                _ => vec![row.clone()],
+            })
+            .collect::<Vec<_>>();
+        return useful(data_ctx, &rows, vector);
+    }
+
+    let useful_for = |ctor: Ctor, vector: Row| {
+        let rows = rows
+            .iter()
+            .filter_map(|row| specialize(&ctor, row))
+            .collect::<Vec<_>>();
+        useful(data_ctx, &rows, &vector).map(|witness| unspecialize(ctor, witness))
+    };
+
+    match head {
+        // A range is useful if any of its values is
+        Pattern::Ctor(range @ Ctor::NumRange(_, _), _) | Pattern::Ctor(range @ Ctor::CharRange(_, _), _) =>
+            range_points(range, rows.iter().filter_map(|row| row.first()))
+                .into_iter()
+                .find_map(|point| useful_for(point, tail.to_vec())),
+        Pattern::Ctor(ctor, _) => useful_for(ctor.clone(), specialize(ctor, vector)?),
+        Pattern::ListFront(items) => list_ctors(rows.iter().filter_map(|row| row.first()), items.len())
+            .into_iter()
+            .find_map(|ctor| {
+                let vector = specialize(&ctor, vector)?;
+                useful_for(ctor, vector)
+            }),
+        Pattern::Or(alts) => alts
+            .iter()
+            .find_map(|alt| {
+                let vector = std::iter::once(alt.clone()).chain(tail.iter().cloned()).collect::<Row>();
+                useful(data_ctx, rows, &vector)
+            }),
+        Pattern::Wildcard => {
+            let heads = rows
+                .iter()
+                .filter_map(|row| row.first())
+                .filter(|head| !matches!(head, Pattern::Wildcard))
+                .collect::<Vec<_>>();
+            let used = heads
+                .iter()
+                .filter_map(|head| match head {
+                    Pattern::Ctor(ctor, _) => Some(ctor),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+
+            // Every constructor of the type, if there are finitely many
+            let all = match heads.first() {
+                None => None,
+                Some(Pattern::ListFront(_)) | Some(Pattern::Ctor(Ctor::List(_), _)) | Some(Pattern::Ctor(Ctor::ListFrom(_), _)) =>
+                    Some(list_ctors(heads.iter().copied(), 0)),
+                Some(Pattern::Ctor(Ctor::Bool(_), _)) => Some(vec![Ctor::Bool(false), Ctor::Bool(true)]),
+                Some(Pattern::Ctor(Ctor::Variant(data, _), _)) => Some((0..data_ctx.get_data(*data).variants.len())
+                    .map(|variant| Ctor::Variant(*data, variant))
+                    .collect()),
+                Some(Pattern::Ctor(ctor @ Ctor::Tuple(_), _)) | Some(Pattern::Ctor(ctor @ Ctor::Record(_), _)) =>
+                    Some(vec![ctor.clone()]),
+                Some(Pattern::Ctor(Ctor::Num(_), _)) | Some(Pattern::Ctor(Ctor::Char(_), _))
+                    | Some(Pattern::Ctor(Ctor::NumRange(_, _), _)) | Some(Pattern::Ctor(Ctor::CharRange(_, _), _)) => None,
+                Some(Pattern::Or(_)) => unreachable!("Or-patterns are expanded first"),
+            };
+
+            let is_list = matches!(all.as_ref().and_then(|all| all.last()), Some(Ctor::ListFrom(_)));
+            let missing = all
+                .as_ref()
+                .and_then(|all| all.iter().find(|ctor| !is_list && !used.contains(ctor)).cloned());
+            match all {
+                // Every constructor appears, so the wildcard is useful if it is useful for any of them
+                Some(all) if missing.is_none() => all
+                    .into_iter()
+                    .find_map(|ctor| {
+                        let vector = specialize(&ctor, vector)?;
+                        useful_for(ctor, vector)
+                    }),
+                // Otherwise, the wildcard is useful if it is useful for the rows that match anything
+                _ => {
+                    let witness = useful(data_ctx, &default(rows), tail)?;
+                    let head = match missing {
+                        Some(ctor) => Pattern::Ctor(ctor.clone(), vec![Pattern::Wildcard; ctor.arity()]),
+                        None => unused_literal(&used),
+                    };
+                    Some(std::iter::once(head).chain(witness).collect())
+                },
+            }
+        },
+    }
+}
+
+// Find a number or character that none of the constructors cover, to use as an example
+fn unused_literal(used: &[&Ctor]) -> Pattern {
+    match used.first() {
+        Some(Ctor::Num(_)) | Some(Ctor::NumRange(_, _)) => (0..)
+            .map(|n| Ctor::Num(n as f64))
+            .find(|ctor| !used.iter().any(|used| used.covers(ctor)))
+            .map_or(Pattern::Wildcard, |ctor| Pattern::Ctor(ctor, Vec::new())),
+        Some(Ctor::Char(_)) | Some(Ctor::CharRange(_, _)) => ('a'..='z')
+            .chain('A'..='Z')
+            .chain('0'..='9')
+            .map(Ctor::Char)
+            .find(|ctor| !used.iter().any(|used| used.covers(ctor)))
+            .map_or(Pattern::Wildcard, |ctor| Pattern::Ctor(ctor, Vec::new())),
+        _ => Pattern::Wildcard,
+    }
+}
+
+// Find a value that none of the arms match, if there is one
+pub(super) fn missing_case<'a>(data_ctx: &DataCtx, arms: impl Iterator<Item=&'a TypeBinding>) -> Option<String> {
+    let rows = arms
+        .map(|arm| vec![Pattern::from_binding(arm)])
+        .collect::<Vec<_>>();
+    useful(data_ctx, &rows, &[Pattern::Wildcard])
+        .map(|witness| witness[0].describe(data_ctx))
+}
+
+// Determine whether an arm matches any value that the arms before it do not
+pub(super) fn is_reachable<'a>(data_ctx: &DataCtx, before: impl Iterator<Item=&'a TypeBinding>, arm: &TypeBinding) -> bool {
+    let rows = before
+        .map(|arm| vec![Pattern::from_binding(arm)])
+        .collect::<Vec<_>>();
+    useful(data_ctx, &rows, &[Pattern::from_binding(arm)]).is_some()
+}
+
+#[test]
+fn missing_cases() {
+    let missing = |code: &str| {
+        let (srcs, module) = crate::embed::compile_main(code);
+        module
+            .err()
+            .and_then(|errors| errors
+                .iter()
+                .filter(|error| error.code() == Some("E0400"))
+                .flat_map(|error| error.diagnostic(&srcs).hints)
+                .next())
+    };
+
+    assert_eq!(missing("fn f |x of (Maybe Num, [Num])| match x {\n\t| (Just _, [_, ...]) => 0\n\t| (None, _) => 1\n\t| (_, []) => 2\n}\ndef main = f((None, []))\n"), None);
+    assert_eq!(
+        missing("fn f |x of (Maybe Num, [Num])| match x {\n\t| (Just _, [_, ...]) => 0\n\t| (None, _) => 1\n}\ndef main = f((None, []))\n"),
+        Some(format!("Case '(Just _, [])' is not handled")),
+    );
+    assert_eq!(
+        missing("fn f |x of { a: Bool, b: Bool }| match x {\n\t| { a: true, b } => b\n\t| { a, b: true } => a\n}\ndef main = f({ a: true, b: true })\n"),
+        Some(format!("Case '{{ a: false, b: false }}' is not handled")),
+    );
+    assert_eq!(
+        missing("fn f |x of [Bool]| match x {\n\t| [] => 0\n\t| [true, ...] => 1\n\t| [_, _] => 2\n}\ndef main = f([])\n"),
+        Some(format!("Case '[false]' is not handled")),
+    );
+    assert_eq!(
+        missing("fn f |x of Num| match x {\n\t| 0 => 0\n\t| 1 => 1\n}\ndef main = f(0)\n"),
+        Some(format!("Case '2' is not handled")),
+    );
+    // Guarded arms might not match
+    assert_eq!(
+        missing("fn f |x of Bool| match x {\n\t| true => 0\n\t| false if x = x => 1\n}\ndef main = f(true)\n"),
+        Some(format!("Case 'false' is not handled")),
+    );
+    // Or-patterns handle the cases of each alternative, and ranges those of every value in them
+    assert_eq!(
+        missing("fn f |x of (Bool, Bool)| match x {\n\t| (true, _) | (_, true) => 0\n}\ndef main = f((true, true))\n"),
+        Some(format!("Case '(false, false)' is not handled")),
+    );
+    assert_eq!(
+        missing("fn f |x of Num| match x {\n\t| 0..5 | 6 => 0\n\t| 7..10 => 1\n}\ndef main = f(0)\n"),
+        Some(format!("Case '11' is not handled")),
+    );
+    // Strings are lists of characters, so string patterns are refutable
+    assert_eq!(
+        missing("fn f |x of [Char]| match x {\n\t| \"a\" ++ _ => 0\n\t| \"\" => 1\n}\ndef main = f(\"a\")\n"),
+        Some(format!("Case '['b', ...]' is not handled")),
+    );
+    assert_eq!(missing("fn f |x of (Bool, Char)| match x {\n\t| (true, _) | (false, 'a'..'z') => 0\n\t| (false, _) => 1\n}\ndef main = f((true, 'a'))\n"), None);
+}
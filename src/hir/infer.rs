@@ -4,13 +4,16 @@ use std::{
 };
 use internment::LocalIntern;
 use crate::{
-    error::Error,
+    error::{Error, closest},
     src::Span,
     node::SrcNode,
     ast::{UnaryOp, BinaryOp},
     ty::{Type, Primitive},
 };
-use super::data::{DataCtx, DataId};
+use super::{
+    data::{DataCtx, DataId},
+    class::ClassCtx,
+};
 
 type Ident = LocalIntern<String>;
 
@@ -48,11 +51,17 @@ pub enum Constraint {
         record: TypeId,
         field: SrcNode<Ident>,
     },
+    // The type must be an instance of the class
+    Class {
+        class: SrcNode<Ident>,
+        ty: TypeId,
+    },
 }
 
 #[derive(Debug)]
 pub struct InferCtx<'a> {
     data_ctx: &'a DataCtx,
+    class_ctx: Option<&'a ClassCtx>,
     parent: Option<&'a Self>,
 
     id_counter: TypeId,
@@ -62,15 +71,21 @@ pub struct InferCtx<'a> {
     // Generics that are valid in this InferCtx scope
     // TODO: Should this be going in here?
     generics: HashMap<Ident, TypeId>,
+    // Generics that are known to be instances of classes
+    givens: Vec<(Ident, Ident)>,
 
     constraint_id_counter: ConstraintId,
     constraints: HashMap<ConstraintId, Constraint>,
+
+    // Used to name type variables that get generalised
+    generic_name_counter: usize,
 }
 
 impl<'a> InferCtx<'a> {
     pub fn from_data_ctx(data_ctx: &'a DataCtx) -> InferCtx<'a> {
         InferCtx {
             data_ctx,
+            class_ctx: None,
             parent: None,
 
             id_counter: 0,
@@ -78,15 +93,19 @@ impl<'a> InferCtx<'a> {
             spans: HashMap::default(),
 
             generics: HashMap::default(),
+            givens: Vec::new(),
 
             constraint_id_counter: 0,
             constraints: HashMap::default(),
+
+            generic_name_counter: 0,
         }
     }
 
     pub fn scoped(&self) -> InferCtx {
         InferCtx {
             data_ctx: self.data_ctx,
+            class_ctx: self.class_ctx,
             parent: Some(self),
 
             id_counter: self.id_counter,
@@ -94,9 +113,19 @@ impl<'a> InferCtx<'a> {
             spans: HashMap::default(),
 
             generics: HashMap::default(),
+            givens: Vec::new(),
 
             constraint_id_counter: self.constraint_id_counter,
             constraints: HashMap::default(),
+
+            generic_name_counter: self.generic_name_counter,
+        }
+    }
+
+    pub fn from_ctxs(data_ctx: &'a DataCtx, class_ctx: &'a ClassCtx) -> InferCtx<'a> {
+        InferCtx {
+            class_ctx: Some(class_ctx),
+            ..Self::from_data_ctx(data_ctx)
         }
     }
 
@@ -109,6 +138,10 @@ impl<'a> InferCtx<'a> {
         self.data_ctx
     }
 
+    pub fn class_ctx(&self) -> Option<&'a ClassCtx> {
+        self.class_ctx
+    }
+
     pub fn get(&self, id: TypeId) -> TypeInfo {
         self.types
             .get(&id)
@@ -147,6 +180,142 @@ impl<'a> InferCtx<'a> {
             .or_else(|| self.parent.and_then(|p| p.generic(name)))
     }
 
+    pub fn add_given(&mut self, class: Ident, generic: Ident) {
+        if !self.givens.contains(&(class, generic)) {
+            self.givens.push((class, generic));
+        }
+    }
+
+    fn is_given(&self, class: Ident, generic: Ident) -> bool {
+        self.givens.contains(&(class, generic))
+            || self.parent.map(|p| p.is_given(class, generic)).unwrap_or(false)
+    }
+
+    // Find the generic parameters that unsolved class constraints apply to
+    pub fn constrained_generics(&self) -> Vec<(Ident, Ident)> {
+        self.constraints
+            .values()
+            .filter_map(|constraint| match constraint {
+                Constraint::Class { class, ty } => match self.get(self.get_base(*ty)) {
+                    TypeInfo::GenParam(name) => Some((**class, name)),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Generalisation
+
+    // Find type variables that have not yet been inferred within the given type
+    pub fn free_vars(&self, id: TypeId, vars: &mut Vec<TypeId>) {
+        self.free_vars_inner(0, id, vars)
+    }
+
+    fn free_vars_inner(&self, iter: usize, id: TypeId, vars: &mut Vec<TypeId>) {
+        const MAX_FREE_VAR_DEPTH: usize = 1024;
+        if iter > MAX_FREE_VAR_DEPTH {
+            return; // Recursive types get reported during reconstruction
+        }
+
+        match self.get(id) {
+            TypeInfo::Unknown(_) => if !vars.contains(&id) {
+                vars.push(id);
+            },
+            TypeInfo::Ref(id) => self.free_vars_inner(iter + 1, id, vars),
+            TypeInfo::Primitive(_) | TypeInfo::GenParam(_) => {},
+            TypeInfo::List(item) => self.free_vars_inner(iter + 1, item, vars),
+            TypeInfo::Tuple(items) => items
+                .into_iter()
+                .for_each(|item| self.free_vars_inner(iter + 1, item, vars)),
+            TypeInfo::Record(fields) => fields
+                .into_iter()
+                .for_each(|(_, field)| self.free_vars_inner(iter + 1, field, vars)),
+            TypeInfo::Func(i, o) => {
+                self.free_vars_inner(iter + 1, i, vars);
+                self.free_vars_inner(iter + 1, o, vars);
+            },
+            TypeInfo::Data(_, params) => params
+                .into_iter()
+                .for_each(|param| self.free_vars_inner(iter + 1, param, vars)),
+        }
+    }
+
+    // Find type variables mentioned by constraints that have not yet been solved
+    pub fn constrained_vars(&self, vars: &mut Vec<TypeId>) {
+        for constraint in self.constraints.values() {
+            let ids = match constraint {
+                Constraint::Unary { out, a, .. } => vec![*out, *a],
+                Constraint::Binary { out, a, b, .. } => vec![*out, *a, *b],
+                Constraint::Access { out, record, .. } => vec![*out, *record],
+                Constraint::Class { ty, .. } => vec![*ty],
+            };
+            ids
+                .into_iter()
+                .for_each(|id| self.free_vars(id, vars));
+        }
+    }
+
+    // Generate a name for a type parameter that cannot conflict with user-written type parameters
+    pub fn fresh_generic_name(&mut self) -> Ident {
+        let n = self.generic_name_counter;
+        self.generic_name_counter += 1;
+        let letter = (b'a' + (n % 26) as u8) as char;
+        if n < 26 {
+            Ident::new(format!("'{}", letter))
+        } else {
+            Ident::new(format!("'{}{}", letter, n / 26))
+        }
+    }
+
+    // Turn a free type variable into a type parameter
+    pub fn generalise(&mut self, var: TypeId, name: Ident) {
+        self.types.insert(var, TypeInfo::GenParam(name));
+    }
+
+    // Create a copy of a generalised type with fresh type variables in place of its type parameters
+    pub fn instantiate_generalised(&mut self, id: TypeId, params: &[Ident], span: Span) -> TypeId {
+        let vars = params
+            .iter()
+            .map(|name| (*name, self.insert(TypeInfo::Unknown(Some(*name)), span)))
+            .collect::<HashMap<_, _>>();
+        self.copy_with_params(0, id, &vars)
+    }
+
+    fn copy_with_params(&mut self, iter: usize, id: TypeId, vars: &HashMap<Ident, TypeId>) -> TypeId {
+        const MAX_COPY_DEPTH: usize = 1024;
+        if iter > MAX_COPY_DEPTH {
+            return id; // Recursive types get reported during reconstruction
+        }
+
+        let info = match self.get(id) {
+            TypeInfo::Ref(id) => return self.copy_with_params(iter + 1, id, vars),
+            TypeInfo::GenParam(name) if vars.contains_key(&name) => return vars[&name],
+            // Types without parameters can be shared
+            TypeInfo::Unknown(_) | TypeInfo::Primitive(_) | TypeInfo::GenParam(_) => return id,
+            TypeInfo::List(item) => TypeInfo::List(self.copy_with_params(iter + 1, item, vars)),
+            TypeInfo::Tuple(items) => TypeInfo::Tuple(items
+                .into_iter()
+                .map(|item| self.copy_with_params(iter + 1, item, vars))
+                .collect()),
+            TypeInfo::Record(fields) => TypeInfo::Record(fields
+                .into_iter()
+                .map(|(name, field)| (name, self.copy_with_params(iter + 1, field, vars)))
+                .collect()),
+            TypeInfo::Func(i, o) => TypeInfo::Func(
+                self.copy_with_params(iter + 1, i, vars),
+                self.copy_with_params(iter + 1, o, vars),
+            ),
+            TypeInfo::Data(data, params) => TypeInfo::Data(data, params
+                .into_iter()
+                .map(|param| self.copy_with_params(iter + 1, param, vars))
+                .collect()),
+        };
+
+        let span = self.span(id);
+        self.insert(info, span)
+    }
+
     // Return true if linking inferred new information
     fn link(&mut self, a: TypeId, b: TypeId) {
         if self.get_base(a) != self.get_base(b) {
@@ -273,6 +442,7 @@ impl<'a> InferCtx<'a> {
                 self.display_type_info(x),
                 self.display_type_info(y),
             ))
+                .with_code("E0200")
                 .with_span(x_span)
                 .with_span(y_span);
 
@@ -385,6 +555,7 @@ impl<'a> InferCtx<'a> {
                         self.display_type_info(a),
                         self.display_type_info(out),
                     ))
+                        .with_code("E0201")
                         .with_span(op.span())
                         .with_span(self.span(a)))
                 } else if matches.len() > 1 {
@@ -403,8 +574,30 @@ impl<'a> InferCtx<'a> {
                     Ok(true)
                 }
             },
+            // Comparisons are structural, so both sides must share a type that supports comparison
+            Constraint::Binary { out, op, a, b } if [
+                BinaryOp::Eq, BinaryOp::NotEq, BinaryOp::Less, BinaryOp::More, BinaryOp::LessEq, BinaryOp::MoreEq,
+            ].contains(&*op) => {
+                let boolean = self.insert(TypeInfo::Primitive(Primitive::Boolean), op.span());
+                self.unify(out, boolean)?;
+                self.unify(a, b)?;
+
+                match self.is_comparable(a, &mut Vec::new()) {
+                    Ok(is_known) => Ok(is_known),
+                    Err(ty) => Err(Error::custom(format!(
+                        "Cannot compare values of type '{}' with {}",
+                        self.display_type_info(a),
+                        *op,
+                    ))
+                        .with_code("E0201")
+                        .with_span(op.span())
+                        .with_span(self.span(a))
+                        .with_secondary_span(self.span(ty))
+                        .with_hint(format!("Values of type '{}' have no structural comparison", self.display_type_info(ty)))),
+                }
+            },
             Constraint::Binary { out, op, a, b } => {
-                let matchers: [fn(_, _, _, _, _) -> Option<fn(_, _, _) -> _>; 5] = [
+                let matchers: [fn(_, _, _, _, _) -> Option<fn(_, _, _) -> _>; 3] = [
                     // Int op Int => Int
                     |this: &Self, out, op, a, b| {
                         let mut this = this.scoped();
@@ -421,29 +614,13 @@ impl<'a> InferCtx<'a> {
                             None
                         }
                     },
-                    // Int op Int => Bool
-                    |this: &Self, out, op, a, b| {
-                        let mut this = this.scoped();
-                        let num = this.insert(TypeInfo::Primitive(Primitive::Number), Span::none());
-                        let boolean = this.insert(TypeInfo::Primitive(Primitive::Boolean), Span::none());
-                        if
-                            this.unify(boolean, out).is_ok()
-                            && [BinaryOp::Eq, BinaryOp::NotEq, BinaryOp::Less, BinaryOp::More, BinaryOp::LessEq, BinaryOp::MoreEq].contains(&op)
-                            && this.unify(num, a).is_ok()
-                            && this.unify(num, b).is_ok()
-                        {
-                            Some(|this: &mut Self, a, b| (TypeInfo::Primitive(Primitive::Boolean), TypeInfo::Primitive(Primitive::Number), TypeInfo::Primitive(Primitive::Number)))
-                        } else {
-                            None
-                        }
-                    },
                     // Bool op Bool => Bool
                     |this: &Self, out, op, a, b| {
                         let mut this = this.scoped();
                         let boolean = this.insert(TypeInfo::Primitive(Primitive::Boolean), Span::none());
                         if
                             this.unify(boolean, out).is_ok()
-                            && [BinaryOp::Eq, BinaryOp::NotEq, BinaryOp::And, BinaryOp::Or].contains(&op)
+                            && [BinaryOp::And, BinaryOp::Or].contains(&op)
                             && this.unify(boolean, a).is_ok()
                             && this.unify(boolean, b).is_ok()
                         {
@@ -452,22 +629,6 @@ impl<'a> InferCtx<'a> {
                             None
                         }
                     },
-                    // Char op Char => Bool
-                    |this: &Self, out, op, a, b| {
-                        let mut this = this.scoped();
-                        let boolean = this.insert(TypeInfo::Primitive(Primitive::Boolean), Span::none());
-                        let character = this.insert(TypeInfo::Primitive(Primitive::Char), Span::none());
-                        if
-                            this.unify(boolean, out).is_ok()
-                            && [BinaryOp::Eq, BinaryOp::NotEq].contains(&op)
-                            && this.unify(character, a).is_ok()
-                            && this.unify(character, b).is_ok()
-                        {
-                            Some(|this: &mut Self, a, b| (TypeInfo::Primitive(Primitive::Boolean), TypeInfo::Primitive(Primitive::Char), TypeInfo::Primitive(Primitive::Char)))
-                        } else {
-                            None
-                        }
-                    },
                     // [A] ++ [A] => [A]
                     |this: &Self, out, op, a, b| {
                         let mut this = this.scoped();
@@ -506,6 +667,7 @@ impl<'a> InferCtx<'a> {
                         self.display_type_info(b),
                         self.display_type_info(out),
                     ))
+                        .with_code("E0201")
                         .with_span(op.span())
                         .with_span(self.span(a))
                         .with_span(self.span(b)))
@@ -548,6 +710,7 @@ impl<'a> InferCtx<'a> {
                                     "Field access is not supported on inner type '{}'",
                                     self.display_type_info(ty_id),
                                 ))
+                                    .with_code("E0202")
                                     .with_span(field.span())
                                     .with_secondary_span(self.span(ty_id))
                                     .with_span(self.span(record))),
@@ -557,6 +720,7 @@ impl<'a> InferCtx<'a> {
                                 "Field access is not supported on datatype with many variants '{}'",
                                 self.display_type_info(record),
                             ))
+                                .with_code("E0202")
                                 .with_span(field.span())
                                 .with_span(self.span(record))
                                 .with_secondary_span(self.span(self.get_base(record))))
@@ -566,6 +730,7 @@ impl<'a> InferCtx<'a> {
                         "Type '{}' does not support field access",
                         self.display_type_info(record),
                     ))
+                        .with_code("E0202")
                         .with_span(field.span())
                         .with_span(self.span(record))),
                 }?;
@@ -578,30 +743,130 @@ impl<'a> InferCtx<'a> {
                         self.unify(out, *ty)?;
                         Ok(true)
                     } else {
-                        Err(Error::custom(format!(
+                        let err = Error::custom(format!(
                             "No such field '{}' in record '{}'",
                             **field,
                             self.display_type_info(record),
                         ))
+                            .with_code("E0202")
                             .with_span(field.span())
-                            .with_span(self.span(record)))
+                            .with_span(self.span(record));
+                        // The field may have been misspelled
+                        Err(match closest(field.as_str(), fields.iter().map(|(name, _)| name.as_str())) {
+                            Some(name) => err.with_suggestion(field.span(), name.to_string(), format!("A field with a similar name exists")),
+                            None => err,
+                        })
                     }
                 } else {
                     Ok(false)
                 }
             },
+            Constraint::Class { class, ty } => match self.get(self.get_base(ty)) {
+                TypeInfo::Unknown(_) => Ok(false),
+                TypeInfo::GenParam(name) => if self.is_given(*class, name) {
+                    Ok(true)
+                } else {
+                    Err(Error::custom(format!("Type parameter '{}' is not known to be an instance of class '{}'", name, *class))
+                        .with_code("E0300")
+                        .with_span(class.span())
+                        .with_span(self.span(ty))
+                        .with_hint(format!("Add 'given {} {}' to the definition", *class, name)))
+                },
+                info => {
+                    let instance = match self.class_ctx {
+                        Some(class_ctx) => class_ctx
+                            .instances_of(*class)
+                            .find(|instance| Self::head_matches(&info, &instance.ty)),
+                        None => None,
+                    };
+
+                    if let Some(instance) = instance {
+                        let (inst_ty, params) = self.instantiate_ty(&instance.generics, &instance.ty, class.span());
+                        self.unify(ty, inst_ty)?;
+                        // The instance may only hold if its parameters are instances of other classes
+                        for (given_class, param) in instance.given.iter() {
+                            let param_ty = params
+                                .iter()
+                                .find(|(name, _)| **name == **param)
+                                .unwrap()
+                                .1;
+                            self.add_constraint(Constraint::Class {
+                                class: SrcNode::new(**given_class, class.span()),
+                                ty: param_ty,
+                            });
+                        }
+                        Ok(true)
+                    } else {
+                        Err(Error::custom(format!(
+                            "Type '{}' is not an instance of class '{}'",
+                            self.display_type_info(ty),
+                            *class,
+                        ))
+                            .with_code("E0300")
+                            .with_span(class.span())
+                            .with_span(self.span(ty)))
+                    }
+                },
+            },
         }
     }
 
-    pub fn solve_all(&mut self) -> Result<(), Error> {
+    // Determine whether values of a type can be compared structurally. `Ok(false)` means that this is not yet known,
+    // and `Err` gives the part of the type that cannot be compared.
+    fn is_comparable(&mut self, ty: TypeId, data_seen: &mut Vec<DataId>) -> Result<bool, TypeId> {
+        match self.get(self.get_base(ty)) {
+            TypeInfo::Unknown(_) => Ok(false),
+            TypeInfo::Ref(ty) => self.is_comparable(ty, data_seen),
+            TypeInfo::Primitive(Primitive::Universe) => Err(ty),
+            TypeInfo::Primitive(_) => Ok(true),
+            TypeInfo::Func(_, _) | TypeInfo::GenParam(_) => Err(ty),
+            TypeInfo::List(item) => self.is_comparable(item, data_seen),
+            TypeInfo::Tuple(items) => items
+                .into_iter()
+                .try_fold(true, |is_known, item| Ok(self.is_comparable(item, data_seen)? && is_known)),
+            TypeInfo::Record(fields) => fields
+                .into_iter()
+                .try_fold(true, |is_known, (_, field)| Ok(self.is_comparable(field, data_seen)? && is_known)),
+            // Recursive data types are comparable if the rest of their variants are
+            TypeInfo::Data(data, _) if data_seen.contains(&data) => Ok(true),
+            TypeInfo::Data(data_id, params) => {
+                data_seen.push(data_id);
+                let data = self.data_ctx.get_data(data_id);
+                data.variants
+                    .iter()
+                    .try_fold(true, |is_known, (_, variant)| {
+                        let variant = self.instantiate_ty_inner(&|name| data.generics
+                            .iter()
+                            .zip(params.iter())
+                            .find(|(gen, _)| ***gen == name)
+                            .map(|(_, param_ty)| *param_ty), variant);
+                        Ok(self.is_comparable(variant, data_seen)? && is_known)
+                    })
+            },
+        }
+    }
+
+    // Determine whether a type has the same outermost type constructor as an instance's type
+    fn head_matches(info: &TypeInfo, ty: &Type) -> bool {
+        match (info, ty) {
+            (TypeInfo::Primitive(a), Type::Primitive(b)) => a == b,
+            (TypeInfo::List(_), Type::List(_)) => true,
+            (TypeInfo::Tuple(a), Type::Tuple(b)) => a.len() == b.len(),
+            (TypeInfo::Record(a), Type::Record(b)) => a.len() == b.len() && a
+                .iter()
+                .zip(b.iter())
+                .all(|((a, _), (b, _))| **a == **b),
+            (TypeInfo::Func(_, _), Type::Func(_, _)) => true,
+            (TypeInfo::Data(a, _), Type::Data(b, _)) => *a == **b,
+            _ => false,
+        }
+    }
+
+    // Solve as many constraints as possible, leaving those that need more type information
+    pub fn solve_some(&mut self) -> Result<(), Error> {
         'solver: loop {
             let constraints = self.constraints.keys().copied().collect::<Vec<_>>();
 
-            // All constraints have been resolved
-            if constraints.len() == 0 {
-                break Ok(());
-            }
-
             for c in constraints {
                 if self.solve_inner(self.constraints[&c].clone())? {
                     self.constraints.remove(&c);
@@ -609,7 +874,22 @@ impl<'a> InferCtx<'a> {
                 }
             }
 
-            break Err(Error::custom(format!("{:?}", self.constraints.values().next())));
+            break Ok(());
+        }
+    }
+
+    pub fn solve_all(&mut self) -> Result<(), Error> {
+        self.solve_some()?;
+
+        // All constraints have been resolved
+        match self.constraints.values().next() {
+            None => Ok(()),
+            Some(Constraint::Class { class, ty }) => Err(Error::custom(format!("Cannot infer which instance of class '{}' to use", **class))
+                .with_code("E0302")
+                .with_span(class.span())
+                .with_secondary_span(self.span(*ty))
+                .with_hint(format!("Specify all missing types"))),
+            Some(constraint) => Err(Error::custom(format!("{:?}", constraint)).with_code("E0204")),
         }
     }
 
@@ -653,6 +933,7 @@ impl<'a> InferCtx<'a> {
     pub fn reconstruct(&self, id: TypeId, span: Span) -> Result<SrcNode<Type>, Error> {
         self.reconstruct_inner(0, id).map_err(|err| match err {
             ReconstructError::Recursive => Error::custom(format!("Recursive type"))
+                .with_code("E0205")
                 .with_span(self.span(id)),
             ReconstructError::Unknown(a) => {
                 let msg = match self.get(self.get_base(id)) {
@@ -660,6 +941,7 @@ impl<'a> InferCtx<'a> {
                     _ => format!("Cannot infer type '{}' in '{}'", self.display_type_info(a), self.display_type_info(id)),
                 };
                 Error::custom(msg)
+                    .with_code("E0204")
                     .with_span(span)
                     .with_secondary_span(self.span(id))
                     .with_hint(format!("Specify all missing types"))
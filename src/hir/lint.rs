@@ -0,0 +1,208 @@
+use std::collections::HashSet;
+use crate::error::Error;
+use super::{Program, Expr, TypeExpr, TypeBinding, Ident, exhaustive};
+
+impl Program {
+    // Find code that is valid but probably a mistake. Names beginning with `_` are never warned about.
+    pub fn warnings(&self) -> Vec<Error> {
+        // The prelude is not the user's to fix
+        let prelude = self.root
+            .defs()
+            .filter(|def| def.name.starts_with("prelude::"))
+            .filter_map(|def| def.name.span().file())
+            .collect::<HashSet<_>>();
+        let mut defs = self.root
+            .defs()
+            .filter(|def| def.name.span().file().map_or(true, |file| !prelude.contains(&file)))
+            .collect::<Vec<_>>();
+        defs.sort_by_key(|def| def.name.span().bounds());
+
+        let mut warnings = Vec::new();
+        for def in defs.iter() {
+            self.lint_expr(&def.body, &mut Vec::new(), &mut warnings);
+        }
+        warnings.append(&mut self.unused_defs(&defs.iter().map(|def| *def.name).collect::<Vec<_>>()));
+        warnings
+    }
+
+    fn lint_expr(&self, expr: &TypeExpr, scope: &mut Vec<Ident>, warnings: &mut Vec<Error>) {
+        match &**expr {
+            Expr::Literal(_) | Expr::Local(_) | Expr::Global(_, _) | Expr::Member(_, _, _) => {},
+            Expr::Intrinsic(_, _, args) => args
+                .iter()
+                .for_each(|arg| self.lint_expr(arg, scope, warnings)),
+            Expr::Unary(_, x) | Expr::Access(x, _) | Expr::Constructor(_, _, x) => self.lint_expr(x, scope, warnings),
+            Expr::Binary(_, x, y) | Expr::Apply(x, y) | Expr::Update(x, _, y) => {
+                self.lint_expr(x, scope, warnings);
+                self.lint_expr(y, scope, warnings);
+            },
+            Expr::List(items) | Expr::Tuple(items) => items
+                .iter()
+                .for_each(|item| self.lint_expr(item, scope, warnings)),
+            Expr::Record(fields) => fields
+                .iter()
+                .for_each(|(_, value)| self.lint_expr(value, scope, warnings)),
+            Expr::Func(param, body) => self.lint_bindings(param, None, body, scope, warnings),
+            Expr::Match(pred, arms) => {
+                self.lint_expr(pred, scope, warnings);
+
+                // Arms that only match values already matched by earlier arms can never be reached. Guarded arms might
+                // not match, so they never make later arms unreachable.
+                for (idx, (binding, _, _)) in arms.iter().enumerate().skip(1) {
+                    let earlier = arms[..idx]
+                        .iter()
+                        .filter(|(_, guard, _)| guard.is_none())
+                        .map(|(binding, _, _)| binding);
+                    if !exhaustive::is_reachable(&self.data_ctx, earlier, binding) {
+                        warnings.push(Error::warning(format!("Match arm is unreachable"))
+                            .with_code("W0003")
+                            .with_span(binding.span())
+                            .with_hint(format!("Earlier arms already handle every case it matches")));
+                    }
+                }
+
+                for (binding, guard, body) in arms.iter() {
+                    self.lint_bindings(binding, guard.as_ref(), body, scope, warnings);
+                }
+            },
+        }
+    }
+
+    // Check the names bound by a pattern, which are in scope for `guard` and `body`
+    fn lint_bindings(
+        &self,
+        binding: &TypeBinding,
+        guard: Option<&TypeExpr>,
+        body: &TypeExpr,
+        scope: &mut Vec<Ident>,
+        warnings: &mut Vec<Error>,
+    ) {
+        let mut names = binding
+            .binding_idents()
+            .into_iter()
+            .map(|(name, (span, _))| (name, *span))
+            .collect::<Vec<_>>();
+        names.sort_by_key(|(_, span)| span.bounds());
+
+        for (name, span) in names.iter().filter(|(name, _)| !name.starts_with('_')) {
+            if scope.contains(name) {
+                warnings.push(Error::warning(format!("Binding '{}' shadows an existing binding", **name))
+                    .with_code("W0004")
+                    .with_span(*span)
+                    .with_hint(format!("Rename it, or prefix it with '_' if this is intended")));
+            }
+            let used = guard
+                .into_iter()
+                .chain(std::iter::once(body))
+                .any(|expr| uses_local(expr, *name));
+            if !used {
+                warnings.push(Error::warning(format!("Binding '{}' is never used", **name))
+                    .with_code("W0001")
+                    .with_span(*span)
+                    .with_hint(format!("Prefix it with '_' if this is intended")));
+            }
+        }
+
+        let len = scope.len();
+        scope.extend(names.into_iter().map(|(name, _)| name));
+        if let Some(guard) = guard {
+            self.lint_expr(guard, scope, warnings);
+        }
+        self.lint_expr(body, scope, warnings);
+        scope.truncate(len);
+    }
+
+    // Find the given definitions that cannot be reached from `main` or from tests. Instance members may be chosen by
+    // type, so everything they use is assumed to be reachable.
+    fn unused_defs(&self, names: &[Ident]) -> Vec<Error> {
+        let base_name = |name: &Ident| name.rsplit("::").next().unwrap_or("").to_string();
+        if self.root.def(Ident::new("main".to_string())).is_none() {
+            return Vec::new();
+        }
+
+        let mut stack = self.root
+            .defs()
+            .map(|def| *def.name)
+            .filter(|name| name.as_str() == "main" || base_name(name).starts_with("test_") || name.contains('#'))
+            .collect::<Vec<_>>();
+        let mut used = HashSet::new();
+        while let Some(name) = stack.pop() {
+            if used.insert(name) {
+                if let Some(def) = self.root.def(name) {
+                    stack.extend(def.body
+                        .visit()
+                        .filter_map(|expr| match &**expr {
+                            Expr::Global(name, _) => Some(*name),
+                            _ => None,
+                        }));
+                }
+            }
+        }
+
+        names
+            .iter()
+            .filter(|name| !used.contains(*name) && !base_name(*name).starts_with('_'))
+            .filter_map(|name| self.root.def(*name))
+            .map(|def| Error::warning(format!("Definition '{}' is never used", *def.name))
+                .with_code("W0002")
+                .with_span(def.name.span())
+                .with_hint(format!("It cannot be reached from 'main'")))
+            .collect()
+    }
+}
+
+// Whether the expression refers to the local `name`. Inner bindings of the same name shadow it, so uses of those don't
+// count.
+fn uses_local(expr: &TypeExpr, name: Ident) -> bool {
+    let binds = |binding: &TypeBinding| binding.binding_idents().contains_key(&name);
+    match &**expr {
+        Expr::Local(local) => *local == name,
+        Expr::Literal(_) | Expr::Global(_, _) | Expr::Member(_, _, _) => false,
+        Expr::Intrinsic(_, _, args) => args
+            .iter()
+            .any(|arg| uses_local(arg, name)),
+        Expr::Unary(_, x) | Expr::Access(x, _) | Expr::Constructor(_, _, x) => uses_local(x, name),
+        Expr::Binary(_, x, y) | Expr::Apply(x, y) | Expr::Update(x, _, y) => uses_local(x, name) || uses_local(y, name),
+        Expr::List(items) | Expr::Tuple(items) => items
+            .iter()
+            .any(|item| uses_local(item, name)),
+        Expr::Record(fields) => fields
+            .iter()
+            .any(|(_, value)| uses_local(value, name)),
+        Expr::Func(param, body) => !binds(param) && uses_local(body, name),
+        Expr::Match(pred, arms) => uses_local(pred, name) || arms
+            .iter()
+            .filter(|(binding, _, _)| !binds(binding))
+            .any(|(_, guard, body)| guard.iter().chain(std::iter::once(body)).any(|expr| uses_local(expr, name))),
+    }
+}
+
+#[test]
+fn warnings() {
+    let code = "fn f |x, _y| let y = 2 in match x {\n\t| a => a\n\t| 3 => 4\n}\nfn g |a| (|a| a)(a)\ndef unused = 5\ndef _unused = 6\ndef main = f(1, 2):g\n";
+    let module = crate::embed::compile_main(code).1.unwrap();
+
+    let mut codes = module
+        .warnings()
+        .iter()
+        .map(|warning| warning.code().unwrap())
+        .collect::<Vec<_>>();
+    codes.sort();
+    assert_eq!(codes, vec!["W0001", "W0002", "W0003", "W0004"]);
+    assert!(module.warnings().iter().any(|warning| warning.msg() == "Binding 'y' is never used"));
+    assert!(module.warnings().iter().any(|warning| warning.msg() == "Definition 'unused' is never used"));
+}
+
+#[test]
+fn shadowed_bindings() {
+    // The outer `x` is only used by the inner bindings that shadow it
+    let code = "fn f |x| (|x| x)(1)\nfn g |x| match 1 {\n\t| x => x\n}\nfn h |x| let x = 1 in x\ndef main = (f(1), g(2), h(3))\n";
+    let module = crate::embed::compile_main(code).1.unwrap();
+
+    let unused = module
+        .warnings()
+        .iter()
+        .filter(|warning| warning.code() == Some("W0001"))
+        .count();
+    assert_eq!(unused, 3);
+}
@@ -1,15 +1,20 @@
 // pub mod val;
 pub mod data;
 pub mod infer;
+pub mod class;
+mod lint;
+mod exhaustive;
 
 use std::collections::{HashMap, HashSet};
 use internment::LocalIntern;
 use crate::{
     ast::{self, Literal},
+    embed,
     error::Error,
     src::Span,
     ty::{Primitive, Type},
     node::{Node, SrcNode, TypeNode},
+    vm::{Natives, NativeId},
 };
 use self::{
     infer::{TypeId, TypeInfo, Constraint, InferCtx},
@@ -50,6 +55,10 @@ pub enum Pat<M> {
     Tuple(Vec<Node<Binding<M>, M>>),
     Record(Vec<(SrcNode<Ident>, Node<Binding<M>, M>)>),
     Deconstruct(SrcNode<(DataId, usize)>, Vec<(SrcNode<Ident>, M)>, Node<Binding<M>, M>),
+    // Inclusive of both ends
+    Range(Literal, Literal),
+    // Alternatives always bind the same names, with the same types
+    Or(Vec<Node<Binding<M>, M>>),
 }
 
 type InferBinding = InferNode<Binding<(Span, TypeId)>>;
@@ -66,7 +75,7 @@ pub struct Binding<M> {
 impl<M> Node<Binding<M>, M> {
     fn get_binding_idents<'a>(&'a self, idents: &mut HashMap<Ident, &'a M>) {
         match &*self.pat {
-            Pat::Wildcard | Pat::Literal(_) => {},
+            Pat::Wildcard | Pat::Literal(_) | Pat::Range(_, _) => {},
             Pat::List(items) => items
                 .iter()
                 .for_each(|item| item.get_binding_idents(idents)),
@@ -85,6 +94,7 @@ impl<M> Node<Binding<M>, M> {
                 .iter()
                 .for_each(|(_, field)| field.get_binding_idents(idents)),
             Pat::Deconstruct(_, _, inner) => inner.get_binding_idents(idents),
+            Pat::Or(alts) => alts[0].get_binding_idents(idents),
         }
 
         if let Some(ident) = &self.binding {
@@ -103,98 +113,27 @@ impl<M> Pat<M> {
     fn is_refutable(&self, data_ctx: &data::DataCtx) -> bool {
         match &*self {
             Pat::Wildcard => false,
-            Pat::Literal(_) => true,
+            Pat::Literal(_) | Pat::Range(_, _) => true,
             Pat::List(_) => true, // List could be different size
             Pat::ListFront(items, _) => items.len() > 0,
             Pat::Tuple(items) => items.iter().any(|item| item.pat.is_refutable(data_ctx)),
             Pat::Record(fields) => fields.iter().any(|(_, field)| field.pat.is_refutable(data_ctx)),
             Pat::Deconstruct(data, _, inner) => data_ctx.get_data(data.0).variants.len() != 1
                 || inner.pat.is_refutable(data_ctx),
-        }
-    }
-}
-
-fn arms_are_exhaustive<'a>(
-    data_ctx: &data::DataCtx,
-    ty: &Type,
-    arms: impl Iterator<Item=&'a TypeBinding> + Clone,
-) -> Result<(), Option<String>> {
-    if arms.clone().any(|binding| !binding.pat.is_refutable(data_ctx)) {
-        Ok(())
-    } else {
-        match ty {
-            Type::List(_) => {
-                let smallest_irrefutable = arms
-                    .clone()
-                    .fold(None, |a, binding| match &*binding.pat {
-                        Pat::ListFront(items, _) if items
-                            .iter()
-                            .all(|item| !item.pat.is_refutable(data_ctx)) => Some(a
-                                .unwrap_or(items.len())
-                                .min(items.len())),
-                        pat if !pat.is_refutable(data_ctx) => Some(0),
-                        _ => a,
-                    })
-                    .ok_or_else(|| Some(format!("[...]")))?;
-                let holes_patched = arms
-                    .filter_map(|binding| match &*binding.pat {
-                        Pat::List(items) if items
-                            .iter()
-                            .all(|item| !item.pat.is_refutable(data_ctx))
-                            && items.len() < smallest_irrefutable => Some(items.len()),
-                        _ => None,
-                    })
-                    .collect::<HashSet<_>>();
-                if holes_patched.len() >= smallest_irrefutable {
-                    Ok(())
-                } else {
-                    let case_n = (0..smallest_irrefutable)
-                        .find(|i| !holes_patched.contains(&i))
-                        .unwrap();
-                    Err(Some(format!("[{}]", (0..case_n).map(|_| "_").collect::<Vec<_>>().join(", "))))
-                }
-            },
-            Type::Primitive(Primitive::Boolean) => {
-                let mut found = [false, false];
-                arms
-                    .for_each(|binding| match &*binding.pat {
-                        Pat::Literal(Literal::Boolean(x)) => found[*x as usize] = true,
-                        _ => {},
-                    });
-                if found == [true, true] {
-                    Ok(())
-                } else {
-                    Err(Some(format!("{}", found[0])))
-                }
-            },
-            Type::Data(data, _) => {
-                let data = data_ctx.get_data(**data);
-                let variants_matched = arms
-                    .filter_map(|binding| match &*binding.pat {
-                        Pat::Deconstruct(data, _, inner) if !inner.pat.is_refutable(data_ctx) =>
-                            Some(data.1),
-                        _ => None,
-                    })
-                    .collect::<HashSet<_>>();
-                if variants_matched.len() == data.variants.len() {
-                    Ok(())
-                } else {
-                    let missing = (0..data.variants.len())
-                        .find(|v| !variants_matched.contains(&v))
-                        .unwrap();
-                    Err(Some(format!("{}", *data.variants[missing].0)))
-                }
-            },
-            _ => Err(None),
+            Pat::Or(alts) => alts.iter().all(|alt| alt.pat.is_refutable(data_ctx)),
         }
     }
 }
 
 #[derive(Copy, Clone, Debug)]
-#[repr(u32)]
+#[repr(u16)]
 pub enum Intrinsic {
     Out,
     In,
+    // Abort execution with the given message
+    Panic,
+    // Call a native function registered by the embedder
+    Native(NativeId),
 }
 
 #[derive(Debug)]
@@ -212,8 +151,11 @@ pub enum Expr<M> {
     Apply(Node<Self, M>, Node<Self, M>), // TODO: Should application be a binary operator?
     Access(Node<Self, M>, SrcNode<Ident>),
     Update(Node<Self, M>, SrcNode<Ident>, Node<Self, M>),
-    Match(Node<Self, M>, Vec<(Node<Binding<M>, M>, Node<Self, M>)>),
+    // Each arm has a pattern, an optional guard and a body
+    Match(Node<Self, M>, Vec<(Node<Binding<M>, M>, Option<Node<Self, M>>, Node<Self, M>)>),
     Constructor(SrcNode<(DataId, usize)>, Vec<(SrcNode<Ident>, M)>, Node<Self, M>),
+    // A class member, along with the type that the instance is chosen for
+    Member(SrcNode<Ident>, SrcNode<Ident>, M),
 }
 
 type InferExpr = InferNode<Expr<(Span, TypeId)>>;
@@ -223,11 +165,15 @@ pub type TypeExpr = TypeNode<Expr<(Span, SrcNode<Type>)>>;
 pub enum Scope<'a> {
     Root {
         module: &'a Module,
-        current_def: Option<(Ident, TypeId, &'a [SrcNode<Ident>])>,
-        globals: GlobalHints<'a>,
+        // Definitions that are being inferred together, along with their generic parameters
+        group: &'a [(Ident, TypeId, Vec<SrcNode<Ident>>)],
+        poisoned: &'a HashSet<Ident>,
+        natives: &'a HashMap<Ident, NativeSig>,
     },
     Local(Ident, TypeId, &'a Self),
     Many(HashMap<Ident, TypeId>, &'a Self),
+    // Locals that have been generalised over the given type parameters
+    Generalised(HashMap<Ident, (TypeId, Vec<Ident>)>, &'a Self),
 }
 
 impl<'a> Scope<'a> {
@@ -253,10 +199,34 @@ impl<'a> Scope<'a> {
                 .get(&ident)
                 .copied()
                 .or_else(|| parent.get_local(ident, infer, span)),
+            Scope::Generalised(locals, parent) => if let Some((ty, params)) = locals.get(&ident) {
+                Some(infer.instantiate_generalised(*ty, params, span))
+            } else {
+                parent.get_local(ident, infer, span)
+            },
             Scope::Root { .. } => None,
         }
     }
 
+    // Collect the types of everything in scope, since type variables they mention cannot be generalised
+    fn type_ids(&self, ids: &mut Vec<TypeId>) {
+        match self {
+            Scope::Local(_, ty, parent) => {
+                ids.push(*ty);
+                parent.type_ids(ids);
+            },
+            Scope::Many(locals, parent) => {
+                ids.extend(locals.values().copied());
+                parent.type_ids(ids);
+            },
+            Scope::Generalised(locals, parent) => {
+                ids.extend(locals.values().map(|(ty, _)| *ty));
+                parent.type_ids(ids);
+            },
+            Scope::Root { group, .. } => ids.extend(group.iter().map(|(_, ty, _)| *ty)),
+        }
+    }
+
     fn get_def_type(
         &self,
         ident: Ident,
@@ -266,10 +236,11 @@ impl<'a> Scope<'a> {
         match self {
             Scope::Local(_, _, parent) => parent.get_def_type(ident, infer, span),
             Scope::Many(_, parent) => parent.get_def_type(ident, infer, span),
-            Scope::Root { module, current_def, globals } => current_def
-                // Check current definition
-                .as_ref()
-                .filter(|(name, _, _)| *name == ident)
+            Scope::Generalised(_, parent) => parent.get_def_type(ident, infer, span),
+            Scope::Root { module, group, poisoned, .. } => group
+                // Check definitions being inferred alongside this one (these are not yet generalised)
+                .iter()
+                .find(|(name, _, _)| *name == ident)
                 .map(|(_, ty, generics)| Ok(Some((
                     *ty,
                     generics
@@ -281,19 +252,12 @@ impl<'a> Scope<'a> {
                 .or_else(|| module
                     .get_def_type(ident, infer, span)
                     .map(|x| Ok(Some(x))))
-                // Check uninitiated globals
-                .unwrap_or_else(|| Ok(if let Some((global_span, generics, hint)) = globals.0.get(&ident) {
-                    let generics = generics
-                        .iter()
-                        .map(|ident| (ident.clone(), infer.insert(TypeInfo::Unknown(Some(**ident)), span)))
-                        .collect::<Vec<_>>();
-                    Some((
-                        hint.to_type_id(infer, &|gen| generics.iter().find(|(name, _)| **name == gen).map(|(_, ty)| *ty))?,
-                        generics,
-                    ))
+                // Check globals that failed to compile
+                .unwrap_or_else(|| if poisoned.contains(&ident) {
+                    Err(Error::poisoned())
                 } else {
-                    None
-                })),
+                    Ok(None)
+                }),
         }
     }
 
@@ -321,24 +285,77 @@ impl<'a> Scope<'a> {
                 vec![universe],
                 Intrinsic::In,
             ))),
-            _ => Ok(None),
+            "panic" => Ok(Some((
+                infer.insert(TypeInfo::Unknown(None), span),
+                Vec::new(),
+                vec![{
+                    let c = infer.insert(TypeInfo::Primitive(Primitive::Char), span);
+                    infer.insert(TypeInfo::List(c), span)
+                }],
+                Intrinsic::Panic,
+            ))),
+            _ => match self {
+                Scope::Local(_, _, parent) => parent.get_intrinsic(ident, infer, span),
+                Scope::Many(_, parent) => parent.get_intrinsic(ident, infer, span),
+                Scope::Generalised(_, parent) => parent.get_intrinsic(ident, infer, span),
+                Scope::Root { natives, .. } => Ok(natives
+                    .get(&ident)
+                    .map(|(id, args, ret)| (
+                        infer.instantiate_ty_inner(&|_| None, ret),
+                        Vec::new(),
+                        args
+                            .iter()
+                            .map(|arg| infer.instantiate_ty_inner(&|_| None, arg))
+                            .collect(),
+                        Intrinsic::Native(*id),
+                    ))),
+            },
         }
     }
 }
 
-#[derive(Copy, Clone)]
-pub struct GlobalHints<'a>(&'a HashMap<Ident, (Span, Vec<SrcNode<Ident>>, &'a SrcNode<ast::Type>)>);
+// The type signature of a native function: its ID, argument types and return type
+type NativeSig = (NativeId, Vec<SrcNode<Type>>, SrcNode<Type>);
 
 #[derive(Debug)]
 pub struct Def {
     pub generics: Vec<SrcNode<Ident>>,
+    // Classes that generic parameters must be instances of
+    pub given: Vec<(SrcNode<Ident>, SrcNode<Ident>)>,
     pub name: SrcNode<Ident>,
     pub body: TypeExpr,
 }
 
+// A definition to be inferred, either declared in the module or implementing a member of a class instance
+struct DefSource<'a> {
+    name: SrcNode<Ident>,
+    generics: &'a [SrcNode<Ident>],
+    given: &'a [(SrcNode<Ident>, SrcNode<Ident>)],
+    ty: Option<&'a SrcNode<ast::Type>>,
+    // The type of the class member, the class parameter, and the type of the instance
+    member: Option<(&'a SrcNode<Type>, Ident, &'a SrcNode<Type>)>,
+    body: &'a SrcNode<ast::Expr>,
+}
+
+impl<'a> From<&'a ast::Def> for DefSource<'a> {
+    fn from(def: &'a ast::Def) -> Self {
+        Self {
+            name: def.name.clone(),
+            generics: &def.generics,
+            given: &def.given,
+            ty: Some(&def.ty),
+            member: None,
+            body: &def.body,
+        }
+    }
+}
+
 pub struct Program {
     pub root: Module,
     pub data_ctx: data::DataCtx,
+    pub class_ctx: class::ClassCtx,
+    // The signatures of native functions that may be called with `@name(...)`
+    natives: HashMap<Ident, NativeSig>,
 }
 
 impl Program {
@@ -346,77 +363,168 @@ impl Program {
         Self {
             root: Module::default(),
             data_ctx: data::DataCtx::default(),
+            class_ctx: class::ClassCtx::default(),
+            natives: HashMap::default(),
         }
     }
 
     pub fn new_root(module: &SrcNode<ast::Module>) -> Result<Self, Vec<Error>> {
+        Self::new_root_with_natives(module, &Natives::default())
+    }
+
+    pub fn new_root_with_natives(module: &SrcNode<ast::Module>, natives: &Natives) -> Result<Self, Vec<Error>> {
+        let (this, errors) = Self::new_root_partial(module, natives);
+        if errors.len() == 0 {
+            Ok(this)
+        } else {
+            Err(errors)
+        }
+    }
+
+    // Like `new_root_with_natives`, but keeps the definitions that compiled successfully even if others did not
+    pub fn new_root_partial(module: &SrcNode<ast::Module>, natives: &Natives) -> (Self, Vec<Error>) {
+        let (data_ctx, mut errors) = data::DataCtx::from_ast_module(module);
+        let (class_ctx, mut class_errors) = class::ClassCtx::from_ast_module(module, &data_ctx);
+        errors.append(&mut class_errors);
+        let natives = natives
+            .iter()
+            .filter_map(|(id, native)| {
+                let sig = native.args
+                    .iter()
+                    .map(|arg| Ok(SrcNode::new(embed::desc_to_type(&data_ctx, arg)?, Span::none())))
+                    .collect::<Result<Vec<_>, Error>>()
+                    .and_then(|args| Ok((id, args, SrcNode::new(embed::desc_to_type(&data_ctx, &native.ret)?, Span::none()))));
+                match sig {
+                    Ok(sig) => Some((Ident::new(native.name.clone()), sig)),
+                    Err(err) => {
+                        errors.push(Error::custom(format!("Invalid signature for native function '{}': {}", native.name, err.msg())));
+                        None
+                    },
+                }
+            })
+            .collect();
         let mut this = Self {
             root: Module::default(),
-            data_ctx: data::DataCtx::from_ast_module(module)?,
+            data_ctx,
+            class_ctx,
+            natives,
         };
-        // Collect list of globals
-        let globals = module.decls
+        // Definitions that failed to compile. Anything that refers to them is not reported.
+        let mut poisoned = module.decls
             .iter()
             .filter_map(|decl| match &**decl {
-                ast::Decl::Def(def) => Some((*def.name, (def.name.span(), def.generics.clone(), &def.ty))),
+                ast::Decl::Error { name: Some(name), is_type: false } => Some(**name),
                 _ => None,
             })
-            .collect();
+            .collect::<HashSet<_>>();
 
-        for decl in module.decls.iter() {
-            match &**decl {
-                ast::Decl::Def(def) => this.insert_def_inner(GlobalHints(&globals), def)
-                    .map_err(|e| vec![e])?,
-                _ => {},
+        let defs = module.decls
+            .iter()
+            .filter_map(|decl| match &**decl {
+                ast::Decl::Def(def) => Some(def),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        // Infer mutually recursive definitions together, after the definitions they depend on
+        for group in def_groups(&defs) {
+            let group = group
+                .into_iter()
+                .map(DefSource::from)
+                .collect::<Vec<_>>();
+            if let Err(err) = this.insert_defs(&group, &poisoned) {
+                poisoned.extend(group.iter().map(|def| *def.name));
+                if !err.is_poisoned() {
+                    errors.push(err);
+                }
             }
         }
 
-        this.type_check()
-            .map_err(|e| vec![e])?;
+        // Instance members are inferred as hidden definitions, now that all definitions are known
+        let mut def_names = defs
+            .iter()
+            .map(|def| *def.name)
+            .collect::<Vec<_>>();
+        for ast_instance in module.decls
+            .iter()
+            .filter_map(|decl| if let ast::Decl::Instance(instance) = &**decl { Some(instance) } else { None })
+        {
+            let (instance, class) = match this.class_ctx
+                .declared_instance(ast_instance)
+                .and_then(|instance| Some((instance, this.class_ctx.class(*instance.class)?)))
+            {
+                Some((instance, class)) => (instance, class),
+                // Invalid instances have already been reported
+                None => continue,
+            };
 
-        Ok(this)
+            let member_defs = ast_instance.members
+                .iter()
+                .map(|(name, body)| {
+                    let member_ty = class.members
+                        .iter()
+                        .find(|(member, _)| **member == **name)
+                        .map(|(_, ty)| ty.clone())
+                        .unwrap();
+                    (SrcNode::new(instance.members[&**name], name.span()), member_ty, body)
+                })
+                .collect::<Vec<_>>();
+            let (param, instance_ty) = (*class.param, instance.ty.clone());
+
+            for (name, member_ty, body) in member_defs.iter() {
+                def_names.push(**name);
+                let member_def = DefSource {
+                    name: name.clone(),
+                    generics: &ast_instance.generics,
+                    given: &ast_instance.given,
+                    ty: None,
+                    member: Some((member_ty, param, &instance_ty)),
+                    body,
+                };
+                if let Err(err) = this.insert_defs(&[member_def], &poisoned) {
+                    if !err.is_poisoned() {
+                        errors.push(err);
+                    }
+                }
+            }
+        }
+
+        errors.append(&mut this.type_check(&def_names));
+
+        (this, errors)
     }
 
-    fn type_check(&self) -> Result<(), Error> {
-        // Go through globals, ensuring they type-check recursively
-        for (_, def) in self.root.defs.iter() {
-            // Ensure that all self-references in the body match with the inferred definition
-            // This is a halfway-house solution that allows recursion without proper damas-HM inference
-            def.body
-                .visit()
-                .try_for_each(|expr| match &**expr {
-                    Expr::Global(ident, _) => {
-                        let mut infer = InferCtx::from_data_ctx(&self.data_ctx);
-                        let global = &self.root.defs[ident];
-                        let definition = infer.instantiate_ty(&global.generics, global.body.ty(), global.body.ty().span()).0;
-                        // TODO: Put the usage span in here
-                        let usage = infer.instantiate_ty(&def.generics, expr.ty(), expr.ty().span()).0;
-                        infer.unify(usage, definition)
-                    },
-                    _ => Ok(()),
-                })?;
+    fn type_check(&self, def_names: &[Ident]) -> Vec<Error> {
+        let mut errors = Vec::new();
 
+        // Go through globals, ensuring they type-check recursively
+        let mut checked = HashSet::new();
+        for def in def_names
+            .iter()
+            .filter(|name| checked.insert(**name))
+            .filter_map(|name| self.root.defs.get(name))
+        {
             // Type-check pattern refutability
-            def.body
+            errors.extend(def.body
                 .visit()
-                .try_for_each(|expr| match &**expr {
-                    Expr::Func(param, _) if param.pat.is_refutable(&self.data_ctx) => Err(Error::custom(format!("Refutable pattern may not be used here"))
+                .filter_map(|expr| match &**expr {
+                    Expr::Func(param, _) if param.pat.is_refutable(&self.data_ctx) => Some(Error::custom(format!("Refutable pattern may not be used here"))
+                        .with_code("E0401")
                         .with_span(param.pat.span())),
-                    Expr::Match(pred, arms) => if let Err(missing_case) = arms_are_exhaustive(&self.data_ctx, pred.ty(), arms.iter().map(|(binding, _)| binding)) {
-                        let hint = missing_case
-                            .map(|case| format!("Case '{}' is not handled", case))
-                            .unwrap_or_else(|| format!("Handle all possible cases"));
-                        Err(Error::custom(format!("Match arms are not exhaustive"))
+                    // Guarded arms might not match, so they cover nothing
+                    Expr::Match(_, arms) => exhaustive::missing_case(&self.data_ctx, arms
+                        .iter()
+                        .filter(|(_, guard, _)| guard.is_none())
+                        .map(|(binding, _, _)| binding))
+                        .map(|case| Error::custom(format!("Match arms are not exhaustive"))
+                            .with_code("E0400")
                             .with_span(expr.span())
-                            .with_hint(hint))
-                    } else {
-                        Ok(())
-                    },
-                    _ => Ok(()),
-                })?;
+                            .with_hint(format!("Case '{}' is not handled", case))),
+                    _ => None,
+                }));
         }
 
-        Ok(())
+        errors
     }
 
     pub fn root(&self) -> &Module {
@@ -424,75 +532,185 @@ impl Program {
     }
 
     pub fn insert_def(&mut self, ast_def: &ast::Def) -> Result<(), Error> {
-        self.insert_def_inner(GlobalHints(&HashMap::default()), ast_def)
+        self.insert_defs(&[DefSource::from(ast_def)], &HashSet::default())
     }
 
-    fn insert_def_inner(&mut self, globals: GlobalHints, ast_def: &ast::Def) -> Result<(), Error> {
+    // Remove a definition. Nothing may refer to it.
+    pub fn remove_def(&mut self, name: Ident) -> Option<Def> {
+        self.root.defs.remove(&name)
+    }
+
+    // Infer the types of a group of (potentially mutually recursive) definitions and generalise them
+    fn insert_defs(&mut self, ast_defs: &[DefSource], poisoned: &HashSet<Ident>) -> Result<(), Error> {
         // Check for double declaration
-        if let Some(existing_def) = self.root.defs.get(&**ast_def.name) {
-            return Err(Error::custom(format!("Definition with name '{}' already exists", **ast_def.name))
-                .with_span(existing_def.name.span())
-                .with_span(ast_def.name.span()));
+        for ast_def in ast_defs.iter() {
+            if let Some(existing_def) = self.root.defs.get(&**ast_def.name) {
+                return Err(Error::custom(format!("Definition with name '{}' already exists", **ast_def.name))
+                    .with_code("E0101")
+                    .with_span(existing_def.name.span())
+                    .with_span(ast_def.name.span()));
+            } else if self.class_ctx.is_member(*ast_def.name) {
+                return Err(Error::custom(format!("Definition with name '{}' conflicts with a class member", **ast_def.name))
+                    .with_code("E0101")
+                    .with_span(ast_def.name.span()));
+            }
         }
 
-        let mut infer = InferCtx::from_data_ctx(&self.data_ctx);
+        let mut infer = InferCtx::from_ctxs(&self.data_ctx, &self.class_ctx);
 
-        // Add the definition's generics to the infer context
-        ast_def
-            .generics
+        // Add the definitions' generics to the infer context
+        ast_defs
             .iter()
+            .flat_map(|ast_def| ast_def.generics.iter())
             .for_each(|name| infer.insert_generic(**name, name.span()));
 
-        let generics = ast_def.generics
+        // Generic parameters may be assumed to be instances of the classes they are given
+        for ast_def in ast_defs.iter() {
+            for (class, param) in ast_def.given.iter() {
+                self.class_ctx.check_given(class, param, ast_def.generics)?;
+                infer.add_given(**class, **param);
+            }
+        }
+
+        let group = ast_defs
             .iter()
-            .map(|g| SrcNode::new(**g, g.span()))
-            .collect::<Vec<_>>();
+            .map(|ast_def| {
+                let def_ty = infer.insert(TypeInfo::Unknown(None), ast_def.body.span());
 
-        let current_def_ty = infer.insert(TypeInfo::Unknown(None), ast_def.body.span());
+                // Unify with optional type annotation
+                if let Some(ty) = ast_def.ty {
+                    let ty_id = ty.to_type_id(&mut infer, &|_| None)?;
+                    infer.unify(def_ty, ty_id)?;
+                }
+
+                // Instance members must have the type of the class member, with the instance type in place of the
+                // class parameter
+                if let Some((member_ty, param, instance_ty)) = ast_def.member {
+                    let generics = ast_def.generics
+                        .iter()
+                        .filter_map(|name| Some((**name, infer.generic(**name)?)))
+                        .collect::<HashMap<_, _>>();
+                    let instance_ty_id = infer.instantiate_ty_inner(&|name| generics.get(&name).copied(), instance_ty);
+                    let member_ty_id = infer.instantiate_ty_inner(&|name| Some(instance_ty_id).filter(|_| name == param), member_ty);
+                    infer.unify(def_ty, member_ty_id)?;
+                }
+
+                Ok((*ast_def.name, def_ty, ast_def.generics.to_vec()))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
 
         let scope = Scope::Root {
             module: &self.root,
-            current_def: Some((*ast_def.name, current_def_ty, &generics)),
-            globals,
+            group: &group,
+            poisoned,
+            natives: &self.natives,
         };
 
-        let body = {
-            let mut body = ast_def.body.to_hir(&mut infer, &scope)?;
+        let bodies = ast_defs
+            .iter()
+            .zip(group.iter())
+            .map(|(ast_def, (_, def_ty, _))| {
+                let body = ast_def.body.to_hir(&mut infer, &scope)?;
+                infer.unify(*def_ty, body.type_id())?;
+                Ok(body)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        // Class constraints on free type variables can only be solved after generalisation
+        infer.solve_some()?;
 
-            infer.unify(current_def_ty, body.type_id())?;
+        // Generalise over type variables that are still free, giving them names that users cannot write. Variables may
+        // be shared between definitions in the group.
+        let def_vars = group
+            .iter()
+            .map(|(_, def_ty, _)| {
+                let mut vars = Vec::new();
+                infer.free_vars(*def_ty, &mut vars);
+                vars
+            })
+            .collect::<Vec<_>>();
+        let mut var_names = HashMap::new();
+        let implicit_generics = def_vars
+            .iter()
+            .map(|vars| vars
+                .iter()
+                .map(|var| *var_names.entry(*var).or_insert_with(|| infer.fresh_generic_name()))
+                .collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        for (var, name) in var_names.iter() {
+            infer.generalise(*var, *name);
+        }
 
-            // Unify with optional type annotation
-            let ty_id = ast_def.ty.to_type_id(&mut infer, &|_| None)?;
+        // Generalised type variables that must be instances of classes become implicit `given` clauses
+        let implicit_given = infer.constrained_generics();
+        for (class, gen) in implicit_given.iter() {
+            infer.add_given(*class, *gen);
+        }
 
-            infer.unify(body.type_id(), ty_id)?;
+        infer.solve_all()?;
 
-            infer.solve_all()?;
-            body.into_checked(&mut infer)?
+        let mut defs = Vec::new();
+        for ((ast_def, body), implicit) in ast_defs.iter().zip(bodies.into_iter()).zip(implicit_generics.iter()) {
+            let body = body.into_checked(&infer)?
                 // Use the def name as the type's span
-                .map_meta(|(span, ty)| (span, ty.map_meta(|_| ast_def.name.span())))
-        };
+                .map_meta(|(span, ty)| (span, ty.map_meta(|_| ast_def.name.span())));
+
+            // Ensure that all generic parameters are in use
+            for gen in ast_def.generics.iter() {
+                let mut uses_gen = false;
+                body.ty().visit(&mut |ty| {
+                    if **ty == Type::GenParam(**gen) {
+                        uses_gen = true;
+                    }
+                });
+                if !uses_gen {
+                    return Err(Error::custom(format!("Type parameter '{}' must be mentioned by type '{}'", **gen, **body.ty()))
+                        .with_code("E0206")
+                        .with_span(gen.span())
+                        .with_span(body.ty().span())
+                        .with_hint(format!("Consider removing '{}' from the list of generics", **gen)));
+                }
+            }
+
+            let generics = ast_def.generics
+                .iter()
+                .cloned()
+                .chain(implicit.iter().map(|name| SrcNode::new(*name, ast_def.name.span())))
+                .collect::<Vec<_>>();
+
+            let given = ast_def.given
+                .iter()
+                .cloned()
+                .chain(implicit_given
+                    .iter()
+                    .filter(|(_, gen)| implicit.contains(gen))
+                    .map(|(class, gen)| (SrcNode::new(*class, ast_def.name.span()), SrcNode::new(*gen, ast_def.name.span()))))
+                .collect::<Vec<_>>();
+
+            defs.push(Def {
+                generics,
+                given,
+                name: ast_def.name.clone(),
+                body,
+            });
+        }
 
-        // Ensure that all generic parameters are in use
-        for gen in generics.iter() {
-            let mut uses_gen = false;
-            body.ty().visit(&mut |ty| {
-                if **ty == Type::GenParam(**gen) {
-                    uses_gen = true;
+        // References between definitions in the group were made before generalisation, so they need to pass on
+        // the generic parameters that were discovered
+        for def in defs.iter_mut() {
+            def.body.for_each_global_mut(&mut |name, generics| {
+                if let Some(idx) = group.iter().position(|(other, _, _)| *other == name) {
+                    for gen in implicit_generics[idx].iter() {
+                        let span = Span::none();
+                        generics.push((SrcNode::new(*gen, span), (span, SrcNode::new(Type::GenParam(*gen), span))));
+                    }
                 }
             });
-            if !uses_gen {
-                return Err(Error::custom(format!("Type parameter '{}' must be mentioned by type '{}'", **gen, **body.ty()))
-                    .with_span(gen.span())
-                    .with_span(body.ty().span())
-                    .with_hint(format!("Consider removing '{}' from the list of generics", **gen)));
-            }
         }
 
-        self.root.defs.insert(*ast_def.name, Def {
-            generics,
-            name: ast_def.name.clone(),
-            body,
-        });
+        for def in defs {
+            self.root.defs.insert(*def.name, def);
+        }
         Ok(())
     }
 }
@@ -507,13 +725,222 @@ impl Module {
         self.defs.get(&name)
     }
 
+    pub fn defs(&self) -> impl Iterator<Item=&Def> {
+        self.defs.values()
+    }
+
     fn get_def_type(&self, ident: Ident, infer: &mut InferCtx, span: Span) -> Option<(TypeId, Vec<(SrcNode<Ident>, TypeId)>)> {
         self.defs
             .get(&ident)
-            .map(|def| infer.instantiate_ty(&def.generics, def.body.ty(), span))
+            .map(|def| {
+                let (type_id, params) = infer.instantiate_ty(&def.generics, def.body.ty(), span);
+                // Uses of the definition must satisfy its class constraints
+                for (class, gen) in def.given.iter() {
+                    let param_ty = params
+                        .iter()
+                        .find(|(name, _)| **name == **gen)
+                        .unwrap()
+                        .1;
+                    infer.add_constraint(Constraint::Class {
+                        class: SrcNode::new(**class, span),
+                        ty: param_ty,
+                    });
+                }
+                (type_id, params)
+            })
+    }
+}
+
+// Dependency analysis
+
+impl ast::Pat {
+    pub(crate) fn collect_idents(&self, idents: &mut Vec<Ident>) {
+        match self {
+            ast::Pat::Wildcard | ast::Pat::Literal(_) | ast::Pat::Range(_, _) | ast::Pat::Error => {},
+            ast::Pat::List(items) | ast::Pat::Tuple(items) | ast::Pat::Or(items) => items
+                .iter()
+                .for_each(|item| item.collect_idents(idents)),
+            ast::Pat::ListFront(items, tail) => {
+                items
+                    .iter()
+                    .for_each(|item| item.collect_idents(idents));
+                idents.extend(tail.iter().map(|tail| **tail));
+            },
+            ast::Pat::Record(fields) => fields
+                .iter()
+                .for_each(|(_, field)| field.collect_idents(idents)),
+            ast::Pat::Deconstruct(_, inner) => inner.collect_idents(idents),
+        }
+    }
+}
+
+impl ast::Binding {
+    pub(crate) fn collect_idents(&self, idents: &mut Vec<Ident>) {
+        match self {
+            ast::Binding::Unbound(pat) => pat.collect_idents(idents),
+            ast::Binding::Bound(name, pat) => {
+                idents.push(**name);
+                pat.collect_idents(idents);
+            },
+            ast::Binding::Ident(name) => idents.push(*name),
+        }
+    }
+}
+
+impl ast::Expr {
+    // Find the free identifiers this expression refers to (i.e: those that could refer to globals)
+    fn free_idents(&self, locals: &mut Vec<Ident>, free: &mut HashSet<Ident>) {
+        // Bring the bindings of a pattern into scope for the duration of `f`
+        fn with_binding(binding: &ast::Binding, locals: &mut Vec<Ident>, f: impl FnOnce(&mut Vec<Ident>)) {
+            let old_len = locals.len();
+            binding.collect_idents(locals);
+            f(locals);
+            locals.truncate(old_len);
+        }
+
+        match self {
+            ast::Expr::Literal(_) | ast::Expr::Error => {},
+            ast::Expr::Path(path) => if path.len() == 1 && !locals.contains(&path.base()) {
+                free.insert(path.base());
+            },
+            ast::Expr::Intrinsic(_, args) | ast::Expr::List(args) | ast::Expr::Tuple(args) => args
+                .iter()
+                .for_each(|arg| arg.free_idents(locals, free)),
+            ast::Expr::Unary(_, a) => a.free_idents(locals, free),
+            ast::Expr::Binary(_, a, b) | ast::Expr::Apply(a, b) => {
+                a.free_idents(locals, free);
+                b.free_idents(locals, free);
+            },
+            ast::Expr::If(pred, a, b) => {
+                pred.free_idents(locals, free);
+                a.free_idents(locals, free);
+                b.free_idents(locals, free);
+            },
+            ast::Expr::Match(pred, arms) => {
+                pred.free_idents(locals, free);
+                for ((binding, _), guard, body) in arms.iter() {
+                    with_binding(binding, locals, |locals| {
+                        if let Some(guard) = guard {
+                            guard.free_idents(locals, free);
+                        }
+                        body.free_idents(locals, free);
+                    });
+                }
+            },
+            ast::Expr::Func(param, _, body) => with_binding(param, locals, |locals| body.free_idents(locals, free)),
+            ast::Expr::Access(record, _) => record.free_idents(locals, free),
+            ast::Expr::Let(binding, _, val, then) => {
+                val.free_idents(locals, free);
+                with_binding(binding, locals, |locals| then.free_idents(locals, free));
+            },
+            ast::Expr::Record(fields) => fields
+                .iter()
+                .for_each(|(_, value)| value.free_idents(locals, free)),
+            ast::Expr::Update(record, field, value) => {
+                record.free_idents(locals, free);
+                locals.push(**field);
+                value.free_idents(locals, free);
+                locals.pop();
+            },
+            ast::Expr::Constructor(_, inner) => inner.free_idents(locals, free),
+        }
     }
 }
 
+// Group definitions into strongly-connected components of their dependency graph. Each group only depends on
+// itself and the groups that come before it.
+fn def_groups<'a>(defs: &[&'a ast::Def]) -> Vec<Vec<&'a ast::Def>> {
+    struct Tarjan<'a> {
+        deps: &'a [Vec<usize>],
+        index: Vec<Option<usize>>,
+        low_link: Vec<usize>,
+        on_stack: Vec<bool>,
+        stack: Vec<usize>,
+        counter: usize,
+        groups: Vec<Vec<usize>>,
+    }
+
+    impl<'a> Tarjan<'a> {
+        fn connect(&mut self, v: usize) {
+            self.index[v] = Some(self.counter);
+            self.low_link[v] = self.counter;
+            self.counter += 1;
+            self.stack.push(v);
+            self.on_stack[v] = true;
+
+            let deps = self.deps;
+            for &w in deps[v].iter() {
+                match self.index[w] {
+                    None => {
+                        self.connect(w);
+                        self.low_link[v] = self.low_link[v].min(self.low_link[w]);
+                    },
+                    Some(w_index) if self.on_stack[w] => self.low_link[v] = self.low_link[v].min(w_index),
+                    Some(_) => {},
+                }
+            }
+
+            if self.index[v] == Some(self.low_link[v]) {
+                let mut group = Vec::new();
+                loop {
+                    let w = self.stack.pop().unwrap();
+                    self.on_stack[w] = false;
+                    group.push(w);
+                    if w == v {
+                        break;
+                    }
+                }
+                // Keep source order within the group
+                group.sort();
+                self.groups.push(group);
+            }
+        }
+    }
+
+    // If a name is declared several times, references go to the first declaration
+    let indices = defs
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(i, def)| (*def.name, i))
+        .collect::<HashMap<_, _>>();
+
+    let deps = defs
+        .iter()
+        .map(|def| {
+            let mut free = HashSet::new();
+            def.body.free_idents(&mut Vec::new(), &mut free);
+            let mut deps = free
+                .into_iter()
+                .filter_map(|ident| indices.get(&ident).copied())
+                .collect::<Vec<_>>();
+            deps.sort();
+            deps
+        })
+        .collect::<Vec<_>>();
+
+    let mut tarjan = Tarjan {
+        deps: &deps,
+        index: vec![None; defs.len()],
+        low_link: vec![0; defs.len()],
+        on_stack: vec![false; defs.len()],
+        stack: Vec::new(),
+        counter: 0,
+        groups: Vec::new(),
+    };
+    for v in 0..defs.len() {
+        if tarjan.index[v].is_none() {
+            tarjan.connect(v);
+        }
+    }
+
+    // Groups are found after the groups they depend on
+    tarjan.groups
+        .into_iter()
+        .map(|group| group.into_iter().map(|i| defs[i]).collect())
+        .collect()
+}
+
 // AST to HIR conversions
 
 impl ast::Binding {
@@ -583,6 +1010,62 @@ impl ast::Binding {
                         .collect::<Result<_, _>>()?;
                     (infer.insert(TypeInfo::Record(field_type_ids), self.span()), Pat::Record(fields))
                 },
+                ast::Pat::Range(start, end) => {
+                    let start_type_info = start.get_type_info(infer, self.span());
+                    let type_id = infer.insert(start_type_info, self.span());
+                    let end_type_info = end.get_type_info(infer, self.span());
+                    let end_type_id = infer.insert(end_type_info, self.span());
+                    infer.unify(end_type_id, type_id)?;
+
+                    let hint = match (start, end) {
+                        (Literal::Number(start), Literal::Number(end)) if start > end => Some(format!("The range is empty, since {} is greater than {}", start, end)),
+                        (Literal::Char(start), Literal::Char(end)) if start > end => Some(format!("The range is empty, since '{}' comes after '{}'", start, end)),
+                        (Literal::Number(_), _) | (Literal::Char(_), _) => None,
+                        _ => Some(format!("Only numbers and characters may be matched by a range")),
+                    };
+                    if let Some(hint) = hint {
+                        return Err(Error::custom(format!("Invalid range pattern"))
+                            .with_code("E0403")
+                            .with_span(self.span())
+                            .with_hint(hint));
+                    }
+
+                    (type_id, Pat::Range(start.clone(), end.clone()))
+                },
+                ast::Pat::Or(alts) => {
+                    let type_id = infer.insert(TypeInfo::Unknown(None), self.span());
+                    let alts = alts
+                        .iter()
+                        .map(|alt| {
+                            let alt = alt.to_hir(infer)?;
+                            infer.unify(alt.type_id(), type_id)?;
+                            Ok(alt)
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+
+                    // Every alternative must bind the same names, with the same types
+                    let first = alts[0].binding_idents();
+                    for alt in alts[1..].iter() {
+                        let idents = alt.binding_idents();
+                        if let Some(name) = first
+                            .keys()
+                            .chain(idents.keys())
+                            .find(|name| !first.contains_key(*name) || !idents.contains_key(*name))
+                        {
+                            return Err(Error::custom(format!("Alternatives of a pattern bind different names"))
+                                .with_code("E0402")
+                                .with_span(alt.span())
+                                .with_hint(format!("'{}' must be bound by every alternative, or by none of them", **name)));
+                        }
+                        for (name, (_, ty)) in idents.iter() {
+                            infer.unify(*ty, first[name].1)?;
+                        }
+                    }
+
+                    (type_id, Pat::Or(alts))
+                },
+                // Already reported during parsing
+                ast::Pat::Error => return Err(Error::poisoned()),
                 ast::Pat::Deconstruct(constructor, inner) => {
                     let inner = inner.to_hir(infer)?;
 
@@ -617,6 +1100,7 @@ impl ast::Type {
                         TypeInfo::Ref(ty_id)
                     } else {
                         return Err(Error::custom(format!("Generic types may not be parameterised"))
+                            .with_code("E0203")
                             .with_span(self.span())
                             .with_span(params.iter().fold(Span::none(), |a, p| a.union(p.span()))));
                     }
@@ -643,6 +1127,8 @@ impl ast::Type {
                 i.to_type_id(infer, get_generic)?,
                 o.to_type_id(infer, get_generic)?,
             ),
+            // Already reported during parsing
+            ast::Type::Error => return Err(Error::poisoned()),
         };
 
         Ok(infer.insert(info, self.span()))
@@ -671,17 +1157,35 @@ impl ast::Expr {
                     let type_id = infer.insert(TypeInfo::Unknown(None), self.span());
                     infer.unify(type_id, global_id)?;
                     (type_id, Expr::Global(path.base(), generics))
+                } else if let Some((class, member_id, param_id)) = infer
+                    .class_ctx()
+                    .map(|class_ctx| class_ctx.get_member_type(path.base(), infer, self.span()))
+                    .transpose()?
+                    .flatten()
+                {
+                    let type_id = infer.insert(TypeInfo::Unknown(None), self.span());
+                    infer.unify(type_id, member_id)?;
+                    (type_id, Expr::Member(
+                        SrcNode::new(class, self.span()),
+                        SrcNode::new(path.base(), self.span()),
+                        (self.span(), param_id),
+                    ))
                 } else {
                     return Err(Error::custom(format!("No such binding '{}' in scope", path.base().to_string()))
+                        .with_code("E0100")
                         .with_span(self.span()));
                 }
             } else {
-                todo!("Complex paths")
+                // Paths are resolved to the qualified names of items before this point
+                return Err(Error::custom(format!("Unresolved path '{:?}'", path))
+                    .with_code("E0100")
+                    .with_span(self.span()));
             },
             ast::Expr::Intrinsic(name, args) => {
                 if let Some((type_id, generics, arg_tys, intrinsic)) = scope.get_intrinsic(*name, infer, self.span())? {
                     if arg_tys.len() != args.len() {
                         return Err(Error::custom(format!("Wrong number of intrinsic arguments: expected {}, found {}", arg_tys.len(), args.len()))
+                            .with_code("E0207")
                             .with_span(self.span()));
                     }
                     let args = args
@@ -700,6 +1204,7 @@ impl ast::Expr {
                     (type_id, Expr::Intrinsic(intrinsic, generics, args))
                 } else {
                     return Err(Error::custom(format!("No such intrinsic value '{}'", &*name))
+                        .with_code("E0100")
                         .with_span(self.span()));
                 }
             }
@@ -817,15 +1322,45 @@ impl ast::Expr {
                 let val = val.to_hir(infer, scope)?;
                 infer.unify(pat.type_id(), val.type_id())?;
 
-                let then_scope = scope.with_many(pat
+                // Generalise over type variables that are free in the value but not in the environment. Variables
+                // mentioned by unsolved constraints might yet be inferred, so they are left alone.
+                infer.solve_some()?;
+                let mut env_ids = Vec::new();
+                scope.type_ids(&mut env_ids);
+                let mut env_vars = Vec::new();
+                env_ids
+                    .into_iter()
+                    .for_each(|id| infer.free_vars(id, &mut env_vars));
+                infer.constrained_vars(&mut env_vars);
+                let mut vars = Vec::new();
+                infer.free_vars(val.type_id(), &mut vars);
+                let params = vars
+                    .into_iter()
+                    .filter(|var| !env_vars.contains(var))
+                    .map(|var| {
+                        let name = infer.fresh_generic_name();
+                        infer.generalise(var, name);
+                        name
+                    })
+                    .collect::<Vec<_>>();
+
+                let bindings = pat
                     .binding_idents()
                     .into_iter()
                     .map(|(ident, (_, ty))| (ident, *ty))
-                    .collect());
+                    .collect::<HashMap<_, _>>();
+                let then_scope = if params.len() == 0 {
+                    scope.with_many(bindings)
+                } else {
+                    Scope::Generalised(bindings
+                        .into_iter()
+                        .map(|(ident, ty)| (ident, (ty, params.clone())))
+                        .collect(), scope)
+                };
                 let then_body = then.to_hir(infer, &then_scope)?;
 
                 (then_body.type_id(), Expr::Match(val, vec![
-                    (pat, then_body)
+                    (pat, None, then_body)
                 ]))
             },
             ast::Expr::If(pred, a, b) => {
@@ -841,11 +1376,11 @@ impl ast::Expr {
                     (InferNode::new(Binding {
                         pat: SrcNode::new(Pat::Literal(Literal::Boolean(true)), Span::none()),
                         binding: None,
-                    }, (pred.span(), pred_type_id)), a),
+                    }, (pred.span(), pred_type_id)), None, a),
                     (InferNode::new(Binding {
                         pat: SrcNode::new(Pat::Literal(Literal::Boolean(false)), Span::none()),
                         binding: None,
-                    }, (pred.span(), pred_type_id)), b),
+                    }, (pred.span(), pred_type_id)), None, b),
                 ]))
             },
             ast::Expr::Match(pred, arms) => {
@@ -855,7 +1390,7 @@ impl ast::Expr {
 
                 let arms = arms
                     .iter()
-                    .map(|((pat, pat_ty), body)| {
+                    .map(|((pat, pat_ty), guard, body)| {
                         let pat = pat.to_hir(infer)?;
 
                         // Unify pattern with type hint
@@ -871,11 +1406,20 @@ impl ast::Expr {
                             .into_iter()
                             .map(|(ident, (_, ty))| (ident, *ty))
                             .collect());
+                        let guard = guard
+                            .as_ref()
+                            .map(|guard| -> Result<_, Error> {
+                                let guard = guard.to_hir(infer, &body_scope)?;
+                                let bool_type_id = infer.insert(TypeInfo::Primitive(Primitive::Boolean), guard.span());
+                                infer.unify(guard.type_id(), bool_type_id)?;
+                                Ok(guard)
+                            })
+                            .transpose()?;
                         let body = body.to_hir(infer, &body_scope)?;
 
                         infer.unify(match_type_id, body.type_id())?;
 
-                        Ok((pat, body))
+                        Ok((pat, guard, body))
                     })
                     .collect::<Result<Vec<_>, _>>()?;
 
@@ -895,6 +1439,8 @@ impl ast::Expr {
                     inner,
                 ))
             },
+            // Already reported during parsing
+            ast::Expr::Error => return Err(Error::poisoned()),
             // ast::Expr::Do(stmts) => {
             //     fn stmts_to_hir<'a>(mut stmts: impl Iterator<Item=&'a ast::DoStatement>, infer: &mut InferCtx, scope: &Scope) -> Result<Option<InferExpr>, Error> {
             //         match stmts.next() {
@@ -951,6 +1497,11 @@ impl InferPat {
                     .collect::<Result<_, _>>()?,
                 inner.into_checked(infer)?,
             ),
+            Pat::Range(start, end) => Pat::Range(start, end),
+            Pat::Or(alts) => Pat::Or(alts
+                .into_iter()
+                .map(|alt| alt.into_checked(infer))
+                .collect::<Result<_, _>>()?),
         };
 
         Ok(SrcNode::new(pat, span))
@@ -1038,9 +1589,10 @@ impl InferExpr {
                 pred.into_checked(infer)?,
                 arms
                     .into_iter()
-                    .map(|(pat, body)| -> Result<_, Error> {
+                    .map(|(pat, guard, body)| -> Result<_, Error> {
                         Ok((
                             pat.into_checked(infer)?,
+                            guard.map(|guard| guard.into_checked(infer)).transpose()?,
                             body.into_checked(infer)?,
                         ))
                     })
@@ -1053,13 +1605,62 @@ impl InferExpr {
                     .map(|(ident, (span, type_id))| Ok((ident, (span, infer.reconstruct(type_id, span)?))))
                     .collect::<Result<_, _>>()?,
                 inner.into_checked(infer)?,
-            )
+            ),
+            Expr::Member(class, member, (span, type_id)) => Expr::Member(
+                class,
+                member,
+                (span, infer.reconstruct(type_id, span)?),
+            ),
         }, (span, infer.reconstruct(type_id, span)?)))
     }
 }
 
 impl TypeExpr {
-    fn visit(&self) -> impl Iterator<Item=&Self> + '_ {
+    // Visit every reference to a global, allowing its generic parameters to be modified
+    fn for_each_global_mut(&mut self, f: &mut impl FnMut(Ident, &mut Vec<(SrcNode<Ident>, (Span, SrcNode<Type>))>)) {
+        match &mut **self {
+            Expr::Literal(_) => {},
+            Expr::Local(_) => {},
+            Expr::Global(name, generics) => f(*name, generics),
+            Expr::Intrinsic(_, _, args) => args
+                .iter_mut()
+                .for_each(|x| x.for_each_global_mut(f)),
+            Expr::Unary(_, x) => x.for_each_global_mut(f),
+            Expr::Binary(_, x, y) => {
+                x.for_each_global_mut(f);
+                y.for_each_global_mut(f);
+            },
+            Expr::List(items) | Expr::Tuple(items) => items
+                .iter_mut()
+                .for_each(|item| item.for_each_global_mut(f)),
+            Expr::Record(fields) => fields
+                .iter_mut()
+                .for_each(|(_, value)| value.for_each_global_mut(f)),
+            Expr::Func(_, body) => body.for_each_global_mut(f),
+            Expr::Apply(g, arg) => {
+                g.for_each_global_mut(f);
+                arg.for_each_global_mut(f);
+            },
+            Expr::Access(record, _) => record.for_each_global_mut(f),
+            Expr::Update(record, _, value) => {
+                record.for_each_global_mut(f);
+                value.for_each_global_mut(f);
+            },
+            Expr::Match(pred, arms) => {
+                pred.for_each_global_mut(f);
+                for (_, guard, arm) in arms.iter_mut() {
+                    if let Some(guard) = guard {
+                        guard.for_each_global_mut(f);
+                    }
+                    arm.for_each_global_mut(f);
+                }
+            },
+            Expr::Constructor(_, _, inner) => inner.for_each_global_mut(f),
+            Expr::Member(_, _, _) => {},
+        }
+    }
+
+    pub(crate) fn visit(&self) -> impl Iterator<Item=&Self> + '_ {
         let mut stack = vec![self];
         std::iter::from_fn(move || stack
             .pop()
@@ -1105,14 +1706,219 @@ impl TypeExpr {
                     },
                     Expr::Match(pred, arms) => {
                         stack.push(pred);
-                        for (_, arm) in arms.iter() {
+                        for (_, guard, arm) in arms.iter() {
+                            stack.extend(guard);
                             stack.push(arm);
                         }
                     },
                     Expr::Constructor(_, _, inner) => stack.push(inner),
+                    Expr::Member(_, _, _) => {},
                 }
 
                 expr
             }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lex, src::FileId};
+
+    fn def_type(src: &str, name: &str) -> String {
+        let (module, errors) = ast::parse_module(&lex::lex(src, FileId::default()).unwrap());
+        assert_eq!(errors.len(), 0);
+        let prog = Program::new_root(&module).unwrap_or_else(|errors| panic!("{:?}", errors));
+        let def = prog.root().def(Ident::new(name.to_string())).unwrap();
+        format!("{}", **def.body.ty())
+    }
+
+    // Check a module that has access to the real prelude
+    fn check_with_prelude(src: &str) -> Result<Program, Vec<Error>> {
+        use crate::{module::ModuleTree, src::SourceMap};
+
+        let mut srcs = SourceMap::default();
+        let file = srcs.add("main.tao", src);
+        let (root, errors) = ast::parse_module(&lex::lex(srcs.code(file), file).unwrap());
+        assert_eq!(errors.len(), 0);
+        let prelude = crate::parse_prelude(&mut srcs).unwrap();
+        let (_, module, errors) = ModuleTree::load(&mut srcs, root, None, prelude);
+        assert_eq!(errors.len(), 0);
+        Program::new_root(&module)
+    }
+
+    #[test]
+    fn mutual_recursion() {
+        let src = "
+            fn is_even |n| if n = 0 then true else is_odd(n - 1)
+            fn is_odd |n| if n = 0 then false else is_even(n - 1)
+        ";
+        assert_eq!(def_type(src, "is_even"), "(Num -> Bool)");
+        assert_eq!(def_type(src, "is_odd"), "(Num -> Bool)");
+    }
+
+    #[test]
+    fn generalise_def() {
+        let src = "
+            def main = (twice(|x| x + 1, 1), twice(|c| c, 'a'))
+            fn twice |f, x| f(f(x))
+        ";
+        assert_eq!(def_type(src, "twice"), "(('a -> 'a) -> ('a -> 'a))");
+        assert_eq!(def_type(src, "main"), "(Num, Char)");
+    }
+
+    #[test]
+    fn generalise_let() {
+        let src = "
+            def main = let id = |x| x in (id(1), id(true))
+        ";
+        assert_eq!(def_type(src, "main"), "(Num, Bool)");
+    }
+
+    #[test]
+    fn class_instances() {
+        let src = "
+            class Default A
+                member default of A
+            instance Default of Num
+                member default = 0
+            instance Default A given Default A of [A]
+                member default = [default]
+            def main = [default] ++ [[1]]
+            fn wrap |x| [x, default]
+        ";
+        assert_eq!(def_type(src, "main"), "[[Num]]");
+        assert_eq!(def_type(src, "wrap"), "('a -> ['a])");
+
+        let prog = Program::new_root(&ast::parse_module(&lex::lex(src, FileId::default()).unwrap()).0).unwrap();
+        let wrap = prog.root().def(Ident::new("wrap".to_string())).unwrap();
+        assert_eq!(wrap.given.len(), 1);
+        assert_eq!(wrap.given[0].0.as_str(), "Default");
+    }
+
+    #[test]
+    fn missing_instance() {
+        let src = "
+            class Default A
+                member default of A
+            instance Default of Num
+                member default = 0
+            def main = if default then 1 else 2
+        ";
+        let (module, _) = ast::parse_module(&lex::lex(src, FileId::default()).unwrap());
+        assert!(Program::new_root(&module).is_err());
+    }
+
+    #[test]
+    fn prelude_classes() {
+        // Instances for lists, tuples and `Maybe` require instances for their items
+        let src = "
+            def main = [
+                eq([(1, 'a'), (2, 'b')], [(1, 'a'), (2, 'b')]),
+                eq([1, 2], [1, 3]),
+                eq(Just \"a\", Just \"a\"),
+                cmp(\"abc\", \"abd\") = Less,
+                cmp([1, 2], [1]) = Greater,
+                max((2, \"a\"), (1, \"z\")) = (2, \"a\"),
+                min([(1, true)], [(1, false), (0, true)]) = [(1, false), (0, true)],
+                cmp(None, Just 0) = Less,
+                max(Just (1, 'b'), Just (1, 'a')) = Just (1, 'b'),
+                add(mul(2, 3), neg(1)) = 5
+            ]
+        ";
+        let prog = check_with_prelude(src).unwrap_or_else(|errors| panic!("{:?}", errors));
+        let main = prog.root().def(Ident::new("main".to_string())).unwrap();
+        assert_eq!(format!("{}", **main.body.ty()), "[Bool]");
+
+        // Functions have no `Eq` instance
+        let errors = check_with_prelude("def main = eq(|x| x, |x| x)").map(|_| ()).unwrap_err();
+        assert!(errors
+            .iter()
+            .any(|err| err.code() == Some("E0300") && err.msg().contains("is not an instance of class 'prelude::Eq'")), "{:?}", errors);
+    }
+
+    #[test]
+    fn structural_comparison() {
+        let src = "
+            data Shape = | Circle Num | Square Num
+            def main = ([1, 2] = [1, 2], (1, 'a') < (1, 'b'), Circle 1 != Square 1, { x: true } >= { x: false })
+        ";
+        assert_eq!(def_type(src, "main"), "(Bool, Bool, Bool, Bool)");
+
+        let src = "
+            def main = [|x| x + 1] = [|x| x]
+        ";
+        let (module, _) = ast::parse_module(&lex::lex(src, FileId::default()).unwrap());
+        assert!(Program::new_root(&module).is_err());
+    }
+
+    #[test]
+    fn or_and_range_patterns() {
+        let src = "
+            fn value |x| match x {
+                | (0, n) | (n, 0) => n
+                | (1..9, _) => 1
+                | _ => 0
+            }
+        ";
+        assert_eq!(def_type(src, "value"), "((Num, Num) -> Num)");
+
+        let codes = |src: &str| {
+            let (module, _) = ast::parse_module(&lex::lex(src, FileId::default()).unwrap());
+            Program::new_root(&module)
+                .err()
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|error| error.code())
+                .collect::<Vec<_>>()
+        };
+        assert!(codes("fn f |x| match x {\n\t| (0, n) | (m, 0) => 0\n\t| _ => 1\n}").contains(&"E0402"));
+        assert!(codes("fn f |x| match x {\n\t| 5..1 => 0\n\t| _ => 1\n}").contains(&"E0403"));
+        assert!(codes("fn f |x| match x {\n\t| true..false => 0\n\t| _ => 1\n}").contains(&"E0403"));
+    }
+
+    #[test]
+    fn guards() {
+        // Guards may use the bindings of their arm
+        let src = "
+            fn sign |n| match n {
+                | n if n < 0 => -1
+                | _ => 1
+            }
+        ";
+        assert_eq!(def_type(src, "sign"), "(Num -> Num)");
+
+        // Guards must be of type `Bool`
+        let src = "
+            fn f |x| match x {
+                | x if 1 => x
+                | _ => 0
+            }
+        ";
+        let (module, _) = ast::parse_module(&lex::lex(src, FileId::default()).unwrap());
+        let errors = Program::new_root(&module).err().unwrap_or_default();
+        assert!(errors.iter().any(|err| err.code() == Some("E0200")), "{:?}", errors);
+    }
+
+    #[test]
+    fn independent_errors() {
+        // Each broken item is used by another item, which must not be reported again
+        let src = "
+            def bad_def = if 1 then 2 else 3
+            def uses_def = bad_def + 1
+            data Broken = (Num, Missing)
+            data UsesBroken = | Wrap Broken
+            def uses_data of Broken -> Num = |_| 0
+            type BadAlias = [Nope]
+            type UsesAlias = (BadAlias, Num)
+            def uses_alias of BadAlias = []
+            def partial = match true { | true => 1 }
+        ";
+        let (module, errors) = ast::parse_module(&lex::lex(src, FileId::default()).unwrap());
+        assert_eq!(errors.len(), 0);
+        let (_, errors) = Program::new_root_partial(&module, &Natives::default());
+        assert!(errors.iter().all(|err| !err.is_poisoned()));
+        assert_eq!(errors.len(), 4, "{:?}", errors);
+        assert!(errors.iter().any(|err| err.code() == Some("E0400")));
+    }
+}
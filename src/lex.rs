@@ -5,7 +5,7 @@ use std::{
 use parze::prelude::*;
 use internment::LocalIntern;
 use crate::{
-    src::Span,
+    src::{Span, Loc, FileId},
     node::SrcNode,
     error::Error,
 };
@@ -30,6 +30,7 @@ pub enum Op {
 
     Join,
     Ellipsis,
+    Range,
 
     Not,
 }
@@ -52,6 +53,7 @@ impl fmt::Display for Op {
             Op::Or => write!(f, "or"),
             Op::Join => write!(f, "++"),
             Op::Ellipsis => write!(f, "..."),
+            Op::Range => write!(f, ".."),
             Op::Not => write!(f, "!"),
         }
     }
@@ -65,7 +67,7 @@ pub enum Delimiter {
 }
 
 impl Delimiter {
-    fn left(&self) -> char {
+    pub fn left(&self) -> char {
         match self {
             Delimiter::Paren => '(',
             Delimiter::Brack => '[',
@@ -73,7 +75,7 @@ impl Delimiter {
         }
     }
 
-    fn right(&self) -> char {
+    pub fn right(&self) -> char {
         match self {
             Delimiter::Paren => ')',
             Delimiter::Brack => ']',
@@ -124,6 +126,17 @@ pub enum Token {
     Data,
     Do,
     Return,
+    Class,
+    Member,
+    Instance,
+    Mod,
+    Use,
+
+    // Only produced by `lex_with_comments`, so the parser never sees it
+    Comment(LocalIntern<String>),
+
+    // Never produced by the lexer: stands in for tokens that failed to parse during error recovery
+    Error,
 }
 
 impl Token {
@@ -180,11 +193,18 @@ impl fmt::Display for Token {
             Token::Data => write!(f, "data"),
             Token::Do => write!(f, "do"),
             Token::Return => write!(f, "return"),
+            Token::Class => write!(f, "class"),
+            Token::Member => write!(f, "member"),
+            Token::Instance => write!(f, "instance"),
+            Token::Mod => write!(f, "mod"),
+            Token::Use => write!(f, "use"),
+            Token::Comment(text) => write!(f, "#{}", text),
+            Token::Error => write!(f, "<error>"),
         }
     }
 }
 
-pub fn lex(code: &str) -> Result<Vec<SrcNode<Token>>, Vec<Error>> {
+pub fn lex(code: &str, file: FileId) -> Result<Vec<SrcNode<Token>>, Vec<Error>> {
     let tokens = recursive(|tokens| {
         let whitespace = permit(|c: &char| c.is_whitespace()).to(())
             .or(just('#').padding_for(permit(|c: &char| *c != '\n').repeated()).to(()));
@@ -248,6 +268,7 @@ pub fn lex(code: &str) -> Result<Vec<SrcNode<Token>>, Vec<Error>> {
             .or(seq("=>".chars()).to(Token::RMap))
             .or(seq("++".chars()).to(Token::Op(Op::Join)))
             .or(seq("...".chars()).to(Token::Op(Op::Ellipsis)))
+            .or(seq("..".chars()).to(Token::Op(Op::Range)))
             .or(just('+').to(Token::Op(Op::Add)))
             .or(just('-').to(Token::Op(Op::Sub)))
             .or(just('*').to(Token::Op(Op::Mul)))
@@ -307,6 +328,11 @@ pub fn lex(code: &str) -> Result<Vec<SrcNode<Token>>, Vec<Error>> {
                 "or" => Token::Op(Op::Or),
                 "do" => Token::Do,
                 "return" => Token::Return,
+                "class" => Token::Class,
+                "member" => Token::Member,
+                "instance" => Token::Instance,
+                "mod" => Token::Mod,
+                "use" => Token::Use,
                 _ => Token::Ident(LocalIntern::new(s)),
             }))
             .or(op)
@@ -320,4 +346,67 @@ pub fn lex(code: &str) -> Result<Vec<SrcNode<Token>>, Vec<Error>> {
     tokens
         .padded_by(end())
         .parse(code.chars())
+        .map(|tokens| in_file(tokens, file))
+        .map_err(|errors| errors
+            .into_iter()
+            .map(|error| error.in_file(file))
+            .collect())
+}
+
+// Lex the code, keeping comments as tokens so that the source can be laid out again without losing them
+pub fn lex_with_comments(code: &str, file: FileId) -> Result<Vec<SrcNode<Token>>, Vec<Error>> {
+    let tokens = lex(code, file)?;
+    let chars = code.chars().collect::<Vec<_>>();
+    Ok(with_comments(tokens, &chars, (0, chars.len()), file))
+}
+
+// Everything between tokens is whitespace or comments, so the comments can be found in the gaps
+fn with_comments(
+    tokens: Vec<SrcNode<Token>>,
+    chars: &[char],
+    (from, until): (usize, usize),
+    file: FileId,
+) -> Vec<SrcNode<Token>> {
+    let mut output = Vec::new();
+    let mut pos = from;
+    for token in tokens {
+        let (start, end) = token.span().bounds().unwrap_or((pos, pos));
+        comments(chars, (pos, start), file, &mut output);
+        pos = end;
+        output.push(token.map_inner(|token| match token {
+            Token::Tree(delim, tokens) => Token::Tree(delim, with_comments(tokens, chars, (start + 1, end - 1), file)),
+            token => token,
+        }));
+    }
+    comments(chars, (pos, until), file, &mut output);
+    output
+}
+
+fn comments(chars: &[char], (from, until): (usize, usize), file: FileId, output: &mut Vec<SrcNode<Token>>) {
+    let mut pos = from;
+    while pos < until {
+        if chars[pos] == '#' {
+            let start = pos;
+            while pos < until && chars[pos] != '\n' {
+                pos += 1;
+            }
+            let text = chars[start + 1..pos].iter().collect::<String>();
+            output.push(Token::Comment(LocalIntern::new(text)).at(Span::range(Loc::at(start), Loc::at(pos)).in_file(file)));
+        } else {
+            pos += 1;
+        }
+    }
+}
+
+// Spans produced by the lexer do not know which file they came from, so attach it afterwards
+fn in_file(tokens: Vec<SrcNode<Token>>, file: FileId) -> Vec<SrcNode<Token>> {
+    tokens
+        .into_iter()
+        .map(|token| token
+            .map_meta(|span| span.in_file(file))
+            .map_inner(|token| match token {
+                Token::Tree(delim, tokens) => Token::Tree(delim, in_file(tokens, file)),
+                token => token,
+            }))
+        .collect()
 }
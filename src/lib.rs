@@ -1,67 +1,155 @@
 #![type_length_limit = "10823821"]
 #![feature(arbitrary_self_types, arbitrary_enum_discriminant)]
 
+pub mod analysis;
 mod ast;
+pub mod embed;
 pub mod error;
+pub mod format;
 mod hir;
 mod lex;
+#[cfg(feature = "lsp")]
+pub mod lsp;
 mod mir;
+mod module;
 mod node;
-mod src;
+pub mod repl;
+pub mod src;
 mod ty;
 pub mod vm;
 
+use std::{path::Path, str::FromStr};
 use crate::{
     error::Error,
     node::SrcNode,
+    src::{SourceMap, FileId},
+    module::ModuleTree,
 };
 use internment::LocalIntern;
 
 // TODO: Make this not hacky
-fn parse_prelude() -> Result<SrcNode<ast::Module>, Vec<Error>> {
-    ast::parse_module(&lex::lex(include_str!("tao/prelude.tao"))?)
+fn add_prelude(srcs: &mut SourceMap) -> FileId {
+    srcs.add("prelude.tao", include_str!("tao/prelude.tao"))
 }
 
-pub fn run_module(src: &str) -> Result<Option<vm::Value>, Vec<Error>> {
-    let tokens = lex::lex(&src)?;
-    let mut ast = parse_prelude()?;
-    ast.decls.append(&mut ast::parse_module(&tokens)?.decls);
-    let hir_prog = hir::Program::new_root(&ast)?;
-
-    // TODO: Get rid of this
-    let main_ident = LocalIntern::new("main".to_string());
+fn parse_prelude(srcs: &mut SourceMap) -> Result<SrcNode<ast::Module>, Vec<Error>> {
+    let file = add_prelude(srcs);
+    parse_file(srcs, file)
+}
 
-    let mir_prog = mir::Program::from_hir(&hir_prog, main_ident).map_err(|e| vec![e])?;
+// Parse the module in `file`, failing if any part of it could not be parsed
+fn parse_file(srcs: &SourceMap, file: FileId) -> Result<SrcNode<ast::Module>, Vec<Error>> {
+    let (module, errors) = ast::parse_module(&lex::lex(srcs.code(file), file)?);
+    if errors.len() == 0 {
+        Ok(module)
+    } else {
+        Err(errors)
+    }
+}
 
-    let prog = mir_prog.compile(false).map_err(|e| vec![e])?;
+// Run the module in `file`, loading any submodules it declares from `dir` and performing IO with `io`
+pub fn run_module(
+    srcs: &mut SourceMap,
+    file: FileId,
+    dir: Option<&Path>,
+    io: &mut dyn vm::IoHandler,
+) -> Result<Option<vm::Value>, Vec<Error>> {
+    embed::Module::compile(srcs, file, dir)?
+        .def("main")?
+        .run(io)
+}
 
-    //println!("{:?}", prog);
+// Evaluate the expression in `file`
+pub fn run_expr(srcs: &mut SourceMap, file: FileId) -> Result<(embed::TypeDesc, vm::Value), Vec<Error>> {
+    let main = embed::Module::compile_expr(srcs, file)?.def("main")?;
+    Ok((main.ty().clone(), main.eval()?))
+}
 
-    Ok(vm::Vm::default().execute(&prog))
+// A stage of compilation whose output may be dumped
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Stage {
+    Tokens,
+    Ast,
+    Hir,
+    Mir,
+    Bytecode,
 }
 
-pub fn run_expr(src: &str) -> Result<(ty::Type, vm::Value), Vec<Error>> {
-    let tokens = lex::lex(&src)?;
-    let mut hir_prog = hir::Program::new_root(&parse_prelude()?)?;
-    hir_prog
-        .insert_def(&ast::Def::main(ast::parse_expr(&tokens)?))
-        .map_err(|e| vec![e])?;
+impl FromStr for Stage {
+    type Err = String;
 
-    // TODO: Get rid of this
-    let main_ident = LocalIntern::new("main".to_string());
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tokens" => Ok(Stage::Tokens),
+            "ast" => Ok(Stage::Ast),
+            "hir" => Ok(Stage::Hir),
+            "mir" => Ok(Stage::Mir),
+            "bytecode" => Ok(Stage::Bytecode),
+            _ => Err(format!("Unknown stage '{}', expected tokens, ast, hir, mir or bytecode", s)),
+        }
+    }
+}
+
+// Produce a readable dump of the module in `file` after the given stage of compilation. The mir and bytecode stages
+// only include what is reachable from `main`.
+pub fn dump(srcs: &mut SourceMap, file: FileId, dir: Option<&Path>, stage: Stage) -> Result<String, Vec<Error>> {
+    let tokens = lex::lex(srcs.code(file), file)?;
+    if stage == Stage::Tokens {
+        return Ok(tokens
+            .iter()
+            .map(|token| format!("{:?}\n", token))
+            .collect());
+    }
 
-    let mir_prog = mir::Program::from_hir(&hir_prog, main_ident).map_err(|e| vec![e])?;
-    let prog = mir_prog.compile(true).map_err(|e| vec![e])?;
+    let (root, mut errors) = ast::parse_module(&tokens);
+    if stage == Stage::Ast {
+        return if errors.len() == 0 {
+            Ok(format!("{:#?}\n", root.inner()))
+        } else {
+            Err(errors)
+        };
+    }
 
-    Ok((
-        hir_prog
+    let prelude = parse_prelude(srcs)?;
+    let (_, ast, mut module_errors) = ModuleTree::load(srcs, root, dir, prelude);
+    errors.append(&mut module_errors);
+    if errors.len() > 0 {
+        return Err(errors);
+    }
+    let hir_prog = hir::Program::new_root(&ast)?;
+    if stage == Stage::Hir {
+        let mut defs = hir_prog
             .root()
-            .def(main_ident)
-            .unwrap()
-            .body
-            .ty()
-            .inner()
-            .clone(),
-        vm::Vm::default().execute(&prog).unwrap(),
-    ))
+            .defs()
+            .filter(|def| !def.name.starts_with("prelude::"))
+            .collect::<Vec<_>>();
+        defs.sort_by(|a, b| a.name.as_str().cmp(b.name.as_str()));
+        return Ok(defs
+            .into_iter()
+            .map(|def| format!(
+                "def {} of {} = {:#?}\n",
+                *def.name,
+                embed::describe(&hir_prog.data_ctx, def.body.ty()),
+                def.body,
+            ))
+            .collect());
+    }
+
+    let mir_prog = mir::Program::from_hir(&hir_prog, LocalIntern::new("main".to_string()), Vec::new())?;
+    if stage == Stage::Mir {
+        let mut globals = mir_prog.globals().collect::<Vec<_>>();
+        globals.sort_by(|(a, _), (b, _)| a.0.as_str().cmp(b.0.as_str()));
+        return Ok(globals
+            .into_iter()
+            .map(|(id, global)| format!(
+                "def {}{} of {} = {:#?}\n",
+                *id.0,
+                id.1.iter().map(|ty| format!(" {}", ty.mangle())).collect::<String>(),
+                global.ty().mangle(),
+                global,
+            ))
+            .collect());
+    }
+
+    Ok(format!("{:?}", mir_prog.compile(false)?))
 }
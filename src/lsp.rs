@@ -0,0 +1,412 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, BufRead, Read, Write},
+    path::PathBuf,
+};
+use serde_json::{json, Value as Json};
+use crate::{
+    analysis::{Analysis, Symbol, SymbolKind},
+    error::DiagnosticSpan,
+    src::Span,
+};
+
+// A language server, communicating with JSON-RPC messages. Documents are synchronised in full whenever they change.
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, Analysis>,
+    // Documents that diagnostics have been published for, which must be cleared when the diagnostics are fixed
+    published: HashSet<String>,
+    shutdown: bool,
+}
+
+impl Server {
+    // Handle messages from `input` until the client asks the server to exit
+    pub fn serve(&mut self, mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        while let Some(msg) = read_message(&mut input)? {
+            if msg["method"] == "exit" {
+                break;
+            }
+            for reply in self.handle(&msg) {
+                write_message(&mut output, &reply)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Handle a request or notification, producing the messages to send in response
+    pub fn handle(&mut self, msg: &Json) -> Vec<Json> {
+        let method = msg["method"].as_str().unwrap_or("");
+        let params = &msg["params"];
+        let mut notifications = Vec::new();
+
+        let result = match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "documentSymbolProvider": true,
+                },
+                "serverInfo": { "name": "tao-lsp" },
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Json::Null)
+            },
+            _ if self.shutdown => Err((-32600, format!("The server has been shut down"))),
+            "textDocument/didOpen" => {
+                let doc = &params["textDocument"];
+                self.update(&mut notifications, doc["uri"].as_str().unwrap_or(""), doc["text"].as_str().unwrap_or(""));
+                Ok(Json::Null)
+            },
+            "textDocument/didChange" => {
+                // The whole document is sent with each change, so only the last change matters
+                let text = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str());
+                if let Some(text) = text {
+                    self.update(&mut notifications, params["textDocument"]["uri"].as_str().unwrap_or(""), text);
+                }
+                Ok(Json::Null)
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(params["textDocument"]["uri"].as_str().unwrap_or(""));
+                self.publish(&mut notifications);
+                Ok(Json::Null)
+            },
+            "textDocument/hover" => Ok(self.document(params)
+                .and_then(|(analysis, loc)| {
+                    let (span, desc) = analysis.hover(loc)?;
+                    Some(json!({
+                        "contents": { "kind": "markdown", "value": format!("```tao\n{}\n```", desc) },
+                        "range": range(analysis, span)?,
+                    }))
+                })
+                .unwrap_or(Json::Null)),
+            "textDocument/definition" => Ok(self.document(params)
+                .and_then(|(analysis, loc)| location(analysis, analysis.definition(loc)?))
+                .unwrap_or(Json::Null)),
+            "textDocument/documentSymbol" => Ok(self.documents
+                .get(params["textDocument"]["uri"].as_str().unwrap_or(""))
+                .map(|analysis| Json::Array(analysis
+                    .symbols()
+                    .iter()
+                    .filter_map(|symbol| document_symbol(analysis, symbol))
+                    .collect()))
+                .unwrap_or(Json::Null)),
+            _ => Err((-32601, format!("Unsupported method '{}'", method))),
+        };
+
+        // Notifications have no id, and must not be replied to
+        match msg.get("id") {
+            Some(id) => notifications.push(match result {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Err((code, message)) => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": code, "message": message },
+                }),
+            }),
+            None => {},
+        }
+        notifications
+    }
+
+    fn update(&mut self, notifications: &mut Vec<Json>, uri: &str, text: &str) {
+        let path = uri_to_path(uri);
+        let name = path
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_else(|| uri.to_string());
+        let dir = path.as_ref().and_then(|path| path.parent());
+        self.documents.insert(uri.to_string(), Analysis::new(&name, text, dir));
+        self.publish(notifications);
+    }
+
+    // Publish the diagnostics of every open document, clearing any that no longer apply
+    fn publish(&mut self, notifications: &mut Vec<Json>) {
+        let mut diagnostics = HashMap::<String, Vec<Json>>::new();
+        for (uri, analysis) in self.documents.iter() {
+            diagnostics.entry(uri.clone()).or_default();
+            let doc_name = analysis.srcs().name(analysis.file());
+            for diag in analysis.diagnostics() {
+                // Errors are reported in the file that their primary span belongs to, if it has one
+                let primary = diag.spans
+                    .iter()
+                    .find(|span| span.primary)
+                    .cloned();
+                let (file_uri, span_range) = match &primary {
+                    Some(span) if span.file == doc_name => (uri.clone(), lsp_range(analysis, span)),
+                    Some(span) => match path_to_uri(&span.file) {
+                        Some(file_uri) => (file_uri, lsp_range(analysis, span)),
+                        None => continue,
+                    },
+                    None => (uri.clone(), json!({
+                        "start": { "line": 0, "character": 0 },
+                        "end": { "line": 0, "character": 0 },
+                    })),
+                };
+                let mut message = diag.message.clone();
+                for hint in diag.hints.iter() {
+                    message += &format!("\nHint: {}", hint);
+                }
+                diagnostics.entry(file_uri).or_default().push(json!({
+                    "range": span_range,
+                    "severity": match diag.severity {
+                        crate::error::Severity::Error => 1,
+                        crate::error::Severity::Warning => 2,
+                    },
+                    "code": diag.code,
+                    "source": "tao",
+                    "message": message,
+                }));
+            }
+        }
+
+        for uri in self.published.drain() {
+            diagnostics.entry(uri).or_default();
+        }
+        let mut uris = diagnostics.keys().cloned().collect::<Vec<_>>();
+        uris.sort();
+        for uri in uris {
+            let diags = diagnostics.remove(&uri).unwrap();
+            if diags.len() > 0 {
+                self.published.insert(uri.clone());
+            }
+            notifications.push(json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/publishDiagnostics",
+                "params": { "uri": uri, "diagnostics": diags },
+            }));
+        }
+    }
+
+    // Find the document and location that a request refers to
+    fn document(&self, params: &Json) -> Option<(&Analysis, crate::src::Loc)> {
+        let analysis = self.documents.get(params["textDocument"]["uri"].as_str()?)?;
+        let pos = &params["position"];
+        let line = pos["line"].as_u64()? as usize;
+        let code = analysis.srcs().code(analysis.file());
+        let loc = analysis.loc(line, char_column(code, line, pos["character"].as_u64()? as usize));
+        Some((analysis, loc))
+    }
+}
+
+// Read a message, or `None` if the input has ended
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut len = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.len() == 0 {
+            break;
+        } else if header.to_ascii_lowercase().starts_with("content-length:") {
+            len = header["content-length:".len()..].trim().parse::<usize>().ok();
+        }
+    }
+
+    let len = len.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Message has no Content-Length header"))?;
+    let mut body = vec![0; len];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn write_message(output: &mut impl Write, msg: &Json) -> io::Result<()> {
+    let body = msg.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    if !uri.starts_with("file://") {
+        return None;
+    }
+    // Decode percent-encoded bytes, such as spaces
+    let encoded = uri["file://".len()..].as_bytes();
+    let mut bytes = Vec::new();
+    let mut i = 0;
+    while i < encoded.len() {
+        if encoded[i] == b'%' && i + 2 < encoded.len() {
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&encoded[i + 1..i + 3]).ok()?, 16).ok()?);
+            i += 3;
+        } else {
+            bytes.push(encoded[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+// Only files on disk have URIs, so files such as the prelude cannot be referred to
+fn path_to_uri(path: &str) -> Option<String> {
+    if !path.starts_with('/') {
+        return None;
+    }
+    Some(format!("file://{}", path
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            byte => format!("%{:02X}", byte),
+        })
+        .collect::<String>()))
+}
+
+// LSP columns count UTF-16 code units, but Tao columns count characters
+fn utf16_column(code: &str, line: usize, column: usize) -> usize {
+    code
+        .lines()
+        .nth(line)
+        .map_or(column, |text| text.chars().take(column).map(char::len_utf16).sum())
+}
+
+fn char_column(code: &str, line: usize, units: usize) -> usize {
+    code
+        .lines()
+        .nth(line)
+        .map_or(units, |text| {
+            let mut end = 0;
+            text
+                .chars()
+                .take_while(|c| {
+                    end += c.len_utf16();
+                    end <= units
+                })
+                .count()
+        })
+}
+
+// Diagnostic positions start at 1, but LSP positions start at 0
+fn lsp_range(analysis: &Analysis, span: &DiagnosticSpan) -> Json {
+    let code = analysis.srcs()
+        .find(&span.file)
+        .map_or("", |file| analysis.srcs().code(file));
+    let (start_line, end_line) = (span.start.line - 1, span.end.line - 1);
+    json!({
+        "start": { "line": start_line, "character": utf16_column(code, start_line, span.start.column - 1) },
+        "end": { "line": end_line, "character": utf16_column(code, end_line, span.end.column - 1) },
+    })
+}
+
+fn range(analysis: &Analysis, span: Span) -> Option<Json> {
+    let (_, (start_line, start_col), (end_line, end_col)) = analysis.resolve(span)?;
+    let code = analysis.srcs().code(span.file()?);
+    Some(json!({
+        "start": { "line": start_line, "character": utf16_column(code, start_line, start_col) },
+        "end": { "line": end_line, "character": utf16_column(code, end_line, end_col) },
+    }))
+}
+
+fn location(analysis: &Analysis, span: Span) -> Option<Json> {
+    let (name, _, _) = analysis.resolve(span)?;
+    Some(json!({ "uri": path_to_uri(name)?, "range": range(analysis, span)? }))
+}
+
+fn document_symbol(analysis: &Analysis, symbol: &Symbol) -> Option<Json> {
+    Some(json!({
+        "name": symbol.name,
+        "kind": match symbol.kind {
+            SymbolKind::Module => 2,
+            SymbolKind::Class => 5,
+            SymbolKind::Member => 6,
+            SymbolKind::Data => 10,
+            SymbolKind::Function => 12,
+            SymbolKind::Constant => 14,
+            SymbolKind::Instance => 19,
+            SymbolKind::Variant => 22,
+            SymbolKind::TypeAlias => 26,
+        },
+        "range": range(analysis, symbol.span)?,
+        "selectionRange": range(analysis, symbol.name_span)?,
+        "children": symbol.children
+            .iter()
+            .filter_map(|child| document_symbol(analysis, child))
+            .collect::<Vec<_>>(),
+    }))
+}
+
+#[test]
+fn scripted_client() {
+    let uri = "file:///tmp/tao%20lsp/main.tao";
+    let text = "data Shape = | Circle Num | Square Num\ndef area = |s| match s {\n    | Circle r => r * r * 3\n    | Square w => w * w\n}\ndef main = area(Circle 2)\ndef broken = 1 + true\n";
+    let fixed = "data Shape = | Circle Num | Square Num\ndef area = |s| match s {\n    | Circle r => r * r * 3\n    | Square w => w * w\n}\ndef main = area(Circle 2)\n";
+    let requests = vec![
+        json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+        json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+        json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+            "textDocument": { "uri": uri, "languageId": "tao", "version": 1, "text": text },
+        } }),
+        json!({ "jsonrpc": "2.0", "id": 2, "method": "textDocument/hover", "params": {
+            "textDocument": { "uri": uri }, "position": { "line": 2, "character": 18 },
+        } }),
+        json!({ "jsonrpc": "2.0", "id": 3, "method": "textDocument/definition", "params": {
+            "textDocument": { "uri": uri }, "position": { "line": 5, "character": 12 },
+        } }),
+        json!({ "jsonrpc": "2.0", "id": 4, "method": "textDocument/documentSymbol", "params": {
+            "textDocument": { "uri": uri },
+        } }),
+        json!({ "jsonrpc": "2.0", "method": "textDocument/didChange", "params": {
+            "textDocument": { "uri": uri, "version": 2 }, "contentChanges": [{ "text": fixed }],
+        } }),
+        json!({ "jsonrpc": "2.0", "id": 5, "method": "shutdown" }),
+        json!({ "jsonrpc": "2.0", "method": "exit" }),
+    ];
+
+    let mut input = Vec::new();
+    for request in requests.iter() {
+        write_message(&mut input, request).unwrap();
+    }
+    let mut output = Vec::new();
+    Server::default().serve(io::Cursor::new(input), &mut output).unwrap();
+
+    let mut output = io::Cursor::new(output);
+    let mut replies = Vec::new();
+    while let Some(reply) = read_message(&mut output).unwrap() {
+        replies.push(reply);
+    }
+    let reply = |id: u64| replies
+        .iter()
+        .find(|reply| reply["id"] == id)
+        .unwrap_or_else(|| panic!("No reply to request {}", id));
+    let diagnostics = replies
+        .iter()
+        .filter(|reply| reply["method"] == "textDocument/publishDiagnostics")
+        .map(|reply| reply["params"]["diagnostics"].as_array().unwrap().len())
+        .collect::<Vec<_>>();
+
+    assert_eq!(reply(1)["result"]["capabilities"]["hoverProvider"], true);
+    // The error is reported, and then cleared once fixed
+    assert_eq!(diagnostics, vec![1, 0]);
+    assert_eq!(reply(2)["result"]["contents"]["value"], "```tao\nr of Num\n```");
+    assert_eq!(reply(3)["result"]["uri"], uri);
+    assert_eq!(reply(3)["result"]["range"]["start"], json!({ "line": 1, "character": 4 }));
+    assert_eq!(reply(4)["result"].as_array().unwrap().len(), 4);
+    assert_eq!(reply(4)["result"][0]["children"][1]["name"], "Square");
+    assert_eq!(reply(5)["result"], Json::Null);
+}
+
+#[test]
+fn utf16_positions() {
+    let uri = "file:///tmp/wide.tao";
+    let text = "def e = \"\u{1F600}\" def x = 1\ndef y = (\"\u{1F600}\", x)\n";
+    let mut server = Server::default();
+    server.handle(&json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+        "textDocument": { "uri": uri, "languageId": "tao", "version": 1, "text": text },
+    } }));
+    // The emoji is a single character, but two UTF-16 code units
+    let reply = server.handle(&json!({ "jsonrpc": "2.0", "id": 1, "method": "textDocument/definition", "params": {
+        "textDocument": { "uri": uri }, "position": { "line": 1, "character": 15 },
+    } }));
+    assert_eq!(reply[0]["result"]["range"]["start"], json!({ "line": 0, "character": 17 }));
+    assert_eq!(reply[0]["result"]["range"]["end"], json!({ "line": 0, "character": 18 }));
+
+    assert_eq!(utf16_column("a\u{1F600}b", 0, 2), 3);
+    assert_eq!(char_column("a\u{1F600}b", 0, 3), 2);
+    // Positions within a character refer to the character
+    assert_eq!(char_column("a\u{1F600}b", 0, 2), 1);
+}
@@ -1,39 +1,370 @@
 use rustyline::Editor;
-use std::{env, fs::File, io::Read};
-use tao::{run_expr, run_module};
+use std::{env, fs, path::Path, process};
+use tao::{
+    dump,
+    format::format,
+    embed::{Module, TypeDesc},
+    error::Error,
+    repl::{Session, Output},
+    src::{SourceMap, FileId},
+    vm::{Natives, StdIo},
+    Stage,
+};
+
+const USAGE: &str = "\
+Usage:
+    tao                                 Start the REPL
+    tao run <file> [args...]            Run a program, passing it the given arguments
+    tao check <file>                    Type-check a program without running it
+    tao dump --stage <stage> <file>     Show a program after a stage of compilation
+                                        (tokens, ast, hir, mir or bytecode)
+    tao test <file>                     Run the `test_*` definitions of a program, which must be of type `Bool`
+    tao fmt [--check] <files...>        Format programs in place, or with `--check` list those that need formatting
+
+Options:
+    --error-format <human|json>         Report errors as text, or as one JSON object per line";
+
+// How errors are written to stderr
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum ErrorFormat {
+    Human,
+    Json,
+}
 
 fn main() {
-    if let Some(filename) = env::args().nth(1) {
-        let mut src = String::new();
-        File::open(&filename)
-            .and_then(|mut file| file.read_to_string(&mut src))
-            .unwrap_or_else(|err| panic!("Could not read file '{}': {:?}", filename, err));
-
-        match run_module(&src) {
-            Ok(Some(val)) => println!("{}", val),
-            Ok(None) => {},
-            Err(errs) => errs
-                .iter()
-                .for_each(|err| print!("{}", err.in_source(&src))),
-        };
-    } else {
-        let mut rl = Editor::<()>::new();
-
-        loop {
-            let line = rl.readline("\n>> ");
-            match line {
-                Ok(line) => {
-                    rl.add_history_entry(&line);
-
-                    match run_expr(&line) {
-                        Ok((ty, val)) => println!("{} of {}", val, ty),
-                        Err(errs) => errs
-                            .iter()
-                            .for_each(|err| print!("{}", err.in_source(&line))),
-                    };
+    let (fmt, args) = match options(env::args().skip(1).collect()) {
+        Ok(res) => res,
+        Err(msg) => {
+            eprintln!("{}\n\n{}", msg, USAGE);
+            process::exit(2);
+        },
+    };
+    let code = match args.first().map(|arg| arg.as_str()) {
+        None => {
+            repl();
+            0
+        },
+        Some("run") => run(&args[1..], fmt),
+        Some("check") => check(&args[1..], fmt),
+        Some("dump") => dump_stage(&args[1..], fmt),
+        Some("test") => test(&args[1..], fmt),
+        Some("fmt") => format_files(&args[1..], fmt),
+        Some("help") | Some("--help") | Some("-h") => {
+            println!("{}", USAGE);
+            0
+        },
+        // `tao <file>` runs the file
+        Some(_) => run(&args, fmt),
+    };
+    process::exit(code);
+}
+
+// Remove the options from the arguments. Options may appear anywhere, except that when running a program they must
+// come before its file, so that the arguments after it are passed to the program untouched.
+fn options(args: Vec<String>) -> Result<(ErrorFormat, Vec<String>), String> {
+    let mut fmt = ErrorFormat::Human;
+    let mut rest = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let value = if arg.starts_with("--error-format=") {
+            arg["--error-format=".len()..].to_string()
+        } else if arg == "--error-format" {
+            args.next().ok_or_else(|| format!("Expected a value for '--error-format'"))?
+        } else {
+            // The file to run is either the first argument after `run`, or the first argument if it isn't a subcommand
+            let is_run_file = !arg.starts_with('-') && {
+                let mut positional = rest.iter().filter(|prev: &&String| !prev.starts_with('-'));
+                match (positional.next().map(|first| first.as_str()), positional.next()) {
+                    (None, _) => !["run", "check", "dump", "test", "fmt", "help"].contains(&arg.as_str()),
+                    (Some("run"), None) => true,
+                    _ => false,
                 }
-                Err(_) => break,
+            };
+            rest.push(arg);
+            if is_run_file {
+                break;
+            } else {
+                continue;
             }
+        };
+        fmt = match value.as_str() {
+            "human" => ErrorFormat::Human,
+            "json" => ErrorFormat::Json,
+            _ => return Err(format!("Unknown error format '{}', expected 'human' or 'json'", value)),
+        };
+    }
+    rest.extend(args);
+    Ok((fmt, rest))
+}
+
+// Read the file at `path` into the source map
+fn load(srcs: &mut SourceMap, path: Option<&String>, fmt: ErrorFormat) -> Result<FileId, i32> {
+    let path = path.ok_or_else(|| {
+        eprintln!("{}", USAGE);
+        2
+    })?;
+    match fs::read_to_string(path) {
+        Ok(code) => Ok(srcs.add(path.clone(), code)),
+        Err(err) => Err(report(srcs, &[Error::custom(format!("Could not read file '{}': {}", path, err))], fmt)),
+    }
+}
+
+fn report(srcs: &SourceMap, errs: &[Error], fmt: ErrorFormat) -> i32 {
+    match fmt {
+        ErrorFormat::Human => errs
+            .iter()
+            .for_each(|err| eprint!("{}", err.in_source(srcs))),
+        ErrorFormat::Json => errs
+            .iter()
+            .for_each(|err| eprintln!("{}", err.diagnostic(srcs).to_json())),
+    }
+    1
+}
+
+// Programs may read their arguments with `@args`
+fn natives(args: Vec<String>) -> Natives {
+    let mut natives = Natives::default();
+    natives.register_fn("args", move || -> Result<Vec<String>, String> { Ok(args.clone()) });
+    natives
+}
+
+fn compile(srcs: &mut SourceMap, path: Option<&String>, args: Vec<String>, fmt: ErrorFormat) -> Result<Module, i32> {
+    let file = load(srcs, path, fmt)?;
+    let dir = path.and_then(|path| Path::new(path).parent());
+    let module = Module::compile_with_natives(srcs, file, dir, natives(args)).map_err(|errs| report(srcs, &errs, fmt))?;
+    // Warnings do not stop the program from running
+    report(srcs, module.warnings(), fmt);
+    Ok(module)
+}
+
+fn run(args: &[String], fmt: ErrorFormat) -> i32 {
+    let mut srcs = SourceMap::default();
+    let res = compile(&mut srcs, args.first(), args.iter().skip(1).cloned().collect(), fmt)
+        .and_then(|module| module
+            .def("main")
+            .and_then(|main| main.run(&mut StdIo))
+            .map_err(|errs| report(&srcs, &errs, fmt)));
+    match res {
+        Ok(Some(val)) => {
+            println!("{}", val);
+            0
+        },
+        Ok(None) => 0,
+        Err(code) => code,
+    }
+}
+
+fn check(args: &[String], fmt: ErrorFormat) -> i32 {
+    let mut srcs = SourceMap::default();
+    match compile(&mut srcs, args.first(), Vec::new(), fmt) {
+        Ok(_) => 0,
+        Err(code) => code,
+    }
+}
+
+fn dump_stage(args: &[String], fmt: ErrorFormat) -> i32 {
+    let (stage, path) = match args {
+        [flag, stage, path] if flag == "--stage" => (stage, path),
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        },
+    };
+    let stage = match stage.parse::<Stage>() {
+        Ok(stage) => stage,
+        Err(err) => {
+            eprintln!("{}", err);
+            return 2;
+        },
+    };
+
+    let mut srcs = SourceMap::default();
+    let file = match load(&mut srcs, Some(path), fmt) {
+        Ok(file) => file,
+        Err(code) => return code,
+    };
+    match dump(&mut srcs, file, Path::new(path).parent(), stage) {
+        Ok(output) => {
+            print!("{}", output);
+            0
+        },
+        Err(errs) => report(&srcs, &errs, fmt),
+    }
+}
+
+fn test(args: &[String], fmt: ErrorFormat) -> i32 {
+    let mut srcs = SourceMap::default();
+    let module = match compile(&mut srcs, args.first(), Vec::new(), fmt) {
+        Ok(module) => module,
+        Err(code) => return code,
+    };
+
+    let tests = module
+        .defs()
+        .into_iter()
+        .filter(|(name, _)| name.rsplit("::").next().unwrap_or("").starts_with("test_"))
+        .collect::<Vec<_>>();
+    let mut failed = 0;
+    for (name, ty) in tests.iter() {
+        let res = if *ty == TypeDesc::Bool {
+            module.def(name).and_then(|def| def.get::<bool>())
+        } else {
+            Err(vec![Error::custom(format!("Test '{}' has type {}, but tests must be of type Bool", name, ty)).with_code("E0103")])
+        };
+        match res {
+            Ok(true) => println!("test {} ... ok", name),
+            Ok(false) => {
+                println!("test {} ... FAILED", name);
+                failed += 1;
+            },
+            Err(errs) => {
+                println!("test {} ... FAILED", name);
+                report(&srcs, &errs, fmt);
+                failed += 1;
+            },
+        }
+    }
+    println!("\n{} passed, {} failed", tests.len() - failed, failed);
+
+    if failed == 0 { 0 } else { 1 }
+}
+
+fn format_files(args: &[String], fmt: ErrorFormat) -> i32 {
+    let check = args.iter().any(|arg| arg == "--check");
+    let paths = args
+        .iter()
+        .filter(|arg| *arg != "--check")
+        .collect::<Vec<_>>();
+    if paths.len() == 0 {
+        eprintln!("{}", USAGE);
+        return 2;
+    }
+
+    let mut srcs = SourceMap::default();
+    let mut code = 0;
+    for path in paths {
+        let file = match load(&mut srcs, Some(path), fmt) {
+            Ok(file) => file,
+            Err(err) => {
+                code = err;
+                continue;
+            },
+        };
+        match format(&srcs, file) {
+            Ok(output) if output == srcs.code(file) => {},
+            Ok(_) if check => {
+                println!("{} is not formatted", path);
+                code = 1;
+            },
+            Ok(output) => if let Err(err) = fs::write(path, output) {
+                code = report(&srcs, &[Error::custom(format!("Could not write file '{}': {}", path, err))], fmt);
+            },
+            Err(errs) => code = report(&srcs, &errs, fmt),
+        }
+    }
+    code
+}
+
+fn repl() {
+    let mut rl = Editor::<()>::new();
+    let mut session = Session::new();
+
+    loop {
+        let line = rl.readline("\n>> ");
+        match line {
+            Ok(line) => {
+                rl.add_history_entry(&line);
+                if line.trim().len() == 0 {
+                    continue;
+                }
+
+                match session.handle(&line) {
+                    Ok(Output::Value(val, ty)) => println!("{} of {}", val, ty),
+                    Ok(Output::Type(ty)) => println!("{}", ty),
+                    Ok(Output::Declared) => {},
+                    Ok(Output::Loaded(path)) => println!("Loaded '{}'", path.display()),
+                    Ok(Output::Browse(defs)) => defs
+                        .iter()
+                        .for_each(|(name, ty)| println!("{} of {}", name, ty)),
+                    Ok(Output::Quit) => break,
+                    Err(errs) => errs
+                        .iter()
+                        .for_each(|err| eprint!("{}", err.in_source(session.srcs()))),
+                };
+            },
+            Err(_) => break,
         }
     }
 }
+
+#[cfg(test)]
+fn strings(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[cfg(test)]
+fn temp_file(name: &str, code: &str) -> String {
+    let path = env::temp_dir().join(format!("tao-{}-{}", process::id(), name));
+    fs::write(&path, code).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+#[test]
+fn error_format_placement() {
+    // The error format may be given before or after the subcommand
+    assert_eq!(
+        options(strings(&["--error-format", "json", "check", "a.tao"])),
+        Ok((ErrorFormat::Json, strings(&["check", "a.tao"]))),
+    );
+    assert_eq!(
+        options(strings(&["check", "--error-format=json", "a.tao"])),
+        Ok((ErrorFormat::Json, strings(&["check", "a.tao"]))),
+    );
+    assert!(options(strings(&["check", "--error-format"])).is_err());
+    assert!(options(strings(&["--error-format=xml", "check", "a.tao"])).is_err());
+}
+
+#[test]
+fn file_and_arguments() {
+    // Everything after the file is passed to the program, even if it looks like an option
+    assert_eq!(
+        options(strings(&["run", "a.tao", "--error-format", "json"])),
+        Ok((ErrorFormat::Human, strings(&["run", "a.tao", "--error-format", "json"]))),
+    );
+    assert_eq!(
+        options(strings(&["a.tao", "run", "--error-format=json"])),
+        Ok((ErrorFormat::Human, strings(&["a.tao", "run", "--error-format=json"]))),
+    );
+    // Other subcommands accept options after their files
+    assert_eq!(
+        options(strings(&["dump", "--stage", "ast", "--error-format", "json", "a.tao"])),
+        Ok((ErrorFormat::Json, strings(&["dump", "--stage", "ast", "a.tao"]))),
+    );
+    assert_eq!(
+        options(strings(&["fmt", "a.tao", "b.tao", "--error-format=json"])),
+        Ok((ErrorFormat::Json, strings(&["fmt", "a.tao", "b.tao"]))),
+    );
+    assert_eq!(
+        options(strings(&["check", "a.tao", "--error-format", "json"])),
+        Ok((ErrorFormat::Json, strings(&["check", "a.tao"]))),
+    );
+}
+
+#[test]
+fn exit_codes() {
+    let good = temp_file("good.tao", "def main = 1\ndef test_sum = 1 + 1 = 2\n");
+    let bad = temp_file("bad.tao", "def main = 1 + true\n");
+    let failing = temp_file("failing.tao", "def test_sum = 1 + 1 = 3\n");
+    for fmt in [ErrorFormat::Human, ErrorFormat::Json].iter().copied() {
+        assert_eq!(check(&strings(&[good.as_str()]), fmt), 0);
+        assert_eq!(check(&strings(&[bad.as_str()]), fmt), 1);
+        assert_eq!(check(&strings(&["does-not-exist.tao"]), fmt), 1);
+        assert_eq!(test(&strings(&[good.as_str()]), fmt), 0);
+        assert_eq!(test(&strings(&[bad.as_str()]), fmt), 1);
+        assert_eq!(test(&strings(&[failing.as_str()]), fmt), 1);
+        // Usage errors
+        assert_eq!(check(&[], fmt), 2);
+        assert_eq!(test(&[], fmt), 2);
+    }
+    [good, bad, failing].iter().for_each(|path| { let _ = fs::remove_file(path); });
+}
@@ -4,7 +4,7 @@ use crate::{
     ast::{self, Literal},
     ty::{Type, Primitive},
     error::Error,
-    node::RawTypeNode,
+    node::{SrcNode, RawTypeNode},
     hir::self,
 };
 
@@ -24,6 +24,8 @@ pub enum RawType {
     Sum(Vec<Self>),
     Func(Box<Self>, Box<Self>),
     Boxed(Ident, Vec<Self>),
+    // Type parameters of generalised local bindings. Values of these types are never inspected.
+    Erased,
 }
 
 impl RawType {
@@ -54,80 +56,83 @@ impl RawType {
                     .collect::<Vec<_>>()
                     .join(""),
             ),
+            RawType::Erased => format!("?"),
         }
     }
 }
 
 impl hir::TypeBinding {
-    fn make_matcher(&self, prog: &hir::Program) -> Matcher {
+    // The matcher and extractor of each alternative of the pattern. Patterns with several or-patterns have an
+    // alternative for each combination of theirs.
+    fn make_alternatives(&self, prog: &hir::Program) -> Vec<(Matcher, Extractor)> {
+        let this = self.binding.as_ref().map(|ident| **ident);
         match &*self.pat {
-            hir::Pat::Wildcard => Matcher::Wildcard,
-            hir::Pat::Literal(litr) => Matcher::Exactly(litr.clone()),
-            hir::Pat::Tuple(items) => Matcher::Product(items
-                .iter()
-                .map(|item| item.make_matcher(prog))
-                .collect()),
-            hir::Pat::Record(fields) => Matcher::Product(fields
-                .iter()
-                .map(|(_, field)| field.make_matcher(prog))
-                .collect()),
-            hir::Pat::List(items) => Matcher::List(items
-                .iter()
-                .map(|item| item.make_matcher(prog))
-                .collect()),
-            hir::Pat::ListFront(items, _) => Matcher::ListFront(items
-                .iter()
-                .map(|item| item.make_matcher(prog))
-                .collect()),
+            hir::Pat::Wildcard => vec![(Matcher::Wildcard, Extractor::Just(this))],
+            // Strings are lists of characters
+            hir::Pat::Literal(Literal::String(s)) => vec![(
+                Matcher::List(s.chars().map(|c| Matcher::Exactly(Literal::Char(c))).collect()),
+                Extractor::Just(this),
+            )],
+            hir::Pat::Literal(litr) => vec![(Matcher::Exactly(litr.clone()), Extractor::Just(this))],
+            hir::Pat::Range(start, end) => vec![(Matcher::Range(start.clone(), end.clone()), Extractor::Just(this))],
+            hir::Pat::Tuple(items) => combinations(items.iter(), prog)
+                .into_iter()
+                .map(|(matchers, extractors)| (Matcher::Product(matchers), Extractor::Product(this, extractors)))
+                .collect(),
+            hir::Pat::Record(fields) => combinations(fields.iter().map(|(_, field)| field), prog)
+                .into_iter()
+                .map(|(matchers, extractors)| (Matcher::Product(matchers), Extractor::Product(this, extractors)))
+                .collect(),
+            hir::Pat::List(items) => combinations(items.iter(), prog)
+                .into_iter()
+                .map(|(matchers, extractors)| (Matcher::List(matchers), Extractor::List(this, extractors)))
+                .collect(),
+            hir::Pat::ListFront(items, tail) => combinations(items.iter(), prog)
+                .into_iter()
+                .map(|(matchers, extractors)| (
+                    Matcher::ListFront(matchers),
+                    Extractor::ListFront(this, extractors, tail.as_ref().map(|ident| **ident)),
+                ))
+                .collect(),
             hir::Pat::Deconstruct(data, _, inner) => {
-                if prog.data_ctx.get_data(data.0).variants.len() == 1 {
-                    inner.make_matcher(prog)
-                } else {
-                    Matcher::Product(vec![
-                        Matcher::Exactly(Literal::Number(data.1 as f64)),
-                        inner.make_matcher(prog),
-                    ])
-                }
+                let variants = prog.data_ctx.get_data(data.0).variants.len();
+                inner
+                    .make_alternatives(prog)
+                    .into_iter()
+                    .map(|(matcher, extractor)| if variants == 1 {
+                        (matcher, extractor)
+                    } else {
+                        (
+                            Matcher::Variant(data.1, variants, Box::new(matcher)),
+                            Extractor::Product(None, vec![Extractor::Just(None), extractor]),
+                        )
+                    })
+                    .collect()
             },
+            // Or-patterns only appear at the top of match arms, so are never bound
+            hir::Pat::Or(alts) => alts
+                .iter()
+                .flat_map(|alt| alt.make_alternatives(prog))
+                .collect(),
         }
     }
+}
 
-    fn make_extractor(&self, prog: &hir::Program) -> Extractor {
-        match &*self.pat {
-            hir::Pat::Wildcard | hir::Pat::Literal(_) =>
-                Extractor::Just(self.binding.as_ref().map(|ident| **ident)),
-            hir::Pat::Tuple(items) => Extractor::Product(
-                self.binding.as_ref().map(|ident| **ident),
-                items.iter().map(|item| item.make_extractor(prog)).collect(),
-            ),
-            hir::Pat::Record(fields) => Extractor::Product(
-                self.binding.as_ref().map(|ident| **ident),
-                fields.iter().map(|(_, field)| field.make_extractor(prog)).collect(),
-            ),
-            hir::Pat::List(items) => Extractor::List(
-                self.binding.as_ref().map(|ident| **ident),
-                items.iter().map(|item| item.make_extractor(prog)).collect(),
-            ),
-            hir::Pat::ListFront(items, tail) => Extractor::ListFront(
-                self.binding.as_ref().map(|ident| **ident),
-                items.iter().map(|item| item.make_extractor(prog)).collect(),
-                tail.as_ref().map(|ident| **ident),
-            ),
-            hir::Pat::Deconstruct(data, _, inner) => {
-                if prog.data_ctx.get_data(data.0).variants.len() == 1 {
-                    inner.make_extractor(prog)
-                } else {
-                    Extractor::Product(
-                        None,
-                        vec![
-                            Extractor::Just(None),
-                            inner.make_extractor(prog),
-                        ],
-                    )
-                }
-            },
-        }
-    }
+// Every combination of the alternatives of each item
+fn combinations<'a>(items: impl Iterator<Item=&'a hir::TypeBinding>, prog: &hir::Program) -> Vec<(Vec<Matcher>, Vec<Extractor>)> {
+    items.fold(vec![(Vec::new(), Vec::new())], |prefixes, item| {
+        let alts = item.make_alternatives(prog);
+        prefixes
+            .into_iter()
+            .flat_map(|(matchers, extractors)| alts
+                .iter()
+                .map(|(matcher, extractor)| (
+                    matchers.iter().cloned().chain(std::iter::once(matcher.clone())).collect::<Vec<_>>(),
+                    extractors.iter().cloned().chain(std::iter::once(extractor.clone())).collect::<Vec<_>>(),
+                ))
+                .collect::<Vec<_>>())
+            .collect()
+    })
 }
 
 pub type DefId = LocalIntern<(Ident, Vec<RawType>)>;
@@ -157,8 +162,9 @@ pub enum Expr {
     Update(RawTypeNode<Self>, usize, Ident, RawTypeNode<Self>),
     // Create a function with the given parameter extractor and body
     Func(Extractor, Vec<Ident>, RawTypeNode<Self>),
-    // Make a flat value against a series of arms
-    Match(RawTypeNode<Self>, Vec<(Matcher, Extractor, RawTypeNode<Self>)>),
+    // Match a value against a series of arms, using the decision tree to choose between them. Arms have an extractor
+    // for each alternative of their pattern, and may have a guard, which is evaluated with the arm's bindings.
+    Match(RawTypeNode<Self>, Decision, Vec<(Vec<Extractor>, Option<RawTypeNode<Self>>, RawTypeNode<Self>)>),
 }
 
 impl hir::TypeExpr {
@@ -166,6 +172,7 @@ impl hir::TypeExpr {
         match &**self {
             hir::Expr::Literal(_) => {},
             hir::Expr::Global(_, _) => {},
+            hir::Expr::Member(_, _, _) => {},
             hir::Expr::Intrinsic(_, _, args) => args
                 .iter()
                 .for_each(|arg| arg.get_env_inner(scope, env)),
@@ -215,7 +222,7 @@ impl hir::TypeExpr {
             },
             hir::Expr::Match(pred, arms) => {
                 pred.get_env_inner(scope, env);
-                for (binding, body) in arms.iter() {
+                for (binding, guard, body) in arms.iter() {
                     let mut bindings = binding
                         .binding_idents()
                         .keys()
@@ -223,6 +230,9 @@ impl hir::TypeExpr {
                         .collect();
                     let scope_len = scope.len();
                     scope.append(&mut bindings);
+                    if let Some(guard) = guard {
+                        guard.get_env_inner(scope, env);
+                    }
                     body.get_env_inner(scope, env);
                     scope.truncate(scope_len);
                 }
@@ -239,30 +249,250 @@ impl hir::TypeExpr {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Matcher {
     Wildcard,
     Exactly(Literal),
+    // Inclusive of both ends
+    Range(Literal, Literal),
     Product(Vec<Matcher>),
     List(Vec<Matcher>),
     ListFront(Vec<Matcher>),
+    // A variant of a data type with the given number of variants. Data values are a tag followed by the inner value.
+    Variant(usize, usize, Box<Matcher>),
 }
 
 impl Matcher {
     pub fn is_refutable(&self) -> bool {
         match self {
             Matcher::Wildcard => false,
-            Matcher::Exactly(_) => true,
+            Matcher::Exactly(_) | Matcher::Range(_, _) => true,
             Matcher::Product(items) => items.iter().any(|item| item.is_refutable()),
             Matcher::List(_) => true,
-            Matcher::ListFront(items) => items.len() != 0 // List matches everything
+            Matcher::ListFront(items) => items.len() != 0, // List matches everything
+            Matcher::Variant(_, _, _) => true,
+        }
+    }
+}
+
+// The location of a part of the value being matched, as the indices used to reach it
+pub type Path = Vec<usize>;
+
+fn child(path: &[usize], idx: usize) -> Path {
+    path.iter().copied().chain(std::iter::once(idx)).collect()
+}
+
+// A decision tree, which chooses the arm of a `match` by testing each part of the value at most once
+#[derive(Debug)]
+pub enum Decision {
+    // The arm with the given index matches, with the given alternative of its pattern
+    Arm(usize, usize),
+    // The arm with the given index and alternative matches if its guard holds. Otherwise, continue with the decision.
+    Guard(usize, usize, Box<Decision>),
+    // No arm matches. Exhaustive matches never get here.
+    Fail,
+    // Switch on the tag of the data value at the path, with a case for each variant
+    Variant(Path, Vec<Decision>),
+    // Switch on the length of the list at the path. The last case is taken for every list at least as long as it.
+    Length(Path, Vec<Decision>),
+    // Compare the value at the path with each literal in turn, taking the default if none are equal
+    Literal(Path, Vec<(Literal, Decision)>, Box<Decision>),
+    // Take the first decision if the value at the path is within the range (inclusive of both ends), and the second if
+    // it is not
+    Range(Path, Literal, Literal, Box<Decision>, Box<Decision>),
+}
+
+// Whether a number or character lies within an inclusive range
+fn in_range(litr: &Literal, start: &Literal, end: &Literal) -> bool {
+    match (litr, start, end) {
+        (Literal::Number(x), Literal::Number(start), Literal::Number(end)) => start <= x && x <= end,
+        (Literal::Char(c), Literal::Char(start), Literal::Char(end)) => start <= c && c <= end,
+        _ => false,
+    }
+}
+
+// The tests that an arm's value must still pass, with the parts of its pattern that match anything removed
+#[derive(Clone)]
+struct Row<'a> {
+    tests: Vec<(Path, &'a Matcher)>,
+    arm: usize,
+    alt: usize,
+    guarded: bool,
+}
+
+impl<'a> Row<'a> {
+    fn add(&mut self, path: Path, matcher: &'a Matcher) {
+        match matcher {
+            Matcher::Wildcard => {},
+            Matcher::ListFront(items) if items.len() == 0 => {},
+            Matcher::Product(items) => items
+                .iter()
+                .enumerate()
+                .for_each(|(idx, item)| self.add(child(&path, idx), item)),
+            matcher => self.tests.push((path, matcher)),
+        }
+    }
+
+    fn add_items(&mut self, path: &[usize], items: &'a [Matcher]) {
+        items
+            .iter()
+            .enumerate()
+            .for_each(|(idx, item)| self.add(child(path, idx), item));
+    }
+
+    fn test_at(&self, path: &[usize]) -> Option<&'a Matcher> {
+        self.tests
+            .iter()
+            .find(|(p, _)| p == path)
+            .map(|(_, matcher)| *matcher)
+    }
+}
+
+// Find the rows that may still match once the test at `path` has been made. Rows that test the path are kept, with
+// the test replaced by tests on the parts of the value, only if `keep` accepts the test.
+fn specialize<'a>(rows: &[Row<'a>], path: &[usize], mut keep: impl FnMut(&'a Matcher, &mut Row<'a>) -> bool) -> Vec<Row<'a>> {
+    rows
+        .iter()
+        .cloned()
+        .filter_map(|mut row| match row.tests.iter().position(|(p, _)| p == path) {
+            Some(idx) => {
+                let (_, matcher) = row.tests.remove(idx);
+                if keep(matcher, &mut row) { Some(row) } else { None }
+            },
+            None => Some(row),
+        })
+        .collect()
+}
+
+impl Decision {
+    // Build a decision tree for a series of arms (with the matcher of each alternative of their pattern, and whether
+    // they have a guard), the first matching arm being chosen
+    pub fn new<'a>(arms: impl Iterator<Item=(&'a [Matcher], bool)>) -> Self {
+        let rows = arms
+            .enumerate()
+            .flat_map(|(arm, (matchers, guarded))| matchers
+                .iter()
+                .enumerate()
+                .map(move |(alt, matcher)| {
+                    let mut row = Row { tests: Vec::new(), arm, alt, guarded };
+                    row.add(Vec::new(), matcher);
+                    row
+                }))
+            .collect::<Vec<_>>();
+        Self::from_rows(&rows)
+    }
+
+    fn from_rows(rows: &[Row]) -> Self {
+        let first = match rows.first() {
+            Some(first) => first,
+            None => return Decision::Fail,
+        };
+        // Test whatever the first row that may still match tests first
+        let (path, matcher) = match first.tests.first() {
+            Some((path, matcher)) => (path.clone(), *matcher),
+            // If the guard fails, the rows after this one may still match
+            None if first.guarded => return Decision::Guard(first.arm, first.alt, Box::new(Self::from_rows(&rows[1..]))),
+            None => return Decision::Arm(first.arm, first.alt),
+        };
+
+        match matcher {
+            Matcher::Variant(_, variants, _) => Decision::Variant(path.clone(), (0..*variants)
+                .map(|tag| Self::from_rows(&specialize(rows, &path, |matcher, row| match matcher {
+                    Matcher::Variant(t, _, inner) if *t == tag => {
+                        row.add(child(&path, 1), inner);
+                        true
+                    },
+                    _ => false,
+                })))
+                .collect()),
+            Matcher::List(_) | Matcher::ListFront(_) => {
+                // Lists longer than every fixed-length pattern and every front are all matched by the same rows
+                let longest = rows
+                    .iter()
+                    .filter_map(|row| row.test_at(&path))
+                    .fold(0, |longest, matcher| match matcher {
+                        Matcher::List(items) => longest.max(items.len() + 1),
+                        Matcher::ListFront(items) => longest.max(items.len()),
+                        _ => longest,
+                    });
+                Decision::Length(path.clone(), (0..=longest)
+                    .map(|len| Self::from_rows(&specialize(rows, &path, |matcher, row| match matcher {
+                        Matcher::List(items) if items.len() == len => {
+                            row.add_items(&path, items);
+                            true
+                        },
+                        Matcher::ListFront(items) if items.len() <= len => {
+                            row.add_items(&path, items);
+                            true
+                        },
+                        _ => false,
+                    })))
+                    .collect())
+            },
+            Matcher::Exactly(_) => {
+                let mut litrs = Vec::new();
+                for matcher in rows.iter().filter_map(|row| row.test_at(&path)) {
+                    match matcher {
+                        Matcher::Exactly(litr) if !litrs.contains(&litr) => litrs.push(litr),
+                        _ => {},
+                    }
+                }
+                let cases = litrs
+                    .iter()
+                    .map(|litr| ((*litr).clone(), Self::from_rows(&specialize(rows, &path, |matcher, _| match matcher {
+                        Matcher::Exactly(l) => l == *litr,
+                        Matcher::Range(start, end) => in_range(litr, start, end),
+                        _ => false,
+                    }))))
+                    .collect::<Vec<_>>();
+                // Booleans have no other values
+                let default = if litrs.contains(&&Literal::Boolean(true)) && litrs.contains(&&Literal::Boolean(false)) {
+                    Decision::Fail
+                } else {
+                    // Ranges may still match values other than the literals, so they must still be tested
+                    Self::from_rows(&specialize(rows, &path, |matcher, row| match matcher {
+                        Matcher::Range(_, _) => {
+                            row.tests.push((path.clone(), matcher));
+                            true
+                        },
+                        _ => false,
+                    }))
+                };
+                Decision::Literal(path, cases, Box::new(default))
+            },
+            Matcher::Range(start, end) => {
+                let inside = Self::from_rows(&specialize(rows, &path, |matcher, row| match matcher {
+                    // Ranges that contain this one match every value in it
+                    Matcher::Range(s, e) if in_range(start, s, e) && in_range(end, s, e) => true,
+                    // Other tests that might match a value in the range must still be made
+                    Matcher::Exactly(l) if in_range(l, start, end) => {
+                        row.tests.push((path.clone(), matcher));
+                        true
+                    },
+                    Matcher::Range(s, e) if in_range(s, start, end) || in_range(e, start, end) => {
+                        row.tests.push((path.clone(), matcher));
+                        true
+                    },
+                    _ => false,
+                }));
+                let outside = Self::from_rows(&specialize(rows, &path, |matcher, row| match matcher {
+                    Matcher::Exactly(l) if in_range(l, start, end) => false,
+                    Matcher::Range(s, e) if in_range(s, start, end) && in_range(e, start, end) => false,
+                    matcher => {
+                        row.tests.push((path.clone(), matcher));
+                        true
+                    },
+                }));
+                Decision::Range(path, start.clone(), end.clone(), Box::new(inside), Box::new(outside))
+            },
+            Matcher::Wildcard | Matcher::Product(_) => unreachable!("Rows never test irrefutable matchers"),
         }
     }
 }
 
 // Describes the extraction of pattern bindings from a basic pattern
 // For example: ((x, y), _, z)
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Extractor {
     Just(Option<Ident>),
     Product(Option<Ident>, Vec<Extractor>),
@@ -311,14 +541,36 @@ pub struct Program {
 }
 
 impl Program {
-    pub fn from_hir(prog: &hir::Program, entry: Ident) -> Result<Self, Error> {
+    // Lower the program reachable from `entry`, instantiating its generic parameters with `params`
+    pub fn from_hir(prog: &hir::Program, entry: Ident, params: Vec<Type>) -> Result<Self, Vec<Error>> {
+        let def = prog.root
+            .def(entry)
+            .ok_or_else(|| vec![Error::custom(format!("Cannot find entry point '{}'", *entry)).with_code("E0103")])?;
+
+        // The entry point must not depend on class instances that cannot be chosen
+        if let Some((class, gen)) = def.given.first() {
+            return Err(vec![Error::custom(format!("Entry point '{}' may not require '{}' to be an instance of class '{}'", *entry, **gen, **class))
+                .with_code("E0103")
+                .with_span(gen.span())]);
+        }
+
+        if def.generics.len() != params.len() {
+            return Err(vec![Error::custom(format!(
+                "Entry point '{}' has {} type parameter(s) but {} were given",
+                *entry,
+                def.generics.len(),
+                params.len(),
+            ))
+                .with_code("E0103")
+                .with_span(def.name.span())]);
+        }
+
         let mut this = Self {
             entry: LocalIntern::new((entry, Vec::new())),
             globals: HashMap::default(),
         };
-
-        let entry = this.instantiate_def(prog, entry, Vec::new())
-            .ok_or_else(|| Error::custom(format!("Cannot find entry point '{}'", *entry)))?;
+        // Cannot fail, the definition exists
+        this.entry = this.instantiate_def(prog, entry, params).unwrap();
 
         Ok(this)
     }
@@ -327,8 +579,12 @@ impl Program {
         self.globals.iter().map(|(id, g)| (*id, g.as_ref().unwrap()))
     }
 
-    fn instantiate_def(&mut self, prog: &hir::Program, name: Ident, params: Vec<RawType>) -> Option<DefId> {
-        let def_id = LocalIntern::new((name, params.clone()));
+    fn instantiate_def(&mut self, prog: &hir::Program, name: Ident, params: Vec<Type>) -> Option<DefId> {
+        let raw_params = params
+            .iter()
+            .map(|param| self.instantiate_type(prog, param, &HashMap::new()))
+            .collect();
+        let def_id = LocalIntern::new((name, raw_params));
 
         if !self.globals.contains_key(&def_id) {
             self.globals.insert(def_id, None); // Insert phoney to keep recursive functions happy
@@ -337,57 +593,66 @@ impl Program {
 
             let generics = def.generics
                 .iter()
-                .zip(params.iter())
+                .zip(params.into_iter())
                 .map(|(name, param)| (**name, param))
                 .collect::<HashMap<_, _>>();
 
-            let body = self.instantiate_expr(prog, &def.body, &mut |gen| generics.get(&gen).cloned().cloned().unwrap());
+            let body = self.instantiate_expr(prog, &def.body, &generics);
             self.globals.insert(def_id, Some(body));
         }
 
         Some(def_id)
     }
 
-    fn instantiate_expr(&mut self, prog: &hir::Program, hir_expr: &hir::TypeExpr, get_generic: &mut impl FnMut(Ident) -> RawType) -> RawTypeNode<Expr> {
+    fn instantiate_expr(&mut self, prog: &hir::Program, hir_expr: &hir::TypeExpr, generics: &HashMap<Ident, Type>) -> RawTypeNode<Expr> {
         let expr = match &**hir_expr {
             hir::Expr::Literal(litr) => Expr::Literal(litr.clone()),
             hir::Expr::Local(local) => Expr::GetLocal(*local),
-            hir::Expr::Global(global, generics) => {
-                let generics = generics.iter().map(|(_, (_, ty))| self.instantiate_type(prog, ty, get_generic)).collect::<Vec<_>>();
-                let def = self.instantiate_def(prog, *global, generics).unwrap();
+            hir::Expr::Global(global, params) => {
+                let params = params.iter().map(|(_, (_, ty))| substitute_erasing(ty, generics)).collect::<Vec<_>>();
+                let def = self.instantiate_def(prog, *global, params).unwrap();
+                Expr::GetGlobal(def)
+            },
+            hir::Expr::Member(class, member, (_, ty)) => {
+                // Now that the type is known, choose the instance that implements the member
+                let (name, params) = prog.class_ctx
+                    .resolve_member(**class, **member, &substitute_erasing(ty, generics))
+                    .expect("Instance should have been checked during type inference");
+                let def = self.instantiate_def(prog, name, params).unwrap();
                 Expr::GetGlobal(def)
             },
             hir::Expr::Intrinsic(intrinsic, generics, args) => Expr::Intrinsic(*intrinsic, args
                 .iter()
-                .map(|arg| self.instantiate_expr(prog, arg, get_generic))
+                .map(|arg| self.instantiate_expr(prog, arg, generics))
                 .collect()),
-            hir::Expr::Unary(op, a) => Expr::Unary(**op, self.instantiate_expr(prog, a, get_generic)),
-            hir::Expr::Binary(op, a, b) => Expr::Binary(**op, self.instantiate_expr(prog, a, get_generic), self.instantiate_expr(prog, b, get_generic)),
+            hir::Expr::Unary(op, a) => Expr::Unary(**op, self.instantiate_expr(prog, a, generics)),
+            hir::Expr::Binary(op, a, b) => Expr::Binary(**op, self.instantiate_expr(prog, a, generics), self.instantiate_expr(prog, b, generics)),
             hir::Expr::Match(pred, arms) => {
-                let pred = self.instantiate_expr(prog, pred, get_generic);
-                self.instantiate_match(prog, pred, &arms, get_generic)
+                let pred = self.instantiate_expr(prog, pred, generics);
+                self.instantiate_match(prog, pred, &arms, generics)
             },
             hir::Expr::Tuple(items) => Expr::Tuple(items
                 .iter()
-                .map(|item| self.instantiate_expr(prog, item, get_generic))
+                .map(|item| self.instantiate_expr(prog, item, generics))
                 .collect()),
             hir::Expr::Record(fields) => Expr::Tuple(fields
                 .iter()
-                .map(|(_, field)| self.instantiate_expr(prog, field, get_generic))
+                .map(|(_, field)| self.instantiate_expr(prog, field, generics))
                 .collect()),
             hir::Expr::List(items) => Expr::List(items
                 .iter()
-                .map(|item| self.instantiate_expr(prog, item, get_generic))
+                .map(|item| self.instantiate_expr(prog, item, generics))
                 .collect()),
             hir::Expr::Func(binding, body) => {
-                let extractor = binding.make_extractor(prog);
+                // Parameters are irrefutable, so never have more than one alternative
+                let (_, extractor) = binding.make_alternatives(prog).remove(0);
                 let e_bindings = extractor.get_bindings();
                 let env = body.get_env().into_iter().filter(|ident| !e_bindings.contains(ident)).collect();
-                Expr::Func(extractor, env, self.instantiate_expr(prog, body, get_generic))
+                Expr::Func(extractor, env, self.instantiate_expr(prog, body, generics))
             },
             hir::Expr::Apply(f, arg) => Expr::Apply(
-                self.instantiate_expr(prog, f, get_generic),
-                self.instantiate_expr(prog, arg, get_generic),
+                self.instantiate_expr(prog, f, generics),
+                self.instantiate_expr(prog, arg, generics),
             ),
             hir::Expr::Access(record, field) => {
                 let fields = match &**record.ty() {
@@ -406,7 +671,7 @@ impl Program {
                     .iter()
                     .enumerate().find(|(_, (name, _))| name == field)
                     .unwrap().0;
-                Expr::Access(self.instantiate_expr(prog, record, get_generic), field_idx)
+                Expr::Access(self.instantiate_expr(prog, record, generics), field_idx)
             },
             hir::Expr::Update(record, field, value) => match &**record.ty() {
                 Type::Record(fields) => {
@@ -415,10 +680,10 @@ impl Program {
                         .enumerate().find(|(_, (name, _))| name == field)
                         .unwrap().0;
                     Expr::Update(
-                        self.instantiate_expr(prog, record, get_generic),
+                        self.instantiate_expr(prog, record, generics),
                         field_idx,
                         **field,
-                        self.instantiate_expr(prog, value, get_generic),
+                        self.instantiate_expr(prog, value, generics),
                     )
                 },
                 Type::Data(data, params) => {
@@ -434,24 +699,24 @@ impl Program {
                         .enumerate().find(|(_, (name, _))| name == field)
                         .unwrap().0;
                     Expr::Update(
-                        self.instantiate_expr(prog, record, get_generic),
+                        self.instantiate_expr(prog, record, generics),
                         field_idx,
                         **field,
-                        self.instantiate_expr(prog, value, get_generic),
+                        self.instantiate_expr(prog, value, generics),
                     )
                 },
                 ty => unreachable!("{:?}", ty),
             },
             hir::Expr::Constructor(data, _, inner) => {
                 // Sum types with one variant don't need a discriminant!
-                let inner = self.instantiate_expr(prog, inner, get_generic);
+                let inner = self.instantiate_expr(prog, inner, generics);
                 if prog.data_ctx.get_data(data.0).variants.len() == 1 {
                     return inner;
                 } else {
                     Expr::Tuple(vec![
                         RawTypeNode::new(
                             Expr::Literal(Literal::Number(data.1 as f64)),
-                            (data.span(), self.instantiate_type(prog, &Type::Primitive(Primitive::Number), get_generic)),
+                            (data.span(), self.instantiate_type(prog, &Type::Primitive(Primitive::Number), generics)),
                         ),
                         inner,
                     ])
@@ -459,7 +724,7 @@ impl Program {
             },
         };
 
-        let ty = self.instantiate_type(prog, hir_expr.ty(), get_generic);
+        let ty = self.instantiate_type(prog, hir_expr.ty(), generics);
 
         RawTypeNode::new(expr, (hir_expr.span(), ty))
     }
@@ -468,47 +733,60 @@ impl Program {
         &mut self,
         prog: &hir::Program,
         pred: RawTypeNode<Expr>,
-        arms: &[(hir::TypeBinding, hir::TypeExpr)],
-        get_generic: &mut impl FnMut(Ident) -> RawType,
+        arms: &[(hir::TypeBinding, Option<hir::TypeExpr>, hir::TypeExpr)],
+        generics: &HashMap<Ident, Type>,
     ) -> Expr {
+        let (matchers, extractors): (Vec<Vec<_>>, Vec<Vec<_>>) = arms
+            .iter()
+            .map(|(binding, _, _)| binding.make_alternatives(prog).into_iter().unzip::<_, _, Vec<_>, Vec<_>>())
+            .unzip();
+        let decision = Decision::new(matchers
+            .iter()
+            .zip(arms.iter())
+            .map(|(matchers, (_, guard, _))| (matchers.as_slice(), guard.is_some())));
         let arms = arms
             .iter()
-            .map(|(binding, body)| (
-                binding.make_matcher(prog),
-                binding.make_extractor(prog),
-                self.instantiate_expr(prog, body, get_generic),
+            .zip(extractors)
+            .map(|((_, guard, body), extractors)| (
+                extractors,
+                guard.as_ref().map(|guard| self.instantiate_expr(prog, guard, generics)),
+                self.instantiate_expr(prog, body, generics),
             ))
             .collect();
 
-        Expr::Match(pred, arms)
+        Expr::Match(pred, decision, arms)
     }
 
     fn instantiate_type(&mut self,
         prog: &hir::Program,
         ty: &Type,
-        get_generic: &mut dyn FnMut(Ident) -> RawType,
+        generics: &HashMap<Ident, Type>,
     ) -> RawType {
         match ty {
             Type::Primitive(prim) => RawType::Primitive(prim.clone()),
-            Type::GenParam(ident) => get_generic(*ident),
+            // Parameters that aren't generics of the definition belong to generalised local bindings
+            Type::GenParam(ident) => generics
+                .get(ident)
+                .map(|ty| self.instantiate_type(prog, ty, &HashMap::new()))
+                .unwrap_or(RawType::Erased),
             Type::Tuple(items) => RawType::Product(items
                 .iter()
-                .map(|item| self.instantiate_type(prog, item, get_generic))
+                .map(|item| self.instantiate_type(prog, item, generics))
                 .collect()),
             Type::Record(fields) => RawType::Product(fields
                 .iter()
-                .map(|(_, field)| self.instantiate_type(prog, field, get_generic))
+                .map(|(_, field)| self.instantiate_type(prog, field, generics))
                 .collect()),
-            Type::List(item) => RawType::List(Box::new(self.instantiate_type(prog, item, get_generic))),
+            Type::List(item) => RawType::List(Box::new(self.instantiate_type(prog, item, generics))),
             Type::Func(i, o) => RawType::Func(
-                Box::new(self.instantiate_type(prog, i, get_generic)),
-                Box::new(self.instantiate_type(prog, o, get_generic)),
+                Box::new(self.instantiate_type(prog, i, generics)),
+                Box::new(self.instantiate_type(prog, o, generics)),
             ),
             Type::Data(data_id, params) => {
                 let data = prog.data_ctx.get_data(**data_id);
                 let params = params
                     .iter()
-                    .map(|ty| self.instantiate_type(prog, ty, get_generic))
+                    .map(|ty| self.instantiate_type(prog, ty, generics))
                     .collect::<Vec<_>>();
                 let mut get_generic = |name| data.generics
                     .iter()
@@ -536,3 +814,38 @@ impl Program {
         }
     }
 }
+
+// Replace the generic parameters of a type with the types they have been instantiated with. Parameters without a
+// type are given to `missing`.
+pub(crate) fn substitute<E>(
+    ty: &Type,
+    generics: &HashMap<Ident, Type>,
+    missing: &impl Fn(Ident) -> Result<Type, E>,
+) -> Result<Type, E> {
+    let sub = |ty: &SrcNode<Type>| -> Result<_, E> { Ok(SrcNode::new(substitute(ty, generics, missing)?, ty.span())) };
+    Ok(match ty {
+        Type::Primitive(prim) => Type::Primitive(prim.clone()),
+        Type::GenParam(ident) => match generics.get(ident) {
+            Some(ty) => ty.clone(),
+            None => missing(*ident)?,
+        },
+        Type::List(item) => Type::List(sub(item)?),
+        Type::Tuple(items) => Type::Tuple(items.iter().map(sub).collect::<Result<_, E>>()?),
+        Type::Record(fields) => Type::Record(fields
+            .iter()
+            .map(|(name, field)| Ok((name.clone(), sub(field)?)))
+            .collect::<Result<_, E>>()?),
+        Type::Func(i, o) => Type::Func(sub(i)?, sub(o)?),
+        Type::Data(data, params) => Type::Data(data.clone(), params.iter().map(sub).collect::<Result<_, E>>()?),
+    })
+}
+
+// Substitute the generic parameters of a type within a definition. Parameters that aren't generics of the definition
+// belong to generalised local bindings and get erased, so are given a name no definition can use.
+fn substitute_erasing(ty: &Type, generics: &HashMap<Ident, Type>) -> Type {
+    let erased = |_: Ident| Ok::<_, std::convert::Infallible>(Type::GenParam(Ident::new(format!("?"))));
+    match substitute(ty, generics, &erased) {
+        Ok(ty) => ty,
+        Err(never) => match never {},
+    }
+}
@@ -0,0 +1,797 @@
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fs,
+    path::{Path as FsPath, PathBuf},
+};
+use internment::LocalIntern;
+use crate::{
+    ast,
+    lex,
+    error::Error,
+    src::{Span, Loc, FileId, SourceMap},
+    node::SrcNode,
+    ty::Primitive,
+};
+
+type Ident = LocalIntern<String>;
+
+// Names that failed to resolve are replaced with this, so that later stages treat them as poisoned
+const UNRESOLVED: &str = "<unresolved>";
+
+#[derive(Copy, Clone, PartialEq)]
+enum Namespace {
+    // Definitions and class members
+    Value,
+    // Data types, type aliases, constructors and classes
+    Type,
+}
+
+// Join the parts of a path with `::`
+fn join(parts: &[Ident]) -> String {
+    parts
+        .iter()
+        .map(|part| part.as_str())
+        .collect::<Vec<_>>()
+        .join("::")
+}
+
+// Find the name of an item declared in the module with the given path. Items of the root module are not qualified.
+fn qualify(path: &[Ident], name: Ident) -> Ident {
+    if path.len() == 0 {
+        name
+    } else {
+        Ident::new(format!("{}::{}", join(path), name))
+    }
+}
+
+struct Module {
+    path: Vec<Ident>,
+    // The directory that submodules are loaded from (if the module lives on disk)
+    dir: Option<PathBuf>,
+    ast: Option<SrcNode<ast::Module>>,
+
+    // Items declared by the module, mapped to their qualified names
+    values: HashMap<Ident, SrcNode<Ident>>,
+    types: HashMap<Ident, SrcNode<Ident>>,
+
+    // Items and modules brought into scope with `use`, along with the span of the import
+    imported_values: HashMap<Ident, (SrcNode<Ident>, Span)>,
+    imported_types: HashMap<Ident, (SrcNode<Ident>, Span)>,
+    imported_modules: HashMap<Ident, (usize, Span)>,
+}
+
+impl Module {
+    fn new(path: Vec<Ident>, dir: Option<PathBuf>, ast: SrcNode<ast::Module>) -> Self {
+        Self {
+            path,
+            dir,
+            ast: Some(ast),
+            values: HashMap::default(),
+            types: HashMap::default(),
+            imported_values: HashMap::default(),
+            imported_types: HashMap::default(),
+            imported_modules: HashMap::default(),
+        }
+    }
+
+    fn items(&self, ns: Namespace) -> &HashMap<Ident, SrcNode<Ident>> {
+        match ns {
+            Namespace::Value => &self.values,
+            Namespace::Type => &self.types,
+        }
+    }
+
+    fn imports(&self, ns: Namespace) -> &HashMap<Ident, (SrcNode<Ident>, Span)> {
+        match ns {
+            Namespace::Value => &self.imported_values,
+            Namespace::Type => &self.imported_types,
+        }
+    }
+
+    fn declare(&mut self, ns: Namespace, name: &SrcNode<Ident>) {
+        let qualified = SrcNode::new(qualify(&self.path, **name), name.span());
+        // Duplicate declarations are reported during type-checking
+        match ns {
+            Namespace::Value => self.values.entry(**name).or_insert(qualified),
+            Namespace::Type => self.types.entry(**name).or_insert(qualified),
+        };
+    }
+}
+
+// A tree of modules, rooted at the module being compiled. The prelude is a module of the root that every other module
+// implicitly imports.
+pub struct ModuleTree {
+    modules: Vec<Module>,
+    prelude: usize,
+    // Every use of an item, along with the qualified name it was resolved to
+    references: Vec<(Span, Namespace, Ident)>,
+}
+
+impl ModuleTree {
+    // Load the submodules of the root module (relative to `dir`, if given) and resolve the names used by every module.
+    // The modules are flattened into a single module with qualified item names. Loaded files are added to `srcs`.
+    pub fn load(
+        srcs: &mut SourceMap,
+        root: SrcNode<ast::Module>,
+        dir: Option<&FsPath>,
+        prelude: SrcNode<ast::Module>,
+    ) -> (Self, SrcNode<ast::Module>, Vec<Error>) {
+        let span = root.span();
+        let mut errors = Vec::new();
+        let mut this = Self {
+            modules: vec![
+                Module::new(Vec::new(), dir.map(|dir| dir.to_path_buf()), root),
+                Module::new(vec![Ident::new(format!("prelude"))], None, prelude),
+            ],
+            prelude: 1,
+            references: Vec::new(),
+        };
+
+        // Load submodules, which may declare further submodules of their own
+        let mut idx = 0;
+        while idx < this.modules.len() {
+            let submodules = this.modules[idx].ast
+                .as_ref()
+                .unwrap()
+                .decls
+                .iter()
+                .filter_map(|decl| if let ast::Decl::Mod(name) = &**decl { Some(name.clone()) } else { None })
+                .collect::<Vec<_>>();
+            for name in submodules {
+                if let Err(err) = this.load_submodule(srcs, idx, &name, &mut errors) {
+                    errors.push(err);
+                }
+            }
+            idx += 1;
+        }
+
+        // Find the items that each module declares
+        for module in this.modules.iter_mut() {
+            let ast = module.ast.take().unwrap();
+            for decl in ast.decls.iter() {
+                match &**decl {
+                    ast::Decl::Def(def) => module.declare(Namespace::Value, &def.name),
+                    ast::Decl::TypeAlias(alias) => module.declare(Namespace::Type, &alias.name),
+                    ast::Decl::Data(data) => {
+                        module.declare(Namespace::Type, &data.name);
+                        match &*data.data_ty {
+                            ast::DataType::Sum(variants) => variants
+                                .iter()
+                                .for_each(|(name, _)| module.declare(Namespace::Type, name)),
+                            ast::DataType::Product(_) => {},
+                        }
+                    },
+                    ast::Decl::Class(class) => {
+                        module.declare(Namespace::Type, &class.name);
+                        class.members
+                            .iter()
+                            .for_each(|(name, _)| module.declare(Namespace::Value, name));
+                    },
+                    ast::Decl::Error { name: Some(name), is_type } => module.declare(
+                        if *is_type { Namespace::Type } else { Namespace::Value },
+                        name,
+                    ),
+                    ast::Decl::Instance(_)
+                    | ast::Decl::Mod(_)
+                    | ast::Decl::Use(_)
+                    | ast::Decl::Error { name: None, .. } => {},
+                }
+            }
+            module.ast = Some(ast);
+        }
+
+        // Resolve imports, now that the items of every module are known
+        for idx in 0..this.modules.len() {
+            let imports = this.modules[idx].ast
+                .as_ref()
+                .unwrap()
+                .decls
+                .iter()
+                .filter_map(|decl| if let ast::Decl::Use(path) = &**decl { Some(path.clone()) } else { None })
+                .collect::<Vec<_>>();
+            for path in imports {
+                if let Err(err) = this.import(idx, &path) {
+                    errors.push(err);
+                }
+            }
+        }
+
+        // Resolve the names used by each module and flatten them into one
+        let mut decls = Vec::new();
+        let mut unresolved = false;
+        let mut references = Vec::new();
+        for idx in 0..this.modules.len() {
+            let mut ast = this.modules[idx].ast.take().unwrap();
+            let mut resolver = Resolver {
+                tree: &this,
+                module: idx,
+                errors: &mut errors,
+                references: &mut references,
+                unresolved: false,
+            };
+            for decl in ast.decls.iter_mut() {
+                resolver.resolve_decl(decl);
+            }
+            unresolved |= resolver.unresolved;
+            decls.append(&mut ast.decls);
+        }
+        this.references = references;
+
+        // Names that failed to resolve have already been reported
+        if unresolved {
+            for is_type in [false, true].iter() {
+                decls.push(SrcNode::new(ast::Decl::Error {
+                    name: Some(SrcNode::new(Ident::new(UNRESOLVED.to_string()), Span::none())),
+                    is_type: *is_type,
+                }, Span::none()));
+            }
+        }
+
+        (this, SrcNode::new(ast::Module { decls }, span), errors)
+    }
+
+    fn load_submodule(&mut self, srcs: &mut SourceMap, parent: usize, name: &SrcNode<Ident>, errors: &mut Vec<Error>) -> Result<(), Error> {
+        let mut path = self.modules[parent].path.clone();
+        path.push(**name);
+
+        if self.module_at(&path).is_some() {
+            return Err(Error::custom(format!("Module '{}' is declared more than once", join(&path)))
+                .with_code("E0101")
+                .with_span(name.span()));
+        }
+
+        let dir = self.modules[parent].dir
+            .clone()
+            .ok_or_else(|| Error::custom(format!("Module '{}' cannot be loaded because its parent is not a file", **name))
+                .with_code("E0102")
+                .with_span(name.span()))?;
+        let file_path = dir.join(format!("{}.tao", **name));
+        let src = fs::read_to_string(&file_path)
+            .map_err(|err| Error::custom(format!("Could not load module '{}' from '{}': {}", **name, file_path.display(), err))
+                .with_code("E0102")
+                .with_span(name.span()))?;
+
+        let file = srcs.add(file_path.display().to_string(), src);
+        let (ast, mut parse_errors) = match lex::lex(srcs.code(file), file) {
+            Ok(tokens) => ast::parse_module(&tokens),
+            Err(mut lex_errors) => {
+                errors.append(&mut lex_errors);
+                return Ok(());
+            },
+        };
+        errors.append(&mut parse_errors);
+
+        // Submodules of this module live in a directory of the same name
+        self.modules.push(Module::new(path, Some(dir.join(name.as_str())), ast));
+        Ok(())
+    }
+
+    fn module_at(&self, path: &[Ident]) -> Option<usize> {
+        self.modules
+            .iter()
+            .position(|module| module.path == path)
+    }
+
+    // Find the module that a path refers to. The first part of the path may be an imported module, a submodule of
+    // the current module, or a module of the root.
+    fn find_module(&self, from: usize, parts: &[Ident]) -> Option<usize> {
+        let (first, rest) = parts.split_first()?;
+        let module = &self.modules[from];
+        let mut path = if let Some((imported, _)) = module.imported_modules.get(first) {
+            self.modules[*imported].path.clone()
+        } else {
+            let mut path = module.path.clone();
+            path.push(*first);
+            if self.module_at(&path).is_some() {
+                path
+            } else {
+                vec![*first]
+            }
+        };
+        path.extend(rest.iter().copied());
+        self.module_at(&path)
+    }
+
+    fn import(&mut self, idx: usize, path: &SrcNode<ast::Path>) -> Result<(), Error> {
+        let (base, prefix) = path.parts().split_last().unwrap();
+
+        // Modules may be imported by name
+        if let Some(imported) = self.find_module(idx, path.parts()) {
+            if let Some((_, other_span)) = self.modules[idx].imported_modules.get(base) {
+                return Err(Error::custom(format!("Module '{}' is imported more than once", base))
+                    .with_code("E0101")
+                    .with_span(path.span())
+                    .with_secondary_span(*other_span));
+            }
+            self.modules[idx].imported_modules.insert(*base, (imported, path.span()));
+            return Ok(());
+        }
+
+        let target = if prefix.len() == 0 {
+            // `use` of a single name can only refer to a module
+            return Err(Error::custom(format!("No such module '{}'", base))
+                .with_code("E0100")
+                .with_span(path.span()));
+        } else {
+            self.find_module(idx, prefix)
+                .ok_or_else(|| Error::custom(format!("No such module '{}'", join(prefix)))
+                    .with_code("E0100")
+                    .with_span(path.span()))?
+        };
+
+        let mut found = false;
+        for ns in [Namespace::Value, Namespace::Type].iter().copied() {
+            let item = match self.modules[target].items(ns).get(base) {
+                Some(item) => item.clone(),
+                None => continue,
+            };
+            found = true;
+
+            let module = &self.modules[idx];
+            // Imports may not shadow items of the importing module, nor each other
+            if let Some(local) = module.items(ns).get(base) {
+                return Err(Error::custom(format!("Import of '{}' conflicts with a declaration of the same name", base))
+                    .with_code("E0101")
+                    .with_span(path.span())
+                    .with_span(local.span())
+                    .with_secondary_span(item.span()));
+            } else if let Some((other, other_span)) = module.imports(ns).get(base) {
+                if **other != *item {
+                    return Err(Error::custom(format!("Conflicting imports of '{}'", base))
+                        .with_code("E0101")
+                        .with_span(path.span())
+                        .with_span(*other_span)
+                        .with_secondary_span(item.span())
+                        .with_secondary_span(other.span()));
+                }
+            }
+
+            let module = &mut self.modules[idx];
+            match ns {
+                Namespace::Value => module.imported_values.insert(*base, (item, path.span())),
+                Namespace::Type => module.imported_types.insert(*base, (item, path.span())),
+            };
+        }
+
+        if found {
+            Ok(())
+        } else {
+            Err(Error::custom(format!("No item named '{}' in module '{}'", base, join(prefix)))
+                .with_code("E0100")
+                .with_span(path.span()))
+        }
+    }
+
+    // Resolve the names used by a definition as if it were declared in the root module
+    pub fn resolve_def(&self, def: &mut ast::Def) -> Result<(), Vec<Error>> {
+        let mut errors = Vec::new();
+        Resolver {
+            tree: self,
+            module: 0,
+            errors: &mut errors,
+            references: &mut Vec::new(),
+            unresolved: false,
+        }.resolve_def(def);
+
+        if errors.len() == 0 {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    // Find the declaration of the item used at `loc` in `file`, if there is one
+    pub fn definition(&self, file: FileId, loc: Loc) -> Option<Span> {
+        let (_, ns, name) = self.references
+            .iter()
+            .filter(|(span, _, _)| span.file() == Some(file) && span.contains(loc))
+            .min_by_key(|(span, _, _)| span.len())?;
+        self.modules
+            .iter()
+            .find_map(|module| module
+                .items(*ns)
+                .values()
+                .find(|item| ***item == *name))
+            .map(|item| item.span())
+    }
+}
+
+struct Resolver<'a> {
+    tree: &'a ModuleTree,
+    module: usize,
+    errors: &'a mut Vec<Error>,
+    references: &'a mut Vec<(Span, Namespace, Ident)>,
+    unresolved: bool,
+}
+
+impl<'a> Resolver<'a> {
+    // Find the qualified name of an item. `Ok(None)` means that a name without a path is not in scope.
+    fn find(&self, parts: &[Ident], ns: Namespace, span: Span) -> Result<Option<Ident>, Error> {
+        let (base, prefix) = parts.split_last().unwrap();
+        if prefix.len() == 0 {
+            let module = &self.tree.modules[self.module];
+            Ok(module.items(ns)
+                .get(base)
+                .map(|item| **item)
+                .or_else(|| module.imports(ns).get(base).map(|(item, _)| **item))
+                // Everything in the prelude is implicitly imported
+                .or_else(|| self.tree.modules[self.tree.prelude].items(ns).get(base).map(|item| **item)))
+        } else {
+            let target = self.tree
+                .find_module(self.module, prefix)
+                .ok_or_else(|| Error::custom(format!("No such module '{}'", join(prefix)))
+                    .with_code("E0100")
+                    .with_span(span))?;
+            self.tree.modules[target]
+                .items(ns)
+                .get(base)
+                .map(|item| Some(**item))
+                .ok_or_else(|| Error::custom(format!("No item named '{}' in module '{}'", base, join(prefix)))
+                    .with_code("E0100")
+                    .with_span(span))
+        }
+    }
+
+    // Replace a (potentially qualified) name with the qualified name of the item it refers to
+    fn resolve(&mut self, parts: &[Ident], ns: Namespace, span: Span, not_found: impl FnOnce(Ident) -> String) -> Ident {
+        match self.find(parts, ns, span) {
+            Ok(Some(name)) => {
+                self.references.push((span, ns, name));
+                name
+            },
+            Ok(None) => {
+                self.errors.push(Error::custom(not_found(parts[0]))
+                    .with_code("E0100")
+                    .with_span(span));
+                self.unresolved = true;
+                Ident::new(UNRESOLVED.to_string())
+            },
+            Err(err) => {
+                self.errors.push(err);
+                self.unresolved = true;
+                Ident::new(UNRESOLVED.to_string())
+            },
+        }
+    }
+
+    fn resolve_type_name(&mut self, name: &mut SrcNode<Ident>, not_found: impl FnOnce(Ident) -> String) {
+        let parts = name
+            .split("::")
+            .map(|part| Ident::new(part.to_string()))
+            .collect::<Vec<_>>();
+        **name = self.resolve(&parts, Namespace::Type, name.span(), not_found);
+    }
+
+    fn declare(&self, name: &mut SrcNode<Ident>) {
+        **name = qualify(&self.tree.modules[self.module].path, **name);
+    }
+
+    fn resolve_decl(&mut self, decl: &mut ast::Decl) {
+        match decl {
+            ast::Decl::Def(def) => self.resolve_def(def),
+            ast::Decl::TypeAlias(alias) => {
+                self.declare(&mut alias.name);
+                let generics = alias.generics.iter().map(|gen| **gen).collect::<Vec<_>>();
+                self.resolve_type(&mut alias.ty, &generics);
+            },
+            ast::Decl::Data(data) => {
+                self.declare(&mut data.name);
+                let generics = data.generics.iter().map(|gen| **gen).collect::<Vec<_>>();
+                match &mut *data.data_ty {
+                    ast::DataType::Sum(variants) => for (name, ty) in variants.iter_mut() {
+                        self.declare(name);
+                        if let Some(ty) = ty {
+                            self.resolve_type(ty, &generics);
+                        }
+                    },
+                    ast::DataType::Product(ty) => self.resolve_type(ty, &generics),
+                }
+            },
+            ast::Decl::Class(class) => {
+                self.declare(&mut class.name);
+                for (name, ty) in class.members.iter_mut() {
+                    self.declare(name);
+                    self.resolve_type(ty, &[*class.param]);
+                }
+            },
+            ast::Decl::Instance(instance) => {
+                self.resolve_type_name(&mut instance.class, |name| format!("No such class '{}'", name));
+                for (class, _) in instance.given.iter_mut() {
+                    self.resolve_type_name(class, |name| format!("No such class '{}'", name));
+                }
+                let generics = instance.generics.iter().map(|gen| **gen).collect::<Vec<_>>();
+                self.resolve_type(&mut instance.ty, &generics);
+
+                // Members live in the same module as their class
+                let class_path = instance.class
+                    .split("::")
+                    .map(|part| Ident::new(part.to_string()))
+                    .collect::<Vec<_>>();
+                for (name, body) in instance.members.iter_mut() {
+                    **name = qualify(&class_path[..class_path.len() - 1], **name);
+                    self.resolve_expr(body, &generics, &mut Vec::new());
+                }
+            },
+            ast::Decl::Error { name: Some(name), .. } => self.declare(name),
+            ast::Decl::Error { name: None, .. } | ast::Decl::Mod(_) | ast::Decl::Use(_) => {},
+        }
+    }
+
+    fn resolve_def(&mut self, def: &mut ast::Def) {
+        self.declare(&mut def.name);
+        for (class, _) in def.given.iter_mut() {
+            self.resolve_type_name(class, |name| format!("No such class '{}'", name));
+        }
+        let generics = def.generics.iter().map(|gen| **gen).collect::<Vec<_>>();
+        self.resolve_type(&mut def.ty, &generics);
+        self.resolve_expr(&mut def.body, &generics, &mut Vec::new());
+    }
+
+    fn resolve_type(&mut self, ty: &mut SrcNode<ast::Type>, generics: &[Ident]) {
+        match &mut **ty {
+            ast::Type::Unknown | ast::Type::Error => {},
+            ast::Type::List(item) => self.resolve_type(item, generics),
+            ast::Type::Tuple(items) => items
+                .iter_mut()
+                .for_each(|item| self.resolve_type(item, generics)),
+            ast::Type::Record(fields) => fields
+                .iter_mut()
+                .for_each(|(_, field)| self.resolve_type(field, generics)),
+            ast::Type::Func(i, o) => {
+                self.resolve_type(i, generics);
+                self.resolve_type(o, generics);
+            },
+            ast::Type::Data(name, params) => {
+                // Generic parameters and primitives are not items
+                let is_builtin = generics.contains(&**name) || Primitive::try_from(name.as_str()).is_ok();
+                if !is_builtin {
+                    self.resolve_type_name(name, |name| format!("No such type '{}'", name));
+                }
+                params
+                    .iter_mut()
+                    .for_each(|param| self.resolve_type(param, generics));
+            },
+        }
+    }
+
+    fn resolve_binding(&mut self, binding: &mut SrcNode<ast::Binding>) {
+        match &mut **binding {
+            ast::Binding::Unbound(pat) => self.resolve_pat(pat),
+            ast::Binding::Bound(_, pat) => self.resolve_pat(pat),
+            ast::Binding::Ident(_) => {},
+        }
+    }
+
+    fn resolve_pat(&mut self, pat: &mut ast::Pat) {
+        match pat {
+            ast::Pat::Wildcard | ast::Pat::Literal(_) | ast::Pat::Range(_, _) | ast::Pat::Error => {},
+            ast::Pat::List(items) | ast::Pat::Tuple(items) | ast::Pat::ListFront(items, _) | ast::Pat::Or(items) => items
+                .iter_mut()
+                .for_each(|item| self.resolve_binding(item)),
+            ast::Pat::Record(fields) => fields
+                .iter_mut()
+                .for_each(|(_, field)| self.resolve_binding(field)),
+            ast::Pat::Deconstruct(constructor, inner) => {
+                self.resolve_type_name(constructor, |name| format!("No data type with constructor '{}' exists", name));
+                self.resolve_binding(inner);
+            },
+        }
+    }
+
+    // Resolve an expression with the bindings of a pattern in scope
+    fn resolve_with_binding(
+        &mut self,
+        binding: &ast::Binding,
+        expr: &mut SrcNode<ast::Expr>,
+        generics: &[Ident],
+        locals: &mut Vec<Ident>,
+    ) {
+        let old_len = locals.len();
+        binding.collect_idents(locals);
+        self.resolve_expr(expr, generics, locals);
+        locals.truncate(old_len);
+    }
+
+    fn resolve_expr(&mut self, expr: &mut SrcNode<ast::Expr>, generics: &[Ident], locals: &mut Vec<Ident>) {
+        let span = expr.span();
+        match &mut **expr {
+            ast::Expr::Literal(_) | ast::Expr::Error => {},
+            ast::Expr::Path(path) => if path.len() != 1 || !locals.contains(&path.base()) {
+                let name = self.resolve(path.parts(), Namespace::Value, span, |name| format!("No such binding '{}' in scope", name));
+                *path = ast::Path::from(name);
+            },
+            ast::Expr::Intrinsic(_, args) | ast::Expr::List(args) | ast::Expr::Tuple(args) => args
+                .iter_mut()
+                .for_each(|arg| self.resolve_expr(arg, generics, locals)),
+            ast::Expr::Unary(_, a) => self.resolve_expr(a, generics, locals),
+            ast::Expr::Binary(_, a, b) | ast::Expr::Apply(a, b) => {
+                self.resolve_expr(a, generics, locals);
+                self.resolve_expr(b, generics, locals);
+            },
+            ast::Expr::If(pred, a, b) => {
+                self.resolve_expr(pred, generics, locals);
+                self.resolve_expr(a, generics, locals);
+                self.resolve_expr(b, generics, locals);
+            },
+            ast::Expr::Match(pred, arms) => {
+                self.resolve_expr(pred, generics, locals);
+                for ((binding, ty), guard, body) in arms.iter_mut() {
+                    self.resolve_binding(binding);
+                    if let Some(ty) = ty {
+                        self.resolve_type(ty, generics);
+                    }
+                    if let Some(guard) = guard {
+                        self.resolve_with_binding(binding, guard, generics, locals);
+                    }
+                    self.resolve_with_binding(binding, body, generics, locals);
+                }
+            },
+            ast::Expr::Func(param, ty, body) => {
+                self.resolve_binding(param);
+                if let Some(ty) = ty {
+                    self.resolve_type(ty, generics);
+                }
+                self.resolve_with_binding(param, body, generics, locals);
+            },
+            ast::Expr::Access(record, _) => self.resolve_expr(record, generics, locals),
+            ast::Expr::Let(binding, ty, val, then) => {
+                self.resolve_expr(val, generics, locals);
+                self.resolve_binding(binding);
+                if let Some(ty) = ty {
+                    self.resolve_type(ty, generics);
+                }
+                self.resolve_with_binding(binding, then, generics, locals);
+            },
+            ast::Expr::Record(fields) => fields
+                .iter_mut()
+                .for_each(|(_, value)| self.resolve_expr(value, generics, locals)),
+            ast::Expr::Update(record, field, value) => {
+                self.resolve_expr(record, generics, locals);
+                locals.push(**field);
+                self.resolve_expr(value, generics, locals);
+                locals.pop();
+            },
+            ast::Expr::Constructor(constructor, inner) => {
+                self.resolve_type_name(constructor, |name| format!("No data type with constructor '{}' exists", name));
+                self.resolve_expr(inner, generics, locals);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(src: &str) -> (SrcNode<ast::Module>, Vec<Error>) {
+        let (module, errors, _) = load_in(src, None);
+        (module, errors)
+    }
+
+    // Load the root module `src`, with submodules loaded from `dir`
+    fn load_in(src: &str, dir: Option<&FsPath>) -> (SrcNode<ast::Module>, Vec<Error>, SourceMap) {
+        let mut srcs = SourceMap::default();
+        let root_file = srcs.add("main.tao", src);
+        let (root, errors) = ast::parse_module(&lex::lex(srcs.code(root_file), root_file).unwrap());
+        assert_eq!(errors.len(), 0);
+        let prelude_file = srcs.add("prelude.tao", "
+            data Maybe A = Just A | None
+            fn len A |xs of [A]| 0
+        ");
+        let (prelude, errors) = ast::parse_module(&lex::lex(srcs.code(prelude_file), prelude_file).unwrap());
+        assert_eq!(errors.len(), 0);
+        let (_, module, errors) = ModuleTree::load(&mut srcs, root, dir, prelude);
+        (module, errors, srcs)
+    }
+
+    // Write a tree of modules to a fresh directory
+    fn module_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tao-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (path, code) in files.iter() {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, code).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn resolve_prelude() {
+        let (module, errors) = load("
+            def len_or_zero of Maybe [Num] -> Num = |xs| match xs {
+                | Just xs => len(xs)
+                | None => 0
+            }
+        ");
+        assert_eq!(errors.len(), 0);
+        let def = module.decls
+            .iter()
+            .find_map(|decl| match &**decl {
+                ast::Decl::Def(def) if def.name.as_str() == "len_or_zero" => Some(def),
+                _ => None,
+            })
+            .unwrap();
+        match &*def.ty {
+            ast::Type::Func(i, _) => match &**i {
+                ast::Type::Data(name, _) => assert_eq!(name.as_str(), "prelude::Maybe"),
+                _ => panic!("Expected data type"),
+            },
+            _ => panic!("Expected function type"),
+        }
+    }
+
+    #[test]
+    fn unresolved_names() {
+        let (_, errors) = load("
+            def main = foo::bar
+            def other = baz
+        ");
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn load_submodules() {
+        let dir = module_dir("submodules", &[
+            ("geo.tao", "mod util\ndata Shape = | Circle Num | Square Num\nfn area |s of Shape| match s {\n    | Circle r => r * r * 3\n    | Square w => util::double(w)\n}\n"),
+            ("geo/util.tao", "fn double |x of Num| x + x\n"),
+        ]);
+        let (module, errors, _) = load_in("
+            mod geo
+            use geo::Circle
+            def main = geo::area(Circle 2)
+            def unit of geo::Shape = geo::Square 1
+        ", Some(&dir));
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(errors.len(), 0, "{:?}", errors);
+
+        let mut names = module.decls
+            .iter()
+            .filter_map(|decl| match &**decl {
+                ast::Decl::Def(def) => Some(def.name.as_str().to_string()),
+                ast::Decl::Data(data) => Some(data.name.as_str().to_string()),
+                _ => None,
+            })
+            .filter(|name| !name.starts_with("prelude::"))
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["geo::Shape", "geo::area", "geo::util::double", "main", "unit"]);
+
+        let def = module.decls
+            .iter()
+            .find_map(|decl| match &**decl {
+                ast::Decl::Def(def) if def.name.as_str() == "unit" => Some(def),
+                _ => None,
+            })
+            .unwrap();
+        match &*def.ty {
+            ast::Type::Data(name, _) => assert_eq!(name.as_str(), "geo::Shape"),
+            _ => panic!("Expected data type"),
+        }
+    }
+
+    #[test]
+    fn import_conflict() {
+        let dir = module_dir("import-conflict", &[("geo.tao", "fn area |x of Num| x * x\n")]);
+        let (_, errors, srcs) = load_in("
+            mod geo
+            use geo::area
+            def area = 1
+        ", Some(&dir));
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert_eq!(errors[0].code(), Some("E0101"));
+
+        // The error points at both the import and the item it imports
+        let mut files = errors[0]
+            .diagnostic(&srcs)
+            .spans
+            .iter()
+            .map(|span| srcs.find(&span.file).unwrap())
+            .collect::<Vec<_>>();
+        files.dedup();
+        assert_eq!(files.len(), 2);
+        assert!(srcs.name(files[1]).ends_with("geo.tao"));
+    }
+}
@@ -0,0 +1,214 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use internment::LocalIntern;
+use crate::{
+    ast,
+    embed::{self, TypeDesc},
+    error::Error,
+    lex::{self, Token},
+    module::ModuleTree,
+    node::SrcNode,
+    src::{SourceMap, FileId, Span},
+    vm::Value,
+};
+
+type Ident = LocalIntern<String>;
+
+// The result of handling a line of input
+pub enum Output {
+    // An expression was evaluated
+    Value(Value, TypeDesc),
+    Type(TypeDesc),
+    // Items were declared
+    Declared,
+    Loaded(PathBuf),
+    // The definitions in scope, along with their types
+    Browse(Vec<(String, TypeDesc)>),
+    Quit,
+}
+
+// A REPL session, which remembers the declarations made so far
+pub struct Session {
+    srcs: SourceMap,
+    prelude: FileId,
+    // The file loaded with `:load`, which `:reload` reads again
+    loaded: Option<(PathBuf, FileId)>,
+    // Lines entered at the prompt that declared items
+    decls: Vec<FileId>,
+    modules: ModuleTree,
+    module: embed::Module,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        let mut srcs = SourceMap::default();
+        let prelude = crate::add_prelude(&mut srcs);
+        let (modules, module) = build(&mut srcs, prelude, None, &[])
+            .unwrap_or_else(|errors| panic!("The prelude failed to compile: {:?}", errors));
+        Self {
+            srcs,
+            prelude,
+            loaded: None,
+            decls: Vec::new(),
+            modules,
+            module,
+        }
+    }
+
+    // The sources of everything entered so far, for displaying errors
+    pub fn srcs(&self) -> &SourceMap {
+        &self.srcs
+    }
+
+    // Handle a line of input, which may be a command, declarations or an expression
+    pub fn handle(&mut self, line: &str) -> Result<Output, Vec<Error>> {
+        let line = line.trim();
+        if line.starts_with(':') {
+            let (cmd, arg) = match line.find(char::is_whitespace) {
+                Some(idx) => (&line[..idx], line[idx..].trim()),
+                None => (line, ""),
+            };
+            match cmd {
+                ":type" | ":t" => {
+                    let file = self.srcs.add("<repl>", arg);
+                    self.with_expr(file, |module, name| {
+                        let def = module.hir.root().def(name).unwrap();
+                        Ok(Output::Type(embed::describe(&module.hir.data_ctx, def.body.ty())))
+                    })
+                },
+                ":load" | ":l" => self.load(PathBuf::from(arg)),
+                ":reload" | ":r" => match self.loaded.as_ref().map(|(path, _)| path.clone()) {
+                    Some(path) => self.load(path),
+                    None => Err(vec![Error::custom(format!("No file has been loaded"))]),
+                },
+                ":browse" | ":b" => Ok(Output::Browse(self.module.defs())),
+                ":quit" | ":q" => Ok(Output::Quit),
+                _ => Err(vec![Error::custom(format!("Unknown command '{}'", cmd))
+                    .with_hint(format!("Commands are :type, :load, :reload, :browse and :quit"))]),
+            }
+        } else {
+            let file = self.srcs.add("<repl>", line);
+            let is_decl = lex::lex(self.srcs.code(file), file)?
+                .first()
+                .map_or(false, |token| match &**token {
+                    Token::Def | Token::Fn | Token::Type | Token::Data | Token::Class | Token::Instance | Token::Mod | Token::Use => true,
+                    _ => false,
+                });
+
+            if is_decl {
+                let mut decls = self.decls.clone();
+                decls.push(file);
+                self.rebuild(self.loaded.clone(), decls)?;
+                Ok(Output::Declared)
+            } else {
+                self.with_expr(file, |module, name| {
+                    let def = module.def(name.as_str())?;
+                    Ok(Output::Value(def.eval()?, def.ty().clone()))
+                })
+            }
+        }
+    }
+
+    fn load(&mut self, path: PathBuf) -> Result<Output, Vec<Error>> {
+        let code = fs::read_to_string(&path)
+            .map_err(|err| vec![Error::custom(format!("Could not read '{}': {}", path.display(), err))])?;
+        let file = self.srcs.add(path.display().to_string(), code);
+        self.rebuild(Some((path.clone(), file)), self.decls.clone())?;
+        Ok(Output::Loaded(path))
+    }
+
+    // Check the loaded file and declarations together, keeping them only if they are valid
+    fn rebuild(&mut self, loaded: Option<(PathBuf, FileId)>, decls: Vec<FileId>) -> Result<(), Vec<Error>> {
+        let (modules, module) = build(
+            &mut self.srcs,
+            self.prelude,
+            loaded.as_ref().map(|(path, file)| (path.as_path(), *file)),
+            &decls,
+        )?;
+        self.loaded = loaded;
+        self.decls = decls;
+        self.modules = modules;
+        self.module = module;
+        Ok(())
+    }
+
+    // Temporarily add the expression in `file` to the module as a definition
+    fn with_expr(
+        &mut self,
+        file: FileId,
+        f: impl FnOnce(&embed::Module, Ident) -> Result<Output, Vec<Error>>,
+    ) -> Result<Output, Vec<Error>> {
+        let tokens = lex::lex(self.srcs.code(file), file)?;
+        let mut def = ast::Def::main(ast::parse_expr(&tokens)?);
+        // Not a valid identifier, so it cannot conflict with a declared item
+        let name = Ident::new(format!("<repl>"));
+        def.name = SrcNode::new(name, Span::none());
+        self.modules.resolve_def(&mut def)?;
+
+        self.module.hir
+            .insert_def(&def)
+            .map_err(|e| vec![e])?;
+        let res = f(&self.module, name);
+        self.module.hir.remove_def(name);
+        res
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Type-check the loaded file and the declarations entered at the prompt as a single module
+fn build(
+    srcs: &mut SourceMap,
+    prelude: FileId,
+    loaded: Option<(&Path, FileId)>,
+    decls: &[FileId],
+) -> Result<(ModuleTree, embed::Module), Vec<Error>> {
+    let mut errors = Vec::new();
+    let mut root = ast::Module::default();
+    for file in loaded.map(|(_, file)| file).into_iter().chain(decls.iter().copied()) {
+        match crate::parse_file(srcs, file) {
+            Ok(module) => root.decls.extend(module.into_inner().decls),
+            Err(mut errs) => errors.append(&mut errs),
+        }
+    }
+    let prelude = crate::parse_file(srcs, prelude)?;
+
+    let dir = loaded.and_then(|(path, _)| path.parent());
+    let (modules, ast, mut module_errors) = ModuleTree::load(srcs, SrcNode::new(root, Span::none()), dir, prelude);
+    errors.append(&mut module_errors);
+    if errors.len() > 0 {
+        return Err(errors);
+    }
+
+    let hir = crate::hir::Program::new_root(&ast)?;
+    Ok((modules, embed::Module::from_hir(hir)))
+}
+
+#[test]
+fn session() {
+    let mut session = Session::new();
+
+    assert!(matches!(session.handle("fn double |x of Num| x * 2"), Ok(Output::Declared)));
+    assert!(matches!(session.handle("double(4)"), Ok(Output::Value(Value::Number(x), TypeDesc::Num)) if x == 8.0));
+    assert!(matches!(session.handle(":type double"), Ok(Output::Type(TypeDesc::Func(_, _)))));
+
+    // Invalid declarations are not kept
+    assert!(session.handle("def double = 5").is_err());
+    assert!(session.handle("def bad = nonexistent").is_err());
+    assert!(matches!(session.handle("double(1)"), Ok(Output::Value(Value::Number(x), _)) if x == 2.0));
+
+    assert!(matches!(session.handle("data Colour = | Red | Green"), Ok(Output::Declared)));
+    match session.handle(":browse") {
+        Ok(Output::Browse(defs)) => assert_eq!(defs.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), vec!["double"]),
+        _ => panic!("Expected definitions"),
+    }
+
+    assert!(session.handle(":reload").is_err());
+    assert!(matches!(session.handle(":quit"), Ok(Output::Quit)));
+}
@@ -31,11 +31,13 @@ impl Loc {
 
     pub fn in_context(&self, code: &str) -> (usize, usize) {
         let mut pos = self.0;
+        // Locations count characters, not bytes
         for (i, line) in code.lines().enumerate() {
-            if pos < line.len() + 1 {
+            let len = line.chars().count();
+            if pos < len + 1 {
                 return (i, pos);
             }
-            pos -= line.len() + 1;
+            pos -= len + 1;
         }
         (code.lines().count(), 0)
     }
@@ -71,11 +73,55 @@ impl From<u64> for Loc {
     }
 }
 
+// Identifies a file within a `SourceMap`. Spans that have not yet been attached to a file (such as those produced
+// during lexing) belong to the default file.
+#[derive(Copy, Clone, Hash, PartialEq, Eq, Default, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct FileId(usize);
+
+#[cfg_attr(feature = "serde", derive(Serialize))]
+struct SrcFile {
+    name: String,
+    code: String,
+}
+
+// A registry of every source file involved in compilation, used to render diagnostics against the right file
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct SourceMap {
+    files: Vec<SrcFile>,
+}
+
+impl SourceMap {
+    pub fn add(&mut self, name: impl Into<String>, code: impl Into<String>) -> FileId {
+        self.files.push(SrcFile {
+            name: name.into(),
+            code: code.into(),
+        });
+        FileId(self.files.len() - 1)
+    }
+
+    pub fn name(&self, file: FileId) -> &str {
+        &self.files[file.0].name
+    }
+
+    pub fn code(&self, file: FileId) -> &str {
+        &self.files[file.0].code
+    }
+
+    pub fn find(&self, name: &str) -> Option<FileId> {
+        self.files
+            .iter()
+            .position(|file| file.name == name)
+            .map(FileId)
+    }
+}
+
 #[derive(Copy, Clone, Hash, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum Span {
     None,
-    Range(Loc, Loc),
+    Range(FileId, Loc, Loc),
 }
 
 impl Span {
@@ -83,29 +129,59 @@ impl Span {
         Span::None
     }
 
-    pub const fn single(loc: Loc) -> Self {
-        Span::Range(loc, loc.next())
+    pub fn single(loc: Loc) -> Self {
+        Span::Range(FileId::default(), loc, loc.next())
     }
 
     pub fn range(from: Loc, until: Loc) -> Self {
         if from.0 < until.0 {
-            Span::Range(from, until)
+            Span::Range(FileId::default(), from, until)
         } else {
             Span::None
         }
     }
 
+    // Attach the span to the given file
+    pub fn in_file(self, file: FileId) -> Self {
+        match self {
+            Span::None => Span::None,
+            Span::Range(_, from, until) => Span::Range(file, from, until),
+        }
+    }
+
+    pub fn file(self) -> Option<FileId> {
+        match self {
+            Span::None => None,
+            Span::Range(file, _, _) => Some(file),
+        }
+    }
+
+    pub fn len(self) -> usize {
+        match self {
+            Span::None => 0,
+            Span::Range(_, from, until) => until.0 - from.0,
+        }
+    }
+
+    // The offsets of the first character in the span and of the character after it
+    pub fn bounds(self) -> Option<(usize, usize)> {
+        match self {
+            Span::None => None,
+            Span::Range(_, from, until) => Some((from.0, until.0)),
+        }
+    }
+
     pub fn contains(self, loc: Loc) -> bool {
         match self {
             Span::None => false,
-            Span::Range(from, until) => from.0 <= loc.0 && until.0 > loc.0,
+            Span::Range(_, from, until) => from.0 <= loc.0 && until.0 > loc.0,
         }
     }
 
     pub fn intersects(self, other: Self) -> bool {
         match (self, other) {
-            (Span::Range(from_a, until_a), Span::Range(from_b, until_b)) =>
-                !(until_a.0 <= from_b.0 || from_a.0 >= until_b.0),
+            (Span::Range(file_a, from_a, until_a), Span::Range(file_b, from_b, until_b)) =>
+                file_a == file_b && !(until_a.0 <= from_b.0 || from_a.0 >= until_b.0),
             _ => false,
         }
     }
@@ -113,19 +189,28 @@ impl Span {
     pub fn extend_to(self, limit: Loc) -> Self {
         match self {
             Span::None => Span::None,
-            Span::Range(from, until) => Span::Range(from, until.max(limit)),
+            Span::Range(file, from, until) => Span::Range(file, from, until.max(limit)),
         }
     }
 
-    pub fn union(self, other: Self) -> Self {
+    // Join two spans, or produce `None` if they are in different files
+    pub fn try_union(self, other: Self) -> Option<Self> {
         match (self, other) {
-            (Span::None, b) => b,
-            (a, Span::None) => a,
-            (Span::Range(from_a, until_a), Span::Range(from_b, until_b)) =>
-                Span::Range(from_a.min(from_b), until_a.max(until_b)),
+            (Span::None, b) => Some(b),
+            (a, Span::None) => Some(a),
+            (Span::Range(file_a, from_a, until_a), Span::Range(file_b, from_b, until_b)) if file_a == file_b =>
+                Some(Span::Range(file_a, from_a.min(from_b), until_a.max(until_b))),
+            _ => None,
         }
     }
 
+    // Join two spans in the same file. Use `try_union` if the spans may be in different files.
+    pub fn union(self, other: Self) -> Self {
+        let span = self.try_union(other);
+        debug_assert!(span.is_some(), "Cannot join spans in different files: {:?} and {:?}", self, other);
+        span.unwrap_or(self)
+    }
+
     pub fn homogenize(self, other: Self) -> Self {
         match (self, other) {
             (Span::None, other) => other,
@@ -135,15 +220,15 @@ impl Span {
 
     pub fn later_than(self, other: Self) -> bool {
         match (self, other) {
-            (Span::Range(from_a, until_a), Span::Range(from_b, until_b)) =>
-                until_a.later_than(until_b),
+            (Span::Range(file_a, _, until_a), Span::Range(file_b, _, until_b)) =>
+                file_a == file_b && until_a.later_than(until_b),
             _ => false,
         }
     }
 
     pub fn earliest(self, other: Self) -> Self {
         match (self, other) {
-            (Span::Range(a, _), Span::Range(b, _)) => if a.later_than(b) {
+            (Span::Range(file_a, a, _), Span::Range(file_b, b, _)) => if file_a == file_b && a.later_than(b) {
                 other
             } else {
                 self
@@ -154,7 +239,7 @@ impl Span {
 
     pub fn in_context(&self, code: &str) -> Option<((usize, usize), (usize, usize))> {
         match self {
-            Span::Range(from, until) => Some((from.in_context(code), until.in_context(code))),
+            Span::Range(_, from, until) => Some((from.in_context(code), until.in_context(code))),
             Span::None => None,
         }
     }
@@ -164,23 +249,11 @@ impl fmt::Debug for Span {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Span::None => write!(f, "<none>"),
-            Span::Range(from, to) => write!(f, "{:?}:{:?}", from, to),
+            Span::Range(file, from, to) => write!(f, "{:?}:{:?}:{:?}", file.0, from, to),
         }
     }
 }
 
-impl From<usize> for Span {
-    fn from(pos: usize) -> Self {
-        Span::Range(Loc::from(pos), Loc::from(pos + 1))
-    }
-}
-
-impl From<(usize, usize)> for Span {
-    fn from((from, to): (usize, usize)) -> Self {
-        Span::Range(Loc::from(from), Loc::from(to))
-    }
-}
-
 impl<T: Into<Loc>> From<Range<T>> for Span {
     fn from(range: Range<T>) -> Self {
         Self::range(range.start.into(), range.end.into())
@@ -221,3 +294,40 @@ impl<T> ParzeSpan<SrcNode<T>> for Span {
                 .unwrap_or(Span::none()))
     }
 }
+
+#[test]
+fn spans_in_files() {
+    use crate::error::Error;
+
+    let mut srcs = SourceMap::default();
+    let prelude = srcs.add("prelude.tao", include_str!("tao/prelude.tao"));
+    let main = srcs.add("main.tao", "def len = 5\n");
+
+    // Spans belong to the default file until they are attached to another
+    assert_eq!(Span::single(Loc::at(4)).file(), Some(FileId::default()));
+    assert_eq!(Span::range(Loc::at(4), Loc::at(7)).file(), Some(FileId::default()));
+    let a = Span::range(Loc::at(4), Loc::at(7)).in_file(main);
+    let b = Span::range(Loc::at(0), Loc::at(3)).in_file(main);
+    assert_eq!(a.union(b).bounds(), Some((0, 7)));
+    assert_eq!(a.try_union(Span::none()), Some(a));
+    assert_eq!(a.try_union(a.in_file(prelude)), None);
+
+    // Errors show the lines of each file that they mention
+    let pos = srcs.code(prelude).find("fn len").unwrap() + 3;
+    let line = srcs.code(prelude)[..pos].matches('\n').count() + 1;
+    let err = Error::custom(format!("Definition with name 'len' already exists"))
+        .with_span(Span::range(Loc::at(pos), Loc::at(pos + 3)).in_file(prelude))
+        .with_span(a);
+    assert_eq!(err.primary_span().file(), Some(prelude));
+    let text = format!("{}", err.in_source(&srcs));
+    assert!(text.contains(&format!("-> prelude.tao, line {}, column 4", line)), "{}", text);
+    assert!(text.contains("-> main.tao, line 1, column 5"), "{}", text);
+    assert!(text.contains("def len = 5"), "{}", text);
+    let files = err
+        .diagnostic(&srcs)
+        .spans
+        .into_iter()
+        .map(|span| span.file)
+        .collect::<Vec<_>>();
+    assert_eq!(files, vec!["prelude.tao", "main.tao"]);
+}
@@ -1,26 +1,62 @@
 use super::{Instr, CodeAddr, ConstAddr, Program, Value};
-use crate::mir;
+use crate::{
+    mir,
+    src::Span,
+};
 
-#[derive(Default)]
 pub struct ProcBuilder {
     code: Vec<Instr>,
+    // The span of the expression that produced each instruction
+    spans: Vec<Span>,
     consts: Vec<Value>,
     debug: Vec<(CodeAddr, String)>,
     global_calls: Vec<(CodeAddr, mir::DefId)>,
+    // The definition that the procedure belongs to, and whether the procedure is a closure within it
+    def: mir::DefId,
+    is_closure: bool,
+    // The spans of the expressions currently being compiled
+    span_stack: Vec<Span>,
 }
 
 impl ProcBuilder {
+    pub fn new(def: mir::DefId) -> Self {
+        Self {
+            code: Vec::new(),
+            spans: Vec::new(),
+            consts: Vec::new(),
+            debug: Vec::new(),
+            global_calls: Vec::new(),
+            def,
+            is_closure: false,
+            span_stack: Vec::new(),
+        }
+    }
+
+    // Create a builder for a closure within the same definition
+    pub fn closure(&self) -> Self {
+        Self {
+            is_closure: true,
+            ..Self::new(self.def)
+        }
+    }
+
+    pub fn push_span(&mut self, span: Span) {
+        self.span_stack.push(span);
+    }
+
+    pub fn pop_span(&mut self) {
+        self.span_stack.pop();
+    }
+
     pub fn emit_instr(&mut self, instr: Instr) -> CodeAddr {
         self.code.push(instr);
+        self.spans.push(self.span_stack.last().copied().unwrap_or(Span::none()));
         (self.code.len() - 1) as CodeAddr
     }
 
     pub fn emit_global_call(&mut self, id: mir::DefId) {
-        self.code.push(Instr::Nop);
-        self.global_calls.push((
-            (self.code.len() - 1) as CodeAddr,
-            id,
-        ));
+        let addr = self.emit_instr(Instr::Nop);
+        self.global_calls.push((addr, id));
     }
 
     pub fn emit_const(&mut self, c: Value) -> ConstAddr {
@@ -40,7 +76,7 @@ impl ProcBuilder {
         self.code.len() as CodeAddr
     }
 
-    pub fn link(mut self, program: &mut Program) -> (CodeAddr, Vec<(CodeAddr, mir::DefId)>) {
+    pub fn link(self, program: &mut Program) -> (CodeAddr, Vec<(CodeAddr, mir::DefId)>) {
         // Emit constants
         let const_offset = program.next_const_addr();
         for c in self.consts.into_iter() {
@@ -49,7 +85,8 @@ impl ProcBuilder {
 
         // Emit instructions
         let code_offset = program.next_instr_addr();
-        for instr in self.code {
+        program.emit_proc(code_offset, self.def, self.is_closure);
+        for (instr, span) in self.code.into_iter().zip(self.spans.into_iter()) {
             // Patch jumps to account for procedure offset
             let instr = match instr {
                 Instr::LoadConst(addr) => Instr::LoadConst(const_offset + addr),
@@ -57,7 +94,7 @@ impl ProcBuilder {
                 Instr::JumpIfNot(addr) => Instr::JumpIfNot(code_offset + addr),
                 instr => instr,
             };
-            program.emit_instr(instr);
+            program.emit_instr_at(instr, span);
         }
 
         for (addr, s) in self.debug {
@@ -32,9 +32,18 @@ const DEBUG: bool = true;
 
 impl RawTypeNode<mir::Expr> {
     pub fn compile(&self, program: &mut Program, scope: &mut (&mir::Program, &mut impl FnMut(CodeAddr, mir::DefId), Vec<Ident>), builder: &mut ProcBuilder) {
-        if DEBUG {
-            builder.emit_debug(format!("{:?}", self.span()));
-        }
+        self.compile_inner(program, scope, builder, false);
+    }
+
+    // Compile an expression whose value is immediately returned from the enclosing function, allowing calls to reuse
+    // the function's frame
+    pub fn compile_tail(&self, program: &mut Program, scope: &mut (&mir::Program, &mut impl FnMut(CodeAddr, mir::DefId), Vec<Ident>), builder: &mut ProcBuilder) {
+        self.compile_inner(program, scope, builder, true);
+    }
+
+    fn compile_inner(&self, program: &mut Program, scope: &mut (&mir::Program, &mut impl FnMut(CodeAddr, mir::DefId), Vec<Ident>), builder: &mut ProcBuilder, tail: bool) {
+        // Instructions are attributed to the innermost expression that produced them
+        builder.push_span(self.span());
 
         match &**self {
             mir::Expr::Literal(val) => match val {
@@ -103,9 +112,23 @@ impl RawTypeNode<mir::Expr> {
                         builder.emit_instr(Instr::NotBool);
                     },
                     (BinaryOp::Eq, mir::RawType::Primitive(Primitive::Boolean), mir::RawType::Primitive(Primitive::Boolean)) => { builder.emit_instr(Instr::EqBool); },
+                    (BinaryOp::NotEq, mir::RawType::Primitive(Primitive::Boolean), mir::RawType::Primitive(Primitive::Boolean)) => {
+                        builder.emit_instr(Instr::EqBool);
+                        builder.emit_instr(Instr::NotBool);
+                    },
                     (BinaryOp::And, mir::RawType::Primitive(Primitive::Boolean), mir::RawType::Primitive(Primitive::Boolean)) => { builder.emit_instr(Instr::AndBool); },
                     (BinaryOp::Or, mir::RawType::Primitive(Primitive::Boolean), mir::RawType::Primitive(Primitive::Boolean)) => { builder.emit_instr(Instr::OrBool); },
                     (BinaryOp::Join, mir::RawType::List(_), mir::RawType::List(_)) => { builder.emit_instr(Instr::JoinList); },
+                    // Everything else is compared structurally (the type checker has already rejected functions)
+                    (BinaryOp::Eq, _, _) => { builder.emit_instr(Instr::EqDeep); },
+                    (BinaryOp::NotEq, _, _) => {
+                        builder.emit_instr(Instr::EqDeep);
+                        builder.emit_instr(Instr::NotBool);
+                    },
+                    (BinaryOp::More, _, _) => { builder.emit_instr(Instr::MoreDeep); },
+                    (BinaryOp::Less, _, _) => { builder.emit_instr(Instr::LessDeep); },
+                    (BinaryOp::MoreEq, _, _) => { builder.emit_instr(Instr::MoreEqDeep); },
+                    (BinaryOp::LessEq, _, _) => { builder.emit_instr(Instr::LessEqDeep); },
                     binary => todo!("Implement binary expression {:?}", binary),
                 };
             },
@@ -121,61 +144,33 @@ impl RawTypeNode<mir::Expr> {
                 }
                 builder.emit_instr(Instr::MakeList(items.len() as u32));
             },
-            mir::Expr::Match(pred, arms) => {
+            mir::Expr::Match(pred, decision, arms) => {
                 pred.compile(program, scope, builder);
-                let mut exit_jumps = Vec::new();
-                for (i, (matcher, extractor, body)) in arms.iter().enumerate() {
-                    if !matcher.is_refutable() || arms.len() == 1 {
-                        extractor.compile(builder);
-                        let bindings = extractor.get_bindings();
-                        bindings.iter().for_each(|b| scope.2.push(*b)); // Push locals
-                        body.compile(program, scope, builder);
-                        bindings.iter().for_each(|_| { // Pop locals
-                            scope.2.pop();
-                            builder.emit_instr(Instr::PopLocal);
-                        });
-                        break; // This arm matches everything, so no need to continue
-                    } else {
-                        let fail_jump_addr = if i < arms.len() - 1 { // Don't check for last arm
-                            builder.emit_instr(Instr::Dup); // Duplicate predicate if it's needed later during extraction
-                            matcher.compile(builder);
-                            Some(builder.emit_instr(Instr::Nop)) // To be patched later
-                        } else {
-                            None
-                        };
-
-                        extractor.compile(builder);
-                        let bindings = extractor.get_bindings();
-                        bindings.iter().for_each(|b| scope.2.push(*b)); // Push locals
-                        body.compile(program, scope, builder);
-                        bindings.iter().for_each(|_| { // Pop locals
-                            scope.2.pop();
-                            builder.emit_instr(Instr::PopLocal);
-                        });
-                        // Don't jump for the last arm, since we'd be jumping to the next instr anyway
-                        if i < arms.len() - 1 {
-                            exit_jumps.push(builder.emit_instr(Instr::Nop)); // To be patched later
-                        }
-
-                        if let Some(fail_jump_addr) = fail_jump_addr {
-                            builder.patch_instr(fail_jump_addr, Instr::JumpIfNot(builder.next_addr()));
-                        }
+                // Matches that cannot fail, such as `let`, need no tests or jumps
+                if let mir::Decision::Arm(idx, alt) = decision {
+                    let (extractors, _, body) = &arms[*idx];
+                    extractors[*alt].compile(builder);
+                    compile_body(&extractors[*alt].get_bindings(), body, program, scope, builder, tail);
+                } else {
+                    let mut arm_addrs = HashMap::new();
+                    let mut exit_jumps = Vec::new();
+                    decision.compile(arms, &mut arm_addrs, &mut exit_jumps, program, scope, builder, tail);
+                    for exit_jump in exit_jumps {
+                        builder.patch_instr(exit_jump, Instr::Jump(builder.next_addr()));
                     }
                 }
-                for exit_jump in exit_jumps {
-                    builder.patch_instr(exit_jump, Instr::Jump(builder.next_addr()));
-                }
             },
             mir::Expr::Func(extractor, env, body) => {
                 // Create body
                 let func_addr = {
-                    let mut builder = ProcBuilder::default();
+                    let mut builder = builder.closure();
+                    builder.push_span(self.span());
 
                     extractor.compile(&mut builder);
                     let bindings = extractor.get_bindings();
                     let mut func_scope = env.clone();
                     bindings.iter().for_each(|b| func_scope.push(*b)); // Push locals
-                    body.compile(program, &mut (scope.0, scope.1, func_scope), &mut builder);
+                    body.compile_tail(program, &mut (scope.0, scope.1, func_scope), &mut builder);
                     bindings.iter().for_each(|_| { // Pop pattern locals
                         builder.emit_instr(Instr::PopLocal);
                     });
@@ -209,7 +204,13 @@ impl RawTypeNode<mir::Expr> {
             mir::Expr::Apply(f, arg) => {
                 arg.compile(program, scope, builder);
                 f.compile(program, scope, builder);
-                builder.emit_instr(Instr::ApplyFunc);
+                if tail {
+                    // The locals of the current frame are no longer needed, so drop them and jump straight to the
+                    // function. The instructions that would otherwise pop them are never reached.
+                    builder.emit_instr(Instr::TailApplyFunc(scope.2.len() as u32));
+                } else {
+                    builder.emit_instr(Instr::ApplyFunc);
+                }
             },
             mir::Expr::Access(tuple, index) => {
                 tuple.compile(program, scope, builder);
@@ -233,126 +234,173 @@ impl RawTypeNode<mir::Expr> {
                 builder.emit_instr(Instr::SetList(*index as u32));
             },
         }
+
+        builder.pop_span();
     }
 }
 
-impl mir::Matcher {
-    pub fn compile(&self, builder: &mut ProcBuilder) {
-        match self {
-            // Should be handled in `Expr::DestructureValue` compilation
-            mir::Matcher::Wildcard => unreachable!(),
-            mir::Matcher::Exactly(val) => match val {
-                // If the value is true, and we're looking for true... then we already have the answer
-                // No need to do `if x = true` when you can just do `if x`.
-                Literal::Boolean(true) => {},
-                // If the value is true and we're looking for false... just negate the result. Easy.
-                Literal::Boolean(false) => { builder.emit_instr(Instr::NotBool); },
-                Literal::Char(c) => {
-                    builder.emit_instr(Instr::Char(*c));
-                    builder.emit_instr(Instr::EqChar);
-                },
-                Literal::Number(x) => {
-                    emit_constant_num(builder, *x);
-                    builder.emit_instr(Instr::EqNum);
-                },
-                Literal::String(x) => todo!(),
-            },
-            mir::Matcher::Product(items) => {
-                let mut fail_jumps = Vec::new();
-                let refutable_items = items
-                    .iter()
-                    .enumerate()
-                    .filter(|(_, item)| item.is_refutable())
-                    .collect::<Vec<_>>();
-                for (i, item) in refutable_items.iter() {
-                    builder.emit_instr(Instr::Dup);
-                    builder.emit_instr(Instr::IndexList(*i as u32));
-                    item.compile(builder);
-                    fail_jumps.push(builder.emit_instr(Instr::Nop));
-                }
-                builder.emit_instr(Instr::Pop); // Pop matched value
-                builder.emit_instr(Instr::True);
-
-                // If any of the tuples failed to match
-                if fail_jumps.len() > 0 {
-                    let true_jump = builder.emit_instr(Instr::Nop);
+// Compile the body of a `match` arm, with the bindings of its pattern having just been extracted
+fn compile_body(
+    bindings: &[Ident],
+    body: &RawTypeNode<mir::Expr>,
+    program: &mut Program,
+    scope: &mut (&mir::Program, &mut impl FnMut(CodeAddr, mir::DefId), Vec<Ident>),
+    builder: &mut ProcBuilder,
+    tail: bool,
+) {
+    bindings.iter().for_each(|b| scope.2.push(*b)); // Push locals
+    body.compile_inner(program, scope, builder, tail);
+    bindings.iter().for_each(|_| { // Pop locals
+        scope.2.pop();
+        builder.emit_instr(Instr::PopLocal);
+    });
+}
 
-                    for addr in fail_jumps {
-                        builder.patch_instr(addr, Instr::JumpIfNot(builder.next_addr()));
-                    }
-                    builder.emit_instr(Instr::Pop); // Pop matched value
-                    builder.emit_instr(Instr::False);
+// Push the part of the value at the top of the stack found at the path, keeping the value
+fn emit_path(builder: &mut ProcBuilder, path: &[usize]) {
+    builder.emit_instr(Instr::Dup);
+    for idx in path {
+        builder.emit_instr(Instr::IndexList(*idx as u32));
+    }
+}
 
-                    builder.patch_instr(true_jump, Instr::Jump(builder.next_addr()));
+impl mir::Decision {
+    // Compile the decision tree, with the value being matched at the top of the stack. The body of each arm is compiled
+    // where it is first reached and other paths to it jump there, so that bodies are never duplicated.
+    fn compile(
+        &self,
+        arms: &[(Vec<mir::Extractor>, Option<RawTypeNode<mir::Expr>>, RawTypeNode<mir::Expr>)],
+        arm_addrs: &mut HashMap<(usize, Vec<Ident>), CodeAddr>,
+        exit_jumps: &mut Vec<CodeAddr>,
+        program: &mut Program,
+        scope: &mut (&mir::Program, &mut impl FnMut(CodeAddr, mir::DefId), Vec<Ident>),
+        builder: &mut ProcBuilder,
+        tail: bool,
+    ) {
+        match self {
+            mir::Decision::Arm(idx, alt) => {
+                let (extractors, _, body) = &arms[*idx];
+                extractors[*alt].compile(builder);
+                // Alternatives that bind the same names in the same order can share the body
+                let bindings = extractors[*alt].get_bindings();
+                match arm_addrs.get(&(*idx, bindings.clone())) {
+                    Some(addr) => { builder.emit_instr(Instr::Jump(*addr)); },
+                    None => {
+                        arm_addrs.insert((*idx, bindings.clone()), builder.next_addr());
+                        compile_body(&bindings, body, program, scope, builder, tail);
+                        exit_jumps.push(builder.emit_instr(Instr::Nop)); // To be patched later
+                    },
                 }
             },
-            mir::Matcher::List(items) => {
-                let mut fail_jumps = Vec::new();
-
+            mir::Decision::Guard(idx, alt, otherwise) => {
+                // Evaluate the guard with the arm's bindings, leaving the value being matched in place
+                let (extractors, guard, _) = &arms[*idx];
+                let extractor = &extractors[*alt];
                 builder.emit_instr(Instr::Dup);
-                builder.emit_instr(Instr::LenEqList(items.len() as u32));
-                fail_jumps.push(builder.emit_instr(Instr::Nop));
+                extractor.compile(builder);
+                let bindings = extractor.get_bindings();
+                bindings.iter().for_each(|b| scope.2.push(*b)); // Push locals
+                guard
+                    .as_ref()
+                    .expect("Guarded arms have a guard")
+                    .compile(program, scope, builder);
+                bindings.iter().for_each(|_| { // Pop locals
+                    scope.2.pop();
+                    builder.emit_instr(Instr::PopLocal);
+                });
+                let fail_jump = builder.emit_instr(Instr::Nop); // To be patched later
+
+                mir::Decision::Arm(*idx, *alt).compile(arms, arm_addrs, exit_jumps, program, scope, builder, tail);
+
+                // Fall through to the arms after this one
+                builder.patch_instr(fail_jump, Instr::JumpIfNot(builder.next_addr()));
+                otherwise.compile(arms, arm_addrs, exit_jumps, program, scope, builder, tail);
+            },
+            mir::Decision::Fail => {
+                let msg = builder.emit_const(Value::List(Rc::new(
+                    "Match arms are not exhaustive".chars().map(Value::Char).collect()
+                )));
+                builder.emit_instr(Instr::LoadConst(msg));
+                builder.emit_instr(Instr::Intrinsic(mir::Intrinsic::Panic));
+            },
+            mir::Decision::Variant(path, cases) | mir::Decision::Length(path, cases) => {
+                emit_path(builder, path);
+                if let mir::Decision::Variant(_, _) = self {
+                    builder.emit_instr(Instr::IndexList(0)); // The tag of the variant
+                } else {
+                    builder.emit_instr(Instr::LenList);
+                }
 
-                let refutable_items = items
+                builder.emit_instr(Instr::JumpTable(cases.len() as u32));
+                let table = cases
                     .iter()
-                    .enumerate()
-                    .filter(|(_, item)| item.is_refutable())
+                    .map(|_| builder.emit_instr(Instr::Nop)) // To be patched later
                     .collect::<Vec<_>>();
-                for (i, item) in refutable_items.iter() {
-                    builder.emit_instr(Instr::Dup);
-                    builder.emit_instr(Instr::IndexList(*i as u32));
-                    item.compile(builder);
-                    fail_jumps.push(builder.emit_instr(Instr::Nop));
+                for (case, addr) in cases.iter().zip(table) {
+                    builder.patch_instr(addr, Instr::Jump(builder.next_addr()));
+                    case.compile(arms, arm_addrs, exit_jumps, program, scope, builder, tail);
                 }
-                builder.emit_instr(Instr::Pop); // Pop matched value
-                builder.emit_instr(Instr::True);
+            },
+            mir::Decision::Literal(path, cases, default) => {
+                let exhaustive = matches!(&**default, mir::Decision::Fail);
+                for (i, (litr, case)) in cases.iter().enumerate() {
+                    // If nothing else can match, there's no need to check the last literal
+                    let fail_jump = if i + 1 < cases.len() || !exhaustive {
+                        emit_path(builder, path);
+                        match litr {
+                            // No need to do `if x = true` when you can just do `if x`
+                            Literal::Boolean(true) => {},
+                            Literal::Boolean(false) => { builder.emit_instr(Instr::NotBool); },
+                            Literal::Char(c) => {
+                                builder.emit_instr(Instr::Char(*c));
+                                builder.emit_instr(Instr::EqChar);
+                            },
+                            Literal::Number(x) => {
+                                emit_constant_num(builder, *x);
+                                builder.emit_instr(Instr::EqNum);
+                            },
+                            Literal::String(_) => unreachable!("Strings are matched as lists of characters"),
+                        }
+                        Some(builder.emit_instr(Instr::Nop)) // To be patched later
+                    } else {
+                        None
+                    };
 
-                // If any of the tuples failed to match
-                if fail_jumps.len() > 0 {
-                    let true_jump = builder.emit_instr(Instr::Nop);
+                    case.compile(arms, arm_addrs, exit_jumps, program, scope, builder, tail);
 
-                    for addr in fail_jumps {
-                        builder.patch_instr(addr, Instr::JumpIfNot(builder.next_addr()));
+                    if let Some(fail_jump) = fail_jump {
+                        builder.patch_instr(fail_jump, Instr::JumpIfNot(builder.next_addr()));
                     }
-                    builder.emit_instr(Instr::Pop); // Pop matched value
-                    builder.emit_instr(Instr::False);
-
-                    builder.patch_instr(true_jump, Instr::Jump(builder.next_addr()));
+                }
+                if !exhaustive {
+                    default.compile(arms, arm_addrs, exit_jumps, program, scope, builder, tail);
                 }
             },
-            mir::Matcher::ListFront(items) => {
+            mir::Decision::Range(path, start, end, inside, outside) => {
+                // Compare the value with each end of the range, leaving as soon as it is outside of it
                 let mut fail_jumps = Vec::new();
-
-                builder.emit_instr(Instr::Dup);
-                builder.emit_instr(Instr::LenMoreEqList(items.len() as u32));
-                fail_jumps.push(builder.emit_instr(Instr::Nop));
-
-                let refutable_items = items
-                    .iter()
-                    .enumerate()
-                    .filter(|(_, item)| item.is_refutable())
-                    .collect::<Vec<_>>();
-                for (i, item) in refutable_items.iter() {
-                    builder.emit_instr(Instr::Dup);
-                    builder.emit_instr(Instr::IndexList(*i as u32));
-                    item.compile(builder);
-                    fail_jumps.push(builder.emit_instr(Instr::Nop));
+                for &(bound, is_start) in [(start, true), (end, false)].iter() {
+                    emit_path(builder, path);
+                    match bound {
+                        Literal::Number(x) => {
+                            emit_constant_num(builder, *x);
+                            builder.emit_instr(if is_start { Instr::LessEqNum } else { Instr::MoreEqNum });
+                        },
+                        Literal::Char(c) => {
+                            builder.emit_instr(Instr::Char(*c));
+                            builder.emit_instr(if is_start { Instr::LessEqDeep } else { Instr::MoreEqDeep });
+                        },
+                        _ => unreachable!("Only numbers and characters may be matched by a range"),
+                    }
+                    fail_jumps.push(builder.emit_instr(Instr::Nop)); // To be patched later
                 }
-                builder.emit_instr(Instr::Pop); // Pop matched value
-                builder.emit_instr(Instr::True);
 
-                // If any of the tuples failed to match
-                if fail_jumps.len() > 0 {
-                    let true_jump = builder.emit_instr(Instr::Nop);
-
-                    for addr in fail_jumps {
-                        builder.patch_instr(addr, Instr::JumpIfNot(builder.next_addr()));
-                    }
-                    builder.emit_instr(Instr::Pop); // Pop matched value
-                    builder.emit_instr(Instr::False);
+                inside.compile(arms, arm_addrs, exit_jumps, program, scope, builder, tail);
 
-                    builder.patch_instr(true_jump, Instr::Jump(builder.next_addr()));
+                for fail_jump in fail_jumps {
+                    builder.patch_instr(fail_jump, Instr::JumpIfNot(builder.next_addr()));
                 }
+                outside.compile(arms, arm_addrs, exit_jumps, program, scope, builder, tail);
             },
         }
     }
@@ -408,7 +456,7 @@ impl mir::Extractor {
 }
 
 impl mir::Program {
-    pub fn compile(&self, is_pure: bool) -> Result<Program, Error> {
+    pub fn compile(&self, is_pure: bool) -> Result<Program, Vec<Error>> {
         let mut program = Program::default();
 
         program.set_pure(is_pure || self.globals.get(&self.entry).and_then(|e| e.as_ref().map(|e| e.ty())) != Some(&mir::RawType::Func(
@@ -424,7 +472,8 @@ impl mir::Program {
         let globals = self
             .globals()
             .map(|(id, global)| {
-                let mut builder = ProcBuilder::default();
+                let mut builder = ProcBuilder::new(id);
+                builder.push_span(global.span());
 
                 if DEBUG {
                     builder.emit_debug(format!(":: {} {} of {}", id.0, id.1.iter().map(|ty| ty.mangle()).collect::<Vec<_>>().join(", "), global.ty().mangle()));
@@ -463,3 +512,164 @@ impl mir::Program {
         Ok(program)
     }
 }
+
+#[cfg(test)]
+fn compile_test(src: &str) -> Program {
+    use crate::{ast, hir, lex, module::ModuleTree, src::SourceMap};
+
+    let mut srcs = SourceMap::default();
+    let file = srcs.add("main.tao", src);
+    let (root, errors) = ast::parse_module(&lex::lex(srcs.code(file), file).unwrap());
+    assert_eq!(errors.len(), 0);
+    let prelude = crate::parse_prelude(&mut srcs).unwrap();
+    let (_, module, errors) = ModuleTree::load(&mut srcs, root, None, prelude);
+    assert_eq!(errors.len(), 0);
+    let hir_prog = hir::Program::new_root(&module).unwrap();
+    mir::Program::from_hir(&hir_prog, Ident::new("main".to_string()), Vec::new())
+        .unwrap()
+        .compile(true)
+        .unwrap()
+}
+
+// Run `main` of the given module, producing its value as it would be displayed
+#[cfg(test)]
+fn run_test(src: &str) -> String {
+    use crate::vm::{Vm, BufferIo};
+
+    let val = Vm::default().execute(&compile_test(src), &mut BufferIo::default()).unwrap().unwrap();
+    format!("{}", val)
+}
+
+#[test]
+fn tail_calls() {
+    use crate::vm::{Vm, BufferIo};
+
+    let prog = compile_test("
+        fn count |n, xs| if n = 0 then xs else count(n - 1, [n] ++ xs)
+        def main = fold_l(0, |a, x| a + x, count(1000000, []))
+    ");
+
+    let mut vm = Vm::default();
+    let val = vm.execute(&prog, &mut BufferIo::default()).unwrap().unwrap();
+    assert!(matches!(val, Value::Number(x) if x == 500000500000.0));
+    // Neither `count` nor `fold_l` should grow the call stack as they recurse
+    assert!(vm.max_call_depth() < 16, "max call depth = {}", vm.max_call_depth());
+}
+
+#[test]
+fn match_decisions() {
+    let src = "
+        fn classify |x| match x {
+            | (Just 0, []) => 0
+            | (Just n, [a, ...]) => n + a
+            | (Just _, _) => 100
+            | (None, [_, _]) => 200
+            | (None, _) => 300
+        }
+        fn letter |c| match c {
+            | 'a' => 1
+            | 'b' => 2
+            | _ => 3
+        }
+        def main = [
+            classify((Just 0, [])),
+            classify((Just 2, [5])),
+            classify((Just 1, [])),
+            classify((None, [1, 2])),
+            classify((None, [1])),
+            classify((None, [1, 2, 3])),
+            letter('b'),
+            letter('z')
+        ]
+    ";
+    assert_eq!(run_test(src), "[0, 7, 100, 200, 300, 300, 2, 3]");
+    // Variants and list lengths are each switched on once
+    assert!(format!("{:?}", compile_test(src)).contains("jump_table"));
+}
+
+#[test]
+fn match_guards() {
+    // Arms whose guard fails fall through to the arms after them
+    assert_eq!(run_test("
+        fn first_even |xs| match xs {
+            | [x, ...] if x % 2 = 0 => x
+            | [_, xs: ...] => first_even(xs)
+            | [] => -1
+        }
+        fn sign |n| match n {
+            | n if n < 0 => -1
+            | 0 => 0
+            | _ => 1
+        }
+        fn pick |m| match m {
+            | Just (a, b) if a = b => 0
+            | Just (a, _) if a > 10 => a
+            | Just (_, b) => b
+            | None => -1
+        }
+        def main = [
+            first_even([1, 3, 4, 5]),
+            first_even([1]),
+            sign(-3),
+            sign(0),
+            sign(7),
+            pick(Just (2, 2)),
+            pick(Just (11, 3)),
+            pick(Just (1, 3)),
+            pick(None)
+        ]
+    "), "[4, -1, -1, 0, 1, 0, 11, 3, -1]");
+}
+
+#[test]
+fn or_and_range_patterns() {
+    assert_eq!(run_test("
+        fn kind |c| match c {
+            | 'a' | 'e' | 'i' | 'o' | 'u' => 0
+            | 'a'..'z' => 1
+            | '0'..'9' => 2
+            | _ => 3
+        }
+        fn size |x| match x {
+            | (0, n) | (n, 0) => n
+            | (1..9, _) => 10
+            | _ => 20
+        }
+        fn either |p| match p {
+            | (Just x, _) | (None, x) => x
+        }
+        def main = [
+            kind('e'),
+            kind('z'),
+            kind('5'),
+            kind('!'),
+            size((0, 7)),
+            size((3, 0)),
+            size((5, 5)),
+            size((9.5, 1)),
+            either((Just 1, 2)),
+            either((None, 3))
+        ]
+    "), "[0, 1, 2, 3, 7, 3, 10, 20, 1, 3]");
+}
+
+#[test]
+fn string_patterns() {
+    assert_eq!(run_test("
+        fn command |s| match s {
+            | \"quit\" => \"bye\"
+            | \"GET \" ++ path => path
+            | ['-', flag: ...] => flag
+            | \"\" => \"empty\"
+            | _ => \"unknown\"
+        }
+        def main = [
+            command(\"quit\"),
+            command(\"GET /index\"),
+            command(\"-v\"),
+            command(\"\"),
+            command(\"qu\"),
+            command(\"quits\")
+        ]
+    "), "[\"bye\", \"/index\", \"v\", \"empty\", \"unknown\", \"unknown\"]");
+}
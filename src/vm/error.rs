@@ -0,0 +1,133 @@
+use std::fmt;
+use crate::error::Error;
+use super::{CodeAddr, Program};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RuntimeErrorKind {
+    // The program failed verification and was not executed
+    InvalidProgram(String),
+    // An instruction tried to pop a value from an empty stack
+    StackUnderflow,
+    // An instruction tried to access a local that does not exist
+    LocalUnderflow,
+    // An instruction found a value of the wrong type (the expected type is given)
+    TypeMismatch(&'static str),
+    BadIndex { index: usize, len: usize },
+    // Division (or remainder) by zero is an error rather than producing infinity or NaN
+    DivideByZero,
+    // A universe was used more than once, or after a later universe had been created
+    ForkedUniverse,
+    Io(String),
+    // The program explicitly panicked with the given message
+    Panic(String),
+    // A native function (given by name) failed with the given message
+    Native(String, String),
+    // The execution used all of its fuel. It may be resumed with more.
+    OutOfFuel,
+    CallDepthExceeded,
+    LocalsExceeded,
+    // Too many list elements were allocated
+    MemoryExceeded,
+}
+
+impl fmt::Display for RuntimeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeErrorKind::InvalidProgram(msg) => write!(f, "Invalid program: {}", msg),
+            RuntimeErrorKind::StackUnderflow => write!(f, "Stack underflow"),
+            RuntimeErrorKind::LocalUnderflow => write!(f, "Local stack underflow"),
+            RuntimeErrorKind::TypeMismatch(expected) => write!(f, "Type mismatch: expected {}", expected),
+            RuntimeErrorKind::BadIndex { index, len } => write!(f, "Index {} is out of bounds for list of length {}", index, len),
+            RuntimeErrorKind::DivideByZero => write!(f, "Division by zero"),
+            RuntimeErrorKind::ForkedUniverse => write!(f, "Forked universe"),
+            RuntimeErrorKind::Io(msg) => write!(f, "IO error: {}", msg),
+            RuntimeErrorKind::Panic(msg) => write!(f, "Panicked: {}", msg),
+            RuntimeErrorKind::Native(name, msg) => write!(f, "Native function '{}' failed: {}", name, msg),
+            RuntimeErrorKind::OutOfFuel => write!(f, "Out of fuel"),
+            RuntimeErrorKind::CallDepthExceeded => write!(f, "Maximum call depth exceeded"),
+            RuntimeErrorKind::LocalsExceeded => write!(f, "Maximum number of locals exceeded"),
+            RuntimeErrorKind::MemoryExceeded => write!(f, "Maximum number of list elements exceeded"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    // The address of the instruction that caused the error
+    pub addr: CodeAddr,
+    // The addresses of the calls that led to the error, innermost first
+    pub trace: Vec<CodeAddr>,
+}
+
+impl RuntimeError {
+    pub fn new(kind: RuntimeErrorKind, addr: CodeAddr) -> Self {
+        Self { kind, addr, trace: Vec::new() }
+    }
+
+    pub fn with_trace(mut self, trace: Vec<CodeAddr>) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    // Produce an error that points at the failing expression, along with a stack trace of the calls that led to it
+    pub fn into_error(self, prog: &Program) -> Error {
+        std::iter::once(self.addr)
+            .chain(self.trace.iter().copied())
+            .fold(
+                Error::custom(format!("Runtime error: {}", self.kind)).with_code("E0500").with_span(prog.span_at(self.addr)),
+                |err, addr| {
+                    let name = match prog.proc_at(addr) {
+                        Some(proc) if proc.is_closure => format!("<closure in {}>", proc.def.0),
+                        Some(proc) => format!("{}", proc.def.0),
+                        None => format!("<unknown>"),
+                    };
+                    err.with_frame(name, prog.span_at(addr))
+                },
+            )
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (at {:#X})", self.kind, self.addr)
+    }
+}
+
+#[test]
+fn stack_traces() {
+    use crate::{embed::compile_main, vm::BufferIo};
+
+    // `check` leaves through a tail call to `unreachable`, which tail calls `panic`, and the outer closure of `f` tail
+    // applies the inner one. None of them should appear in the trace.
+    let src = "fn check |x| if x > 1 then unreachable(\"too big\") else x\n\
+        fn f |x| (|y| check(y) + 1)(x)\n\
+        def main = f(5) * 2\n";
+    let (srcs, module) = compile_main(src);
+    let errors = module
+        .unwrap()
+        .def("main")
+        .unwrap()
+        .run(&mut BufferIo::default())
+        .unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].code(), Some("E0500"));
+
+    let prelude = srcs.find("prelude.tao").unwrap();
+    let pos = srcs.code(prelude).find("@panic(s)").unwrap();
+    let panic_line = srcs.code(prelude)[..pos].matches('\n').count() + 1;
+
+    let text = format!("{}", errors[0].in_source(&srcs));
+    assert!(text.contains("Runtime error: Panicked: Unreachable: too big"), "{}", text);
+    assert!(text.contains("Stack trace (calls in tail position are not shown):"), "{}", text);
+    let frames = text
+        .lines()
+        .filter(|line| line.starts_with("  at "))
+        .map(|line| line.split(", column").next().unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(frames, vec![
+        format!("  at <closure in prelude::panic> (prelude.tao, line {}", panic_line),
+        format!("  at <closure in f> (main.tao, line 2"),
+        format!("  at main (main.tao, line 3"),
+    ], "{}", text);
+}
@@ -0,0 +1,110 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+};
+use utf8_chars::BufReadCharsExt;
+
+// The means by which a program's `@in` and `@out` intrinsics interact with the outside world
+pub trait IoHandler {
+    // Read the next character of input, or `None` if the input has ended
+    fn read_char(&mut self) -> Result<Option<char>, String>;
+
+    fn write_char(&mut self, c: char) -> Result<(), String>;
+}
+
+// Performs IO using the process' stdin and stdout
+#[derive(Default)]
+pub struct StdIo;
+
+impl IoHandler for StdIo {
+    fn read_char(&mut self) -> Result<Option<char>, String> {
+        io::stdin()
+            .lock()
+            .chars()
+            .next()
+            .transpose()
+            .map_err(|err| format!("{}", err))
+    }
+
+    fn write_char(&mut self, c: char) -> Result<(), String> {
+        print!("{}", c);
+        if c == '\n' {
+            io::stdout()
+                .lock()
+                .flush()
+                .map_err(|err| format!("{}", err))?;
+        }
+        Ok(())
+    }
+}
+
+// Reads input from, and collects output into, in-memory buffers
+#[derive(Default)]
+pub struct BufferIo {
+    input: VecDeque<char>,
+    output: String,
+}
+
+impl BufferIo {
+    pub fn new(input: &str) -> Self {
+        Self {
+            input: input.chars().collect(),
+            output: String::new(),
+        }
+    }
+
+    // The input that has not yet been read
+    pub fn remaining_input(&self) -> String {
+        self.input.iter().collect()
+    }
+
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    pub fn into_output(self) -> String {
+        self.output
+    }
+}
+
+impl IoHandler for BufferIo {
+    fn read_char(&mut self) -> Result<Option<char>, String> {
+        Ok(self.input.pop_front())
+    }
+
+    fn write_char(&mut self, c: char) -> Result<(), String> {
+        self.output.push(c);
+        Ok(())
+    }
+}
+
+// Performs IO by calling the given functions
+pub struct FnIo<R, W> {
+    read: R,
+    write: W,
+}
+
+impl<R, W> FnIo<R, W>
+where
+    R: FnMut() -> Option<char>,
+    W: FnMut(char),
+{
+    pub fn new(read: R, write: W) -> Self {
+        Self { read, write }
+    }
+}
+
+impl<R, W> IoHandler for FnIo<R, W>
+where
+    R: FnMut() -> Option<char>,
+    W: FnMut(char),
+{
+    fn read_char(&mut self) -> Result<Option<char>, String> {
+        Ok((self.read)())
+    }
+
+    fn write_char(&mut self, c: char) -> Result<(), String> {
+        (self.write)(c);
+        Ok(())
+    }
+}
@@ -3,9 +3,15 @@ mod value;
 mod builder;
 mod compile;
 mod vm;
+mod error;
+mod io;
+mod native;
 
 pub use self::{
-    program::{Instr, CodeAddr, ConstAddr, Program},
+    program::{Instr, CodeAddr, ConstAddr, Program, ProcInfo},
     value::Value,
-    vm::Vm,
+    vm::{Vm, Limits},
+    error::{RuntimeError, RuntimeErrorKind},
+    io::{IoHandler, StdIo, BufferIo, FnIo},
+    native::{Native, Natives, NativeFn, NativeId},
 };
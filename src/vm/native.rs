@@ -0,0 +1,129 @@
+use std::{fmt, rc::Rc};
+use crate::embed::{TypeDesc, IntoNative};
+use super::Value;
+
+// Native functions are given their arguments in order, and produce a value or an error message
+pub type NativeFn = Rc<dyn Fn(Vec<Value>) -> Result<Value, String>>;
+
+// The index of a native function within a table of natives
+pub type NativeId = u16;
+
+// A Rust function that may be called from Tao with `@name(...)`
+#[derive(Clone)]
+pub struct Native {
+    pub name: String,
+    pub args: Vec<TypeDesc>,
+    pub ret: TypeDesc,
+    func: NativeFn,
+}
+
+impl Native {
+    pub fn call(&self, args: Vec<Value>) -> Result<Value, String> {
+        (self.func)(args)
+    }
+
+    // Check that a value produced by the native has the shape of its return type. The contents of data types and
+    // generic parameters are not inspected.
+    pub fn check_return(&self, val: &Value) -> Result<(), String> {
+        if has_shape(val, &self.ret) {
+            Ok(())
+        } else {
+            Err(format!("Returned a value that is not of type {}", self.ret))
+        }
+    }
+}
+
+fn has_shape(val: &Value, ty: &TypeDesc) -> bool {
+    match (ty, val) {
+        (TypeDesc::Num, Value::Number(_))
+            | (TypeDesc::Bool, Value::Boolean(_))
+            | (TypeDesc::Char, Value::Char(_))
+            | (TypeDesc::Universe, Value::Universe(_))
+            | (TypeDesc::Func(_, _), Value::Func(_)) => true,
+        (TypeDesc::List(item), Value::List(items)) => items.iter().all(|val| has_shape(val, item)),
+        (TypeDesc::Tuple(items), Value::List(vals)) => items.len() == vals.len() && items
+            .iter()
+            .zip(vals.iter())
+            .all(|(ty, val)| has_shape(val, ty)),
+        (TypeDesc::Record(fields), Value::List(vals)) => fields.len() == vals.len(),
+        (TypeDesc::Data(_, _), _) | (TypeDesc::Param(_), _) => true,
+        _ => false,
+    }
+}
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "@{}", self.name)?;
+        self.args
+            .iter()
+            .try_for_each(|arg| write!(f, " {} ->", arg))?;
+        write!(f, " {}", self.ret)
+    }
+}
+
+// A table of native functions. Programs refer to natives by their index in the table, so a program must be executed
+// with the same table that it was compiled with.
+#[derive(Clone, Debug, Default)]
+pub struct Natives {
+    natives: Vec<Native>,
+}
+
+impl Natives {
+    // Register a native function with the given argument and return types. The built-in intrinsics (`out`, `in` and
+    // `panic`) take priority over natives of the same name, and a later registration replaces an earlier one.
+    pub fn register(
+        &mut self,
+        name: &str,
+        args: Vec<TypeDesc>,
+        ret: TypeDesc,
+        func: impl Fn(Vec<Value>) -> Result<Value, String> + 'static,
+    ) -> &mut Self {
+        self.insert(Native {
+            name: name.to_string(),
+            args,
+            ret,
+            func: Rc::new(func),
+        })
+    }
+
+    // Register a Rust function, deriving its Tao type from the types of its arguments and return value
+    pub fn register_fn<A>(&mut self, name: &str, func: impl IntoNative<A>) -> &mut Self {
+        let (args, ret, func) = func.into_native();
+        self.insert(Native {
+            name: name.to_string(),
+            args,
+            ret,
+            func,
+        })
+    }
+
+    fn insert(&mut self, native: Native) -> &mut Self {
+        match self.natives.iter().position(|other| other.name == native.name) {
+            Some(idx) => self.natives[idx] = native,
+            None => {
+                assert!(self.natives.len() <= NativeId::MAX as usize, "Too many native functions");
+                self.natives.push(native);
+            },
+        }
+        self
+    }
+
+    pub fn get(&self, id: NativeId) -> Option<&Native> {
+        self.natives.get(id as usize)
+    }
+
+    pub fn find(&self, name: &str) -> Option<(NativeId, &Native)> {
+        self.natives
+            .iter()
+            .enumerate()
+            .find(|(_, native)| native.name == name)
+            .map(|(id, native)| (id as NativeId, native))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=(NativeId, &Native)> {
+        self.natives
+            .iter()
+            .enumerate()
+            .map(|(id, native)| (id as NativeId, native))
+    }
+}
@@ -1,5 +1,8 @@
 use std::fmt;
-use crate::mir;
+use crate::{
+    mir,
+    src::Span,
+};
 use super::Value;
 
 pub type CodeAddr = u32;
@@ -31,6 +34,8 @@ pub enum Instr {
     MakeFunc(u16, u32),
     // Apply the argument one below the top of the stack to the function at the top of the stack
     ApplyFunc,
+    // Like `ApplyFunc`, but first pop N locals and reuse the current frame rather than returning to it
+    TailApplyFunc(u32),
     /// Push a list made from the last N items on the stack (reversed)
     MakeList(u32),
     // Index the list at the top of the stack
@@ -43,6 +48,8 @@ pub enum Instr {
     LenEqList(u32),
     // If the list has the given length or more, push true to the stack
     LenMoreEqList(u32),
+    // Push the length of the list at the top of the stack
+    LenList,
 
     NegNum,
     AddNum,
@@ -65,6 +72,13 @@ pub enum Instr {
 
     JoinList,
 
+    // Structurally compare the values at the top of the stack (used for lists, tuples, records and data types)
+    EqDeep,
+    MoreDeep,
+    LessDeep,
+    MoreEqDeep,
+    LessEqDeep,
+
     /// Load a constant from the program constants
     LoadConst(ConstAddr),
     /// Push a copy of the local with the given offset on to the stack
@@ -78,6 +92,9 @@ pub enum Instr {
     Jump(u32),
     /// Jump to the address if the last value in the stack is `false`
     JumpIfNot(u32),
+    /// Pop a number N and execute the Nth of the next `n` instructions (each a jump), or the last of them if N is too
+    /// large
+    JumpTable(u32),
     // Call the given address
     Call(CodeAddr),
     /// Pop the top value in the stack, consider this a return value
@@ -102,12 +119,14 @@ impl fmt::Debug for Instr {
             Instr::Char(c) => write!(f, "char '{}'", c),
             Instr::MakeFunc(n, addr) => write!(f, "func.make {} {:#X}", n, addr),
             Instr::ApplyFunc => write!(f, "func.apply"),
+            Instr::TailApplyFunc(n) => write!(f, "func.tail_apply {}", n),
             Instr::MakeList(n) => write!(f, "list.make {}", n),
             Instr::IndexList(x) => write!(f, "list.index {}", x),
             Instr::SetList(x) => write!(f, "list.set {}", x),
             Instr::TailList(x) => write!(f, "list.tail {}", x),
             Instr::LenEqList(n) => write!(f, "list.len_eq {}", n),
             Instr::LenMoreEqList(n) => write!(f, "list.len_more_eq {}", n),
+            Instr::LenList => write!(f, "list.len"),
             Instr::NegNum => write!(f, "num.neg"),
             Instr::AddNum => write!(f, "num.add"),
             Instr::SubNum => write!(f, "num.sub"),
@@ -125,12 +144,18 @@ impl fmt::Debug for Instr {
             Instr::OrBool => write!(f, "bool.or"),
             Instr::EqChar => write!(f, "char.eq"),
             Instr::JoinList => write!(f, "list.join"),
+            Instr::EqDeep => write!(f, "deep.eq"),
+            Instr::MoreDeep => write!(f, "deep.more"),
+            Instr::LessDeep => write!(f, "deep.less"),
+            Instr::MoreEqDeep => write!(f, "deep.more_eq"),
+            Instr::LessEqDeep => write!(f, "deep.less_eq"),
             Instr::LoadConst(addr) => write!(f, "const {:#X}", addr),
             Instr::LoadLocal(offset) => write!(f, "load_local {}", offset),
             Instr::PushLocal => write!(f, "push_local"),
             Instr::PopLocal => write!(f, "pop_local"),
             Instr::Jump(addr) => write!(f, "jump {:#X}", addr),
             Instr::JumpIfNot(addr) => write!(f, "jump_if_not {:#X}", addr),
+            Instr::JumpTable(n) => write!(f, "jump_table {}", n),
             Instr::Call(addr) => write!(f, "call {:#X}", addr),
             Instr::Return(n) => write!(f, "return {}", n),
             Instr::Intrinsic(i) => write!(f, "intrinsic {:?}", i),
@@ -143,9 +168,21 @@ fn size() {
     assert!(std::mem::size_of::<Instr>() <= 8);
 }
 
+// A procedure in the program, either a definition or a closure within one
+#[derive(Clone, Debug)]
+pub struct ProcInfo {
+    pub addr: CodeAddr,
+    pub def: mir::DefId,
+    pub is_closure: bool,
+}
+
 #[derive(Default)]
 pub struct Program {
     code: Vec<Instr>,
+    // The source span of each instruction
+    spans: Vec<Span>,
+    // Procedures, in order of address
+    procs: Vec<ProcInfo>,
     consts: Vec<Value>,
     debug: Vec<(CodeAddr, String)>,
     entry: CodeAddr,
@@ -161,13 +198,67 @@ impl Program {
         self.is_pure
     }
 
+    // Safety: the program must have been verified with `Program::verify` and `addr` must be reachable from the entry
     pub unsafe fn fetch_instr_unchecked(&self, addr: CodeAddr) -> Instr {
         debug_assert!((addr as usize) < self.code.len(), "addr = {}, len = {}", addr, self.code.len());
         *self.code.get_unchecked(addr as usize)
     }
 
-    pub fn fetch_const(&self, addr: ConstAddr) -> Value {
-        self.consts[addr as usize].clone()
+    pub fn fetch_const(&self, addr: ConstAddr) -> Option<Value> {
+        self.consts.get(addr as usize).cloned()
+    }
+
+    // Check that control can never leave the program: every jump, call and function address must be in bounds and
+    // execution must never fall off the end of the code. A verified program may be executed without checking the
+    // instruction pointer.
+    pub fn verify(&self) -> Result<(), String> {
+        self.verify_addr(self.entry)?;
+
+        for (addr, instr) in self.code.iter().enumerate() {
+            match instr {
+                Instr::MakeFunc(_, target) | Instr::Jump(target) | Instr::JumpIfNot(target) | Instr::Call(target) =>
+                    self.verify_addr(*target)?,
+                Instr::LoadConst(c) if *c as usize >= self.consts.len() =>
+                    return Err(format!("Constant {:#X} is out of bounds", c)),
+                // The table itself must be within the program, and every entry must be a jump
+                Instr::JumpTable(n) if *n == 0 || addr + *n as usize >= self.code.len() =>
+                    return Err(format!("Jump table at {:#X} is out of bounds", addr)),
+                Instr::JumpTable(n) if !self.code[addr + 1..=addr + *n as usize].iter().all(|entry| matches!(entry, Instr::Jump(_))) =>
+                    return Err(format!("Jump table at {:#X} has an entry that is not a jump", addr)),
+                _ => {},
+            }
+
+            // Only unconditional jumps, tail calls and returns may end the program, since anything else continues to the
+            // next instruction (calls return to it)
+            if addr + 1 == self.code.len() && !matches!(instr, Instr::Jump(_) | Instr::TailApplyFunc(_) | Instr::Return(_)) {
+                return Err(format!("Execution may continue beyond the last instruction, {:?}", instr));
+            }
+        }
+
+        self.consts
+            .iter()
+            .try_for_each(|val| self.verify_value(val))
+    }
+
+    // Check that any functions within a value point into the program, so that the value may be given to it
+    pub fn verify_value(&self, val: &Value) -> Result<(), String> {
+        match val {
+            Value::Func(func) => {
+                self.verify_addr(func.0)?;
+                func.1.iter().try_for_each(|val| self.verify_value(val))
+            },
+            Value::List(items) => items.iter().try_for_each(|val| self.verify_value(val)),
+            _ => Ok(()),
+        }
+    }
+
+    // Check that an address points into the code of the program
+    pub fn verify_addr(&self, addr: CodeAddr) -> Result<(), String> {
+        if (addr as usize) < self.code.len() {
+            Ok(())
+        } else {
+            Err(format!("Address {:#X} is out of bounds", addr))
+        }
     }
 
     pub fn emit_const(&mut self, c: Value) -> ConstAddr {
@@ -176,10 +267,34 @@ impl Program {
     }
 
     pub fn emit_instr(&mut self, instr: Instr) -> CodeAddr {
+        self.emit_instr_at(instr, Span::none())
+    }
+
+    pub fn emit_instr_at(&mut self, instr: Instr, span: Span) -> CodeAddr {
         self.code.push(instr);
+        self.spans.push(span);
         (self.code.len() - 1) as CodeAddr
     }
 
+    // Procedures must be emitted in order of address
+    pub fn emit_proc(&mut self, addr: CodeAddr, def: mir::DefId, is_closure: bool) {
+        debug_assert!(self.procs.last().map_or(true, |proc| proc.addr <= addr));
+        self.procs.push(ProcInfo { addr, def, is_closure });
+    }
+
+    pub fn span_at(&self, addr: CodeAddr) -> Span {
+        self.spans.get(addr as usize).copied().unwrap_or(Span::none())
+    }
+
+    // Find the procedure that contains the given address
+    pub fn proc_at(&self, addr: CodeAddr) -> Option<&ProcInfo> {
+        match self.procs.binary_search_by_key(&addr, |proc| proc.addr) {
+            Ok(idx) => Some(&self.procs[idx]),
+            Err(0) => None,
+            Err(idx) => Some(&self.procs[idx - 1]),
+        }
+    }
+
     pub fn patch_instr(&mut self, addr: CodeAddr, instr: Instr) {
         debug_assert!(matches!(self.code[addr as usize], Instr::Nop));
         self.code[addr as usize] = instr;
@@ -215,7 +330,7 @@ impl fmt::Debug for Program {
             } else {
                 ""
             };
-            writeln!(f, "{:>#5X} | {:?} {}", addr, instr, debug)?;
+            writeln!(f, "{:>#5X} | {:?} {} {:?}", addr, instr, debug, self.span_at(addr as CodeAddr))?;
         }
         writeln!(f, "-- Data --")?;
         for (addr, val) in self.consts.iter().enumerate() {
@@ -229,3 +344,18 @@ impl fmt::Debug for Program {
         Ok(())
     }
 }
+
+#[test]
+fn proc_at() {
+    use internment::LocalIntern;
+
+    let def = |name: &str| -> mir::DefId { LocalIntern::new((LocalIntern::new(name.to_string()), Vec::new())) };
+    let mut prog = Program::default();
+    prog.emit_proc(0, def("a"), false);
+    prog.emit_proc(3, def("b"), true);
+    (0..5).for_each(|_| { prog.emit_instr(Instr::Nop); });
+
+    assert_eq!(prog.proc_at(2).unwrap().def.0.as_str(), "a");
+    assert_eq!(prog.proc_at(3).unwrap().def.0.as_str(), "b");
+    assert!(prog.proc_at(4).unwrap().is_closure);
+}
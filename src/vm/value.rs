@@ -1,9 +1,8 @@
 use std::{
     rc::Rc,
     fmt,
+    cmp::Ordering,
 };
-#[cfg(not(debug_assertions))]
-use std::hint::unreachable_unchecked;
 use im_rc::Vector;
 use super::CodeAddr;
 
@@ -23,83 +22,88 @@ impl Value {
         Value::List(Rc::new(iter.collect()))
     }
 
-    pub fn into_number_unchecked(self) -> f64 {
-        match self {
-            Value::Number(x) => x,
-            #[cfg(debug_assertions)]
-            this => unreachable!("Expected number, found {:?}", this),
-            #[cfg(not(debug_assertions))]
-            _ => unsafe { unreachable_unchecked() },
+    // Make a value of a data type with more than one variant. Data types with a single variant are represented by the
+    // inner value alone.
+    pub fn make_variant(idx: usize, inner: Value) -> Self {
+        Value::make_list(vec![Value::Number(idx as f64), inner].into_iter())
+    }
+
+    // Split a value of a data type with more than one variant into its variant index and inner value
+    pub fn into_variant(self) -> Option<(usize, Value)> {
+        let items = self.into_list()?;
+        match (items.get(0).cloned()?.into_number()?, items.get(1)) {
+            (idx, Some(inner)) if items.len() == 2 => Some((idx as usize, inner.clone())),
+            _ => None,
         }
     }
 
-    pub fn into_boolean_unchecked(self) -> bool {
+    pub fn into_number(self) -> Option<f64> {
         match self {
-            Value::Boolean(x) => x,
-            #[cfg(debug_assertions)]
-            this => unreachable!("Expected bool, found {:?}", this),
-            #[cfg(not(debug_assertions))]
-            _ => unsafe { unreachable_unchecked() },
+            Value::Number(x) => Some(x),
+            _ => None,
         }
     }
 
-    pub fn into_char_unchecked(self) -> char {
+    pub fn into_boolean(self) -> Option<bool> {
         match self {
-            Value::Char(x) => x,
-            #[cfg(debug_assertions)]
-            this => unreachable!("Expected char, found {:?}", this),
-            #[cfg(not(debug_assertions))]
-            _ => unsafe { unreachable_unchecked() },
+            Value::Boolean(x) => Some(x),
+            _ => None,
         }
     }
 
-    pub fn into_func_unchecked(self) -> Rc<(CodeAddr, Vec<Value>)> {
+    pub fn into_char(self) -> Option<char> {
         match self {
-            Value::Func(addr) => addr,
-            #[cfg(debug_assertions)]
-            this => unreachable!("Expected func, found {:?}", this),
-            #[cfg(not(debug_assertions))]
-            _ => unsafe { unreachable_unchecked() },
+            Value::Char(x) => Some(x),
+            _ => None,
         }
     }
 
-    pub fn into_universe_unchecked(self) -> u64 {
+    pub fn into_func(self) -> Option<Rc<(CodeAddr, Vec<Value>)>> {
         match self {
-            Value::Universe(val) => val,
-            #[cfg(debug_assertions)]
-            this => unreachable!("Expected universe, found {:?}", this),
-            #[cfg(not(debug_assertions))]
-            _ => unsafe { unreachable_unchecked() },
+            Value::Func(func) => Some(func),
+            _ => None,
         }
     }
 
-    pub fn into_list_unchecked(self) -> Rc<Vector<Value>> {
+    pub fn into_universe(self) -> Option<u64> {
         match self {
-            Value::List(list) => list,
-            #[cfg(debug_assertions)]
-            this => unreachable!("Expected list, found {:?}", this),
-            #[cfg(not(debug_assertions))]
-            _ => unsafe { unreachable_unchecked() },
+            Value::Universe(val) => Some(val),
+            _ => None,
         }
     }
 
-    pub fn index(self, x: usize) -> Self {
+    pub fn into_list(self) -> Option<Rc<Vector<Value>>> {
         match self {
-            Value::List(list) => list[x].clone(),
-            #[cfg(debug_assertions)]
-            this => unreachable!("Expected list, found {:?}", this),
-            #[cfg(not(debug_assertions))]
-            _ => unsafe { unreachable_unchecked() },
+            Value::List(list) => Some(list),
+            _ => None,
         }
     }
 
-    pub fn as_list_unchecked_mut(&mut self) -> &mut Vector<Value> {
+    pub fn as_list_mut(&mut self) -> Option<&mut Vector<Value>> {
         match self {
-            Value::List(list) => Rc::make_mut(list),
-            #[cfg(debug_assertions)]
-            this => unreachable!("Expected list, found {:?}", this),
-            #[cfg(not(debug_assertions))]
-            _ => unsafe { unreachable_unchecked() },
+            Value::List(list) => Some(Rc::make_mut(list)),
+            _ => None,
+        }
+    }
+
+    // Structurally compare two values of the same type. Lists (and so tuples, records and data types) are compared
+    // lexicographically. `None` is produced if the values are unordered, such as when comparing against NaN.
+    pub fn cmp_deep(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Number(x), Value::Number(y)) => x.partial_cmp(y),
+            (Value::Boolean(x), Value::Boolean(y)) => Some(x.cmp(y)),
+            (Value::Char(x), Value::Char(y)) => Some(x.cmp(y)),
+            (Value::List(xs), Value::List(ys)) => {
+                for (x, y) in xs.iter().zip(ys.iter()) {
+                    match x.cmp_deep(y)? {
+                        Ordering::Equal => {},
+                        ord => return Some(ord),
+                    }
+                }
+                Some(xs.len().cmp(&ys.len()))
+            },
+            // Values of different types are never produced by well-typed programs, so consider them unordered
+            _ => None,
         }
     }
 }
@@ -113,8 +117,7 @@ impl fmt::Display for Value {
             Value::List(xs) => match xs.get(0) {
                 Some(Value::Char(_)) => write!(f, "\"{}\"", xs
                     .iter()
-                    .cloned()
-                    .map(|v| v.into_char_unchecked())
+                    .filter_map(|v| v.clone().into_char())
                     .collect::<String>()),
                 _ => write!(f, "[{}]", xs
                     .iter()
@@ -132,3 +135,12 @@ impl fmt::Display for Value {
 fn size() {
     assert!(std::mem::size_of::<Value>() <= 16);
 }
+
+#[test]
+fn cmp_deep() {
+    let list = |xs: &[f64]| Value::make_list(xs.iter().map(|x| Value::Number(*x)));
+    assert_eq!(list(&[1.0, 2.0]).cmp_deep(&list(&[1.0, 2.0])), Some(Ordering::Equal));
+    assert_eq!(list(&[1.0, 2.0]).cmp_deep(&list(&[1.0, 3.0])), Some(Ordering::Less));
+    assert_eq!(list(&[1.0, 2.0]).cmp_deep(&list(&[1.0])), Some(Ordering::Greater));
+    assert_eq!(list(&[std::f64::NAN]).cmp_deep(&list(&[std::f64::NAN])), None);
+}
@@ -1,230 +1,669 @@
 use std::{
     rc::Rc,
-    io::{self, Write},
+    cmp::Ordering,
 };
-use super::{Instr, Program, Value};
+use im_rc::Vector;
+use super::{Instr, Program, Value, CodeAddr, RuntimeError, RuntimeErrorKind, IoHandler, Natives};
 use crate::mir;
-use utf8_chars::BufReadCharsExt;
+
+// Limits on the resources that a single execution may use. `None` means that a resource is unlimited.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Limits {
+    // The number of instructions that may be executed before execution is suspended
+    pub fuel: Option<u64>,
+    pub max_call_depth: Option<usize>,
+    pub max_locals: Option<usize>,
+    // The total number of list elements that may be allocated
+    pub max_list_elements: Option<u64>,
+}
 
 #[derive(Default)]
-pub struct Vm;
+pub struct Vm {
+    // The deepest that the call stack grew during the last execution
+    max_call_depth: usize,
+    // The native functions that programs may call. These must be the natives that the program was compiled with.
+    natives: Natives,
+    limits: Limits,
+    fuel: Option<u64>,
+    // An execution that ran out of fuel, and may be resumed
+    suspended: Option<State>,
+}
 
 impl Vm {
-    pub fn execute(mut self, prog: &Program) -> Option<Value> {
-        let mut universe = 0;
-        let mut expr_stack = if prog.is_pure() {
-            Vec::new()
-        } else {
-            vec![Value::Universe(universe)]
+    pub fn with_natives(natives: Natives) -> Self {
+        Self {
+            natives,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_limits(self, limits: Limits) -> Self {
+        Self {
+            limits,
+            ..self
+        }
+    }
+
+    pub fn max_call_depth(&self) -> usize {
+        self.max_call_depth
+    }
+
+    // The fuel left over from the last execution, if fuel is limited
+    pub fn remaining_fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    // Whether the last execution ran out of fuel and may be resumed
+    pub fn is_suspended(&self) -> bool {
+        self.suspended.is_some()
+    }
+
+    // Execute a program, using `io` to perform any input and output
+    pub fn execute(&mut self, prog: &Program, io: &mut dyn IoHandler) -> Result<Option<Value>, RuntimeError> {
+        prog.verify().map_err(|msg| RuntimeError::new(RuntimeErrorKind::InvalidProgram(msg), prog.entry()))?;
+
+        let state = State {
+            universe: 0,
+            expr_stack: if prog.is_pure() {
+                Vec::new()
+            } else {
+                vec![Value::Universe(0)]
+            },
+            call_stack: Vec::new(),
+            local_stack: Vec::new(),
+            ip: prog.entry(),
+            allocated: 0,
+            check_universe: !prog.is_pure(),
+        };
+
+        self.max_call_depth = 0;
+        self.fuel = self.limits.fuel;
+        self.suspended = None;
+        self.run(prog, state, io)
+    }
+
+    // Apply a function value produced by `prog` to an argument, returning the result of the application
+    pub fn call(&mut self, prog: &Program, func: Value, arg: Value, io: &mut dyn IoHandler) -> Result<Value, RuntimeError> {
+        let invalid = |msg| RuntimeError::new(RuntimeErrorKind::InvalidProgram(msg), prog.entry());
+        prog.verify().map_err(invalid)?;
+        // The function and argument may have come from outside the program, so they must be checked too
+        prog.verify_value(&func).and_then(|_| prog.verify_value(&arg)).map_err(invalid)?;
+
+        let mut func = func
+            .into_func()
+            .ok_or_else(|| RuntimeError::new(RuntimeErrorKind::TypeMismatch("function"), prog.entry()))?;
+        let state = State {
+            universe: 0,
+            expr_stack: vec![arg],
+            call_stack: Vec::new(),
+            // Returning with an empty call stack ends execution, as with `ApplyFunc`
+            local_stack: Rc::make_mut(&mut func).1.drain(..).collect(),
+            ip: func.0,
+            allocated: 0,
+            check_universe: false,
         };
-        let mut call_stack = Vec::new();
-        let mut local_stack = Vec::<Value>::new();
 
-        let mut ip = prog.entry();
+        self.max_call_depth = 0;
+        self.fuel = self.limits.fuel;
+        self.suspended = None;
+        // Only a universe check can cause no value to be produced
+        self.run(prog, state, io).map(|val| val.unwrap())
+    }
 
+    // Continue an execution of `prog` that ran out of fuel, giving it `fuel` more instructions. The result is that of
+    // the original call to `execute` (or `call`, in which case it is always `Some`).
+    pub fn resume(&mut self, prog: &Program, fuel: u64, io: &mut dyn IoHandler) -> Result<Option<Value>, RuntimeError> {
+        let invalid = |msg| RuntimeError::new(RuntimeErrorKind::InvalidProgram(msg), prog.entry());
+        let state = self.suspended
+            .take()
+            .ok_or_else(|| invalid(format!("There is no suspended execution to resume")))?;
+
+        // The suspended state may belong to a different program, so everything that refers to code must be checked
+        prog.verify().map_err(invalid)?;
+        std::iter::once(state.ip)
+            .chain(state.call_stack.iter().copied())
+            .try_for_each(|addr| prog.verify_addr(addr))
+            .and_then(|_| state.expr_stack
+                .iter()
+                .chain(state.local_stack.iter())
+                .try_for_each(|val| prog.verify_value(val)))
+            .map_err(invalid)?;
+
+        self.fuel = Some(fuel);
+        self.run(prog, state, io)
+    }
+
+    fn run(&mut self, prog: &Program, mut state: State, io: &mut dyn IoHandler) -> Result<Option<Value>, RuntimeError> {
         let val = loop {
-            let instr = unsafe { prog.fetch_instr_unchecked(ip) };
-            //println!("{:>#5X} => {:?}", ip, instr);
+            let addr = state.ip;
+            self.max_call_depth = self.max_call_depth.max(state.call_stack.len());
 
-            ip += 1;
+            if let Some(fuel) = &mut self.fuel {
+                if *fuel == 0 {
+                    let err = state.error(RuntimeErrorKind::OutOfFuel, addr);
+                    self.suspended = Some(state);
+                    return Err(err);
+                }
+                *fuel -= 1;
+            }
 
-            match instr {
-                Instr::Nop => {},
+            let res = state.step(prog, &self.natives, io).and_then(|val| {
+                let limits = &self.limits;
+                if limits.max_call_depth.map_or(false, |max| state.call_stack.len() > max) {
+                    Err(RuntimeErrorKind::CallDepthExceeded)
+                } else if limits.max_locals.map_or(false, |max| state.local_stack.len() > max) {
+                    Err(RuntimeErrorKind::LocalsExceeded)
+                } else if limits.max_list_elements.map_or(false, |max| state.allocated > max) {
+                    Err(RuntimeErrorKind::MemoryExceeded)
+                } else {
+                    Ok(val)
+                }
+            });
+            match res {
+                Ok(None) => {},
+                Ok(Some(val)) => break val,
+                Err(kind) => return Err(state.error(kind, addr)),
+            }
+        };
 
-                Instr::Dup => expr_stack.push(expr_stack.last().unwrap().clone()),
-                Instr::Pop => { expr_stack.pop().unwrap(); },
+        if state.check_universe {
+            // Impure programs produce a `((), Universe)` pair, and the universe must be the latest one
+            let universe = val
+                .into_list()
+                .and_then(|xs| xs.get(1).cloned())
+                .and_then(Value::into_universe);
+            if universe != Some(state.universe) {
+                return Err(RuntimeError::new(RuntimeErrorKind::ForkedUniverse, state.ip));
+            }
+            Ok(None)
+        } else {
+            Ok(Some(val))
+        }
+    }
+}
 
-                Instr::Integer(x) => expr_stack.push(Value::Number(x as f64)),
-                Instr::Float(x) => expr_stack.push(Value::Number(x as f64)),
-                Instr::True => expr_stack.push(Value::Boolean(true)),
-                Instr::False => expr_stack.push(Value::Boolean(false)),
-                Instr::Char(c) => expr_stack.push(Value::Char(c)),
+struct State {
+    universe: u64,
+    expr_stack: Vec<Value>,
+    call_stack: Vec<CodeAddr>,
+    local_stack: Vec<Value>,
+    ip: CodeAddr,
+    // The number of list elements allocated so far
+    allocated: u64,
+    // Whether the result is a `((), Universe)` pair whose universe must be checked
+    check_universe: bool,
+}
 
-                Instr::MakeFunc(n, addr) => {
-                    let mut env = (0..n).map(|_| expr_stack.pop().unwrap()).collect();
-                    expr_stack.push(Value::Func(Rc::new((addr, env))));
-                },
-                Instr::ApplyFunc => {
-                    let mut func = expr_stack.pop().unwrap().into_func_unchecked();
-                    Rc::make_mut(&mut func).1
-                        .drain(..)
-                        .for_each(|env| local_stack.push(env));
-                    call_stack.push(ip);
-                    ip = func.0;
-                },
-                Instr::MakeList(n) => {
-                    let list = Value::make_list((0..n).map(|_| expr_stack.pop().unwrap()));
-                    expr_stack.push(list);
-                },
-                Instr::IndexList(x) => {
-                    let item = expr_stack.pop().unwrap().index(x as usize);
-                    expr_stack.push(item);
-                },
-                Instr::SetList(x) => {
-                    let item = expr_stack.pop().unwrap();
-                    expr_stack.last_mut().unwrap().as_list_unchecked_mut()[x as usize] = item;
-                },
-                Instr::TailList(x) => {
-                    let list = std::mem::take(Rc::make_mut(&mut expr_stack.pop().unwrap().into_list_unchecked()));
-                    expr_stack.push(Value::List(Rc::new(list.skip(x as usize))));
-                },
-                Instr::LenEqList(n) => {
-                    let len = expr_stack.pop().unwrap().into_list_unchecked().len();
-                    expr_stack.push(Value::Boolean(len == n as usize));
-                },
-                Instr::LenMoreEqList(n) => {
-                    let len = expr_stack.pop().unwrap().into_list_unchecked().len();
-                    expr_stack.push(Value::Boolean(len >= n as usize));
-                },
+impl State {
+    // Produce an error at `addr`, along with the calls that led to it. Tail calls reuse the caller's frame and push no
+    // return address, so functions that were left through a tail call do not appear in the trace.
+    fn error(&self, kind: RuntimeErrorKind, addr: CodeAddr) -> RuntimeError {
+        RuntimeError::new(kind, addr).with_trace(self
+            .call_stack
+            .iter()
+            .rev()
+            // Return addresses point just after their call
+            .map(|ret_addr| ret_addr - 1)
+            .collect())
+    }
 
-                Instr::NegNum => {
-                    let x = expr_stack.pop().unwrap().into_number_unchecked();
-                    expr_stack.push(Value::Number(-x));
-                },
-                Instr::AddNum => {
-                    let x = expr_stack.pop().unwrap().into_number_unchecked();
-                    let y = expr_stack.pop().unwrap().into_number_unchecked();
-                    expr_stack.push(Value::Number(x + y));
-                },
-                Instr::SubNum => {
-                    let x = expr_stack.pop().unwrap().into_number_unchecked();
-                    let y = expr_stack.pop().unwrap().into_number_unchecked();
-                    expr_stack.push(Value::Number(x - y));
-                },
-                Instr::MulNum => {
-                    let x = expr_stack.pop().unwrap().into_number_unchecked();
-                    let y = expr_stack.pop().unwrap().into_number_unchecked();
-                    expr_stack.push(Value::Number(x * y));
-                },
-                Instr::DivNum => {
-                    let x = expr_stack.pop().unwrap().into_number_unchecked();
-                    let y = expr_stack.pop().unwrap().into_number_unchecked();
-                    expr_stack.push(Value::Number(x / y));
-                },
-                Instr::RemNum => {
-                    let x = expr_stack.pop().unwrap().into_number_unchecked();
-                    let y = expr_stack.pop().unwrap().into_number_unchecked();
-                    expr_stack.push(Value::Number(x % y));
-                },
-                Instr::EqNum => {
-                    let x = expr_stack.pop().unwrap().into_number_unchecked();
-                    let y = expr_stack.pop().unwrap().into_number_unchecked();
-                    expr_stack.push(Value::Boolean(x == y));
-                },
-                Instr::MoreNum => {
-                    let x = expr_stack.pop().unwrap().into_number_unchecked();
-                    let y = expr_stack.pop().unwrap().into_number_unchecked();
-                    expr_stack.push(Value::Boolean(x > y));
-                },
-                Instr::LessNum => {
-                    let x = expr_stack.pop().unwrap().into_number_unchecked();
-                    let y = expr_stack.pop().unwrap().into_number_unchecked();
-                    expr_stack.push(Value::Boolean(x < y));
-                },
-                Instr::MoreEqNum => {
-                    let x = expr_stack.pop().unwrap().into_number_unchecked();
-                    let y = expr_stack.pop().unwrap().into_number_unchecked();
-                    expr_stack.push(Value::Boolean(x >= y));
-                },
-                Instr::LessEqNum => {
-                    let x = expr_stack.pop().unwrap().into_number_unchecked();
-                    let y = expr_stack.pop().unwrap().into_number_unchecked();
-                    expr_stack.push(Value::Boolean(x <= y));
-                },
+    fn pop(&mut self) -> Result<Value, RuntimeErrorKind> {
+        self.expr_stack.pop().ok_or(RuntimeErrorKind::StackUnderflow)
+    }
 
-                Instr::NotBool => {
-                    let x = expr_stack.pop().unwrap().into_boolean_unchecked();
-                    expr_stack.push(Value::Boolean(!x));
-                },
-                Instr::EqBool => {
-                    let x = expr_stack.pop().unwrap().into_boolean_unchecked();
-                    let y = expr_stack.pop().unwrap().into_boolean_unchecked();
-                    expr_stack.push(Value::Boolean(x == y));
-                },
-                Instr::AndBool => {
-                    let x = expr_stack.pop().unwrap().into_boolean_unchecked();
-                    let y = expr_stack.pop().unwrap().into_boolean_unchecked();
-                    expr_stack.push(Value::Boolean(x && y));
-                },
-                Instr::OrBool => {
-                    let x = expr_stack.pop().unwrap().into_boolean_unchecked();
-                    let y = expr_stack.pop().unwrap().into_boolean_unchecked();
-                    expr_stack.push(Value::Boolean(x || y));
-                },
+    fn pop_number(&mut self) -> Result<f64, RuntimeErrorKind> {
+        self.pop()?.into_number().ok_or(RuntimeErrorKind::TypeMismatch("number"))
+    }
 
-                Instr::EqChar => {
-                    let x = expr_stack.pop().unwrap().into_char_unchecked();
-                    let y = expr_stack.pop().unwrap().into_char_unchecked();
-                    expr_stack.push(Value::Boolean(x == y));
-                },
+    fn pop_boolean(&mut self) -> Result<bool, RuntimeErrorKind> {
+        self.pop()?.into_boolean().ok_or(RuntimeErrorKind::TypeMismatch("bool"))
+    }
 
-                Instr::JoinList => {
-                    let mut x = (*expr_stack.pop().unwrap().into_list_unchecked()).clone();
-                    x.append((*expr_stack.pop().unwrap().into_list_unchecked()).clone());
-                    expr_stack.push(Value::List(Rc::new(x)));
-                },
+    fn pop_char(&mut self) -> Result<char, RuntimeErrorKind> {
+        self.pop()?.into_char().ok_or(RuntimeErrorKind::TypeMismatch("char"))
+    }
+
+    fn pop_list(&mut self) -> Result<Rc<Vector<Value>>, RuntimeErrorKind> {
+        self.pop()?.into_list().ok_or(RuntimeErrorKind::TypeMismatch("list"))
+    }
+
+    // Consume the current universe. Universes are linear, so only the latest universe may be used.
+    fn pop_universe(&mut self) -> Result<(), RuntimeErrorKind> {
+        let universe = self.pop()?.into_universe().ok_or(RuntimeErrorKind::TypeMismatch("universe"))?;
+        if universe == self.universe {
+            Ok(())
+        } else {
+            Err(RuntimeErrorKind::ForkedUniverse)
+        }
+    }
+
+    fn num_op(&mut self, f: impl FnOnce(f64, f64) -> Value) -> Result<(), RuntimeErrorKind> {
+        let x = self.pop_number()?;
+        let y = self.pop_number()?;
+        self.expr_stack.push(f(x, y));
+        Ok(())
+    }
 
-                Instr::LoadConst(addr) => expr_stack.push(prog.fetch_const(addr)),
-                Instr::LoadLocal(offset) => expr_stack.push(local_stack.get(local_stack.len() - 1 - offset as usize).unwrap().clone()),
-                Instr::PushLocal => local_stack.push(expr_stack.pop().unwrap()),
-                Instr::PopLocal => { local_stack.pop().unwrap(); },
+    fn bool_op(&mut self, f: impl FnOnce(bool, bool) -> bool) -> Result<(), RuntimeErrorKind> {
+        let x = self.pop_boolean()?;
+        let y = self.pop_boolean()?;
+        self.expr_stack.push(Value::Boolean(f(x, y)));
+        Ok(())
+    }
+
+    fn deep_op(&mut self, f: impl FnOnce(Option<Ordering>) -> bool) -> Result<(), RuntimeErrorKind> {
+        let x = self.pop()?;
+        let y = self.pop()?;
+        self.expr_stack.push(Value::Boolean(f(x.cmp_deep(&y))));
+        Ok(())
+    }
+
+    // Execute a single instruction, producing the final value if the program has finished
+    fn step(&mut self, prog: &Program, natives: &Natives, io: &mut dyn IoHandler) -> Result<Option<Value>, RuntimeErrorKind> {
+        // Safety: the program has been verified, so the instruction pointer is always in bounds
+        let instr = unsafe { prog.fetch_instr_unchecked(self.ip) };
+        //println!("{:>#5X} => {:?}", self.ip, instr);
+
+        self.ip += 1;
+
+        match instr {
+            Instr::Nop => {},
+
+            Instr::Dup => {
+                let x = self.expr_stack.last().ok_or(RuntimeErrorKind::StackUnderflow)?.clone();
+                self.expr_stack.push(x);
+            },
+            Instr::Pop => { self.pop()?; },
+
+            Instr::Integer(x) => self.expr_stack.push(Value::Number(x as f64)),
+            Instr::Float(x) => self.expr_stack.push(Value::Number(x as f64)),
+            Instr::True => self.expr_stack.push(Value::Boolean(true)),
+            Instr::False => self.expr_stack.push(Value::Boolean(false)),
+            Instr::Char(c) => self.expr_stack.push(Value::Char(c)),
 
-                Instr::Jump(addr) => ip = addr,
-                Instr::JumpIfNot(addr) => {
-                    if matches!(expr_stack.pop().unwrap(), Value::Boolean(false)) {
-                        ip = addr;
+            Instr::MakeFunc(n, addr) => {
+                let env = (0..n).map(|_| self.pop()).collect::<Result<Vec<_>, _>>()?;
+                self.allocated += n as u64;
+                self.expr_stack.push(Value::Func(Rc::new((addr, env))));
+            },
+            Instr::ApplyFunc => {
+                let mut func = self.pop()?.into_func().ok_or(RuntimeErrorKind::TypeMismatch("function"))?;
+                let local_stack = &mut self.local_stack;
+                Rc::make_mut(&mut func).1
+                    .drain(..)
+                    .for_each(|env| local_stack.push(env));
+                self.call_stack.push(self.ip);
+                self.ip = func.0;
+            },
+            Instr::TailApplyFunc(n) => {
+                let mut func = self.pop()?.into_func().ok_or(RuntimeErrorKind::TypeMismatch("function"))?;
+                let len = self.local_stack
+                    .len()
+                    .checked_sub(n as usize)
+                    .ok_or(RuntimeErrorKind::LocalUnderflow)?;
+                self.local_stack.truncate(len);
+                let local_stack = &mut self.local_stack;
+                Rc::make_mut(&mut func).1
+                    .drain(..)
+                    .for_each(|env| local_stack.push(env));
+                self.ip = func.0;
+            },
+            Instr::MakeList(n) => {
+                let items = (0..n).map(|_| self.pop()).collect::<Result<Vec<_>, _>>()?;
+                self.allocated += n as u64;
+                self.expr_stack.push(Value::make_list(items.into_iter()));
+            },
+            Instr::IndexList(x) => {
+                let list = self.pop_list()?;
+                let item = list
+                    .get(x as usize)
+                    .ok_or(RuntimeErrorKind::BadIndex { index: x as usize, len: list.len() })?
+                    .clone();
+                self.expr_stack.push(item);
+            },
+            Instr::SetList(x) => {
+                let item = self.pop()?;
+                let top = self.expr_stack
+                    .last_mut()
+                    .ok_or(RuntimeErrorKind::StackUnderflow)?;
+                // A shared list gets copied before it is modified
+                if let Value::List(list) = top {
+                    if Rc::strong_count(list) > 1 {
+                        self.allocated += list.len() as u64;
                     }
+                }
+                let list = top
+                    .as_list_mut()
+                    .ok_or(RuntimeErrorKind::TypeMismatch("list"))?;
+                let len = list.len();
+                *list
+                    .get_mut(x as usize)
+                    .ok_or(RuntimeErrorKind::BadIndex { index: x as usize, len })? = item;
+            },
+            Instr::TailList(x) => {
+                let list = self.pop_list()?;
+                // A shared list gets copied before it is modified
+                let list = Rc::try_unwrap(list).unwrap_or_else(|list| {
+                    self.allocated += list.len() as u64;
+                    (*list).clone()
+                });
+                if x as usize > list.len() {
+                    return Err(RuntimeErrorKind::BadIndex { index: x as usize, len: list.len() });
+                }
+                self.expr_stack.push(Value::List(Rc::new(list.skip(x as usize))));
+            },
+            Instr::LenEqList(n) => {
+                let len = self.pop_list()?.len();
+                self.expr_stack.push(Value::Boolean(len == n as usize));
+            },
+            Instr::LenMoreEqList(n) => {
+                let len = self.pop_list()?.len();
+                self.expr_stack.push(Value::Boolean(len >= n as usize));
+            },
+            Instr::LenList => {
+                let len = self.pop_list()?.len();
+                self.expr_stack.push(Value::Number(len as f64));
+            },
+
+            Instr::NegNum => {
+                let x = self.pop_number()?;
+                self.expr_stack.push(Value::Number(-x));
+            },
+            Instr::AddNum => self.num_op(|x, y| Value::Number(x + y))?,
+            Instr::SubNum => self.num_op(|x, y| Value::Number(x - y))?,
+            Instr::MulNum => self.num_op(|x, y| Value::Number(x * y))?,
+            Instr::DivNum | Instr::RemNum => {
+                let x = self.pop_number()?;
+                let y = self.pop_number()?;
+                if y == 0.0 {
+                    return Err(RuntimeErrorKind::DivideByZero);
+                }
+                self.expr_stack.push(Value::Number(if matches!(instr, Instr::DivNum) { x / y } else { x % y }));
+            },
+            Instr::EqNum => self.num_op(|x, y| Value::Boolean(x == y))?,
+            Instr::MoreNum => self.num_op(|x, y| Value::Boolean(x > y))?,
+            Instr::LessNum => self.num_op(|x, y| Value::Boolean(x < y))?,
+            Instr::MoreEqNum => self.num_op(|x, y| Value::Boolean(x >= y))?,
+            Instr::LessEqNum => self.num_op(|x, y| Value::Boolean(x <= y))?,
+
+            Instr::NotBool => {
+                let x = self.pop_boolean()?;
+                self.expr_stack.push(Value::Boolean(!x));
+            },
+            Instr::EqBool => self.bool_op(|x, y| x == y)?,
+            Instr::AndBool => self.bool_op(|x, y| x && y)?,
+            Instr::OrBool => self.bool_op(|x, y| x || y)?,
+
+            Instr::EqChar => {
+                let x = self.pop_char()?;
+                let y = self.pop_char()?;
+                self.expr_stack.push(Value::Boolean(x == y));
+            },
+
+            Instr::JoinList => {
+                let mut x = (*self.pop_list()?).clone();
+                x.append((*self.pop_list()?).clone());
+                self.allocated += x.len() as u64;
+                self.expr_stack.push(Value::List(Rc::new(x)));
+            },
+
+            Instr::EqDeep => self.deep_op(|ord| ord == Some(Ordering::Equal))?,
+            Instr::MoreDeep => self.deep_op(|ord| ord == Some(Ordering::Greater))?,
+            Instr::LessDeep => self.deep_op(|ord| ord == Some(Ordering::Less))?,
+            Instr::MoreEqDeep => self.deep_op(|ord| matches!(ord, Some(Ordering::Greater) | Some(Ordering::Equal)))?,
+            Instr::LessEqDeep => self.deep_op(|ord| matches!(ord, Some(Ordering::Less) | Some(Ordering::Equal)))?,
+
+            Instr::LoadConst(addr) => {
+                let val = prog
+                    .fetch_const(addr)
+                    .ok_or_else(|| RuntimeErrorKind::InvalidProgram(format!("Constant {:#X} is out of bounds", addr)))?;
+                self.expr_stack.push(val);
+            },
+            Instr::LoadLocal(offset) => {
+                let local = self.local_stack
+                    .len()
+                    .checked_sub(1 + offset as usize)
+                    .map(|idx| self.local_stack[idx].clone())
+                    .ok_or(RuntimeErrorKind::LocalUnderflow)?;
+                self.expr_stack.push(local);
+            },
+            Instr::PushLocal => {
+                let x = self.pop()?;
+                self.local_stack.push(x);
+            },
+            Instr::PopLocal => { self.local_stack.pop().ok_or(RuntimeErrorKind::LocalUnderflow)?; },
+
+            Instr::Jump(addr) => self.ip = addr,
+            Instr::JumpIfNot(addr) => {
+                if !self.pop_boolean()? {
+                    self.ip = addr;
+                }
+            },
+            Instr::JumpTable(n) => {
+                // Verification ensures that the table is never empty and only contains jumps
+                let idx = self.pop_number()? as u32;
+                self.ip += idx.min(n - 1);
+            },
+            Instr::Call(addr) => {
+                self.call_stack.push(self.ip);
+                self.ip = addr;
+            },
+            Instr::Return(n) => {
+                let val = self.pop()?;
+                let len = self.expr_stack
+                    .len()
+                    .checked_sub(n as usize)
+                    .ok_or(RuntimeErrorKind::StackUnderflow)?;
+                self.expr_stack.truncate(len);
+                if let Some(ret_addr) = self.call_stack.pop() {
+                    self.expr_stack.push(val);
+                    self.ip = ret_addr;
+                } else {
+                    return Ok(Some(val));
+                }
+            },
+
+            Instr::Intrinsic(intrinsic) => match intrinsic {
+                mir::Intrinsic::Out => {
+                    let c = self.pop_char()?;
+                    self.pop_universe()?;
+
+                    io.write_char(c).map_err(RuntimeErrorKind::Io)?;
+
+                    self.universe += 1;
+                    self.expr_stack.push(Value::Universe(self.universe));
                 },
-                Instr::Call(addr) => {
-                    call_stack.push(ip);
-                    ip = addr;
+                mir::Intrinsic::In => {
+                    self.pop_universe()?;
+
+                    let c = io
+                        .read_char()
+                        .map_err(RuntimeErrorKind::Io)?
+                        .ok_or_else(|| RuntimeErrorKind::Io(format!("Unexpected end of input")))?;
+
+                    self.universe += 1;
+                    self.allocated += 2;
+                    self.expr_stack.push(Value::make_list(
+                        std::iter::once(Value::Char(c)).chain(std::iter::once(Value::Universe(self.universe)))
+                    ));
                 },
-                Instr::Return(n) => {
-                    let val = expr_stack.pop().unwrap();
-                    expr_stack.truncate(expr_stack.len() - n as usize);
-                    if let Some(ret_addr) = call_stack.pop() {
-                        expr_stack.push(val);
-                        ip = ret_addr;
-                    } else {
-                        break val;
-                    }
+                mir::Intrinsic::Panic => {
+                    let msg = self.pop_list()?
+                        .iter()
+                        .filter_map(|c| c.clone().into_char())
+                        .collect();
+                    return Err(RuntimeErrorKind::Panic(msg));
                 },
-
-                Instr::Intrinsic(intrinsic) => {
-                    match intrinsic {
-                        mir::Intrinsic::Out => {
-                            let c = expr_stack.pop().unwrap();
-                            assert_eq!(expr_stack.pop().unwrap().into_universe_unchecked(), universe, "Forked universe");
-
-                            let c = c.into_char_unchecked();
-                            print!("{}", c);
-                            if c == '\n' {
-                                io::stdout().lock().flush().unwrap();
-                            }
-
-                            universe += 1;
-                            expr_stack.push(Value::Universe(universe));
-                        },
-                        mir::Intrinsic::In => {
-                            assert_eq!(expr_stack.pop().unwrap().into_universe_unchecked(), universe, "Forked universe");
-
-                            let c = io::stdin().lock().chars().next().map(|c| Value::Char(c.unwrap())).unwrap();
-
-                            universe += 1;
-                            expr_stack.push(Value::make_list(
-                                std::iter::once(c).chain(std::iter::once(Value::Universe(universe)))
-                            ));
-                        },
-                    }
+                mir::Intrinsic::Native(id) => {
+                    let native = natives
+                        .get(id)
+                        .ok_or_else(|| RuntimeErrorKind::InvalidProgram(format!("No native function with ID {}", id)))?;
+                    // Arguments are pushed in reverse, so the first argument is on top
+                    let args = (0..native.args.len()).map(|_| self.pop()).collect::<Result<Vec<_>, _>>()?;
+                    // Natives are outside of the verified program, so their results are checked before use. A function
+                    // with a bad address would otherwise be jumped to.
+                    let val = native
+                        .call(args)
+                        .and_then(|val| native.check_return(&val).map(|_| val))
+                        .and_then(|val| prog.verify_value(&val).map(|_| val))
+                        .map_err(|msg| RuntimeErrorKind::Native(native.name.clone(), msg))?;
+                    self.expr_stack.push(val);
                 },
-            }
-        };
-
-        if !prog.is_pure() {
-            assert_eq!(val.clone().into_list_unchecked()[1].clone().into_universe_unchecked(), universe, "Forked universe");
-            None
-        } else {
-            Some(val)
+            },
         }
+
+        Ok(None)
     }
 }
+
+#[cfg(test)]
+use super::BufferIo;
+
+#[test]
+fn runtime_errors() {
+    let run = |code: &[Instr]| {
+        let mut prog = Program::default();
+        code.iter().for_each(|instr| { prog.emit_instr(*instr); });
+        prog.set_pure(true);
+        Vm::default().execute(&prog, &mut BufferIo::default()).map_err(|err| err.kind)
+    };
+
+    assert_eq!(
+        run(&[Instr::Integer(0), Instr::Integer(1), Instr::DivNum, Instr::Return(0)]).unwrap_err(),
+        RuntimeErrorKind::DivideByZero,
+    );
+    assert_eq!(
+        run(&[Instr::Integer(1), Instr::IndexList(0), Instr::Return(0)]).unwrap_err(),
+        RuntimeErrorKind::TypeMismatch("list"),
+    );
+    assert_eq!(run(&[Instr::Pop, Instr::Return(0)]).unwrap_err(), RuntimeErrorKind::StackUnderflow);
+    // Execution would fall off the end of the code
+    assert!(matches!(run(&[Instr::Integer(1)]).unwrap_err(), RuntimeErrorKind::InvalidProgram(_)));
+    assert!(matches!(run(&[Instr::Jump(7), Instr::Return(0)]).unwrap_err(), RuntimeErrorKind::InvalidProgram(_)));
+    assert!(matches!(run(&[Instr::Integer(0), Instr::JumpTable(2), Instr::Return(0)]).unwrap_err(), RuntimeErrorKind::InvalidProgram(_)));
+    // Jump tables may only contain jumps
+    assert!(matches!(
+        run(&[Instr::Integer(0), Instr::JumpTable(2), Instr::Jump(4), Instr::Return(0), Instr::Integer(1), Instr::Return(0)]).unwrap_err(),
+        RuntimeErrorKind::InvalidProgram(_),
+    ));
+
+    // Indices past the end of a jump table take its last entry
+    let table = |idx| run(&[
+        Instr::Integer(idx),
+        Instr::JumpTable(2),
+        Instr::Jump(4),
+        Instr::Jump(6),
+        Instr::Integer(10),
+        Instr::Return(0),
+        Instr::Integer(20),
+        Instr::Return(0),
+    ]).unwrap().unwrap();
+    assert!(matches!(table(0), Value::Number(x) if x == 10.0));
+    assert!(matches!(table(1), Value::Number(x) if x == 20.0));
+    assert!(matches!(table(7), Value::Number(x) if x == 20.0));
+}
+
+#[test]
+fn native_results() {
+    use crate::embed::TypeDesc;
+
+    let run = |ret: TypeDesc, val: Value| {
+        let mut natives = Natives::default();
+        natives.register("make", Vec::new(), ret, move |_| Ok(val.clone()));
+        let mut prog = Program::default();
+        [
+            Instr::Intrinsic(mir::Intrinsic::Native(0)),
+            Instr::Return(0),
+        ].iter().for_each(|instr| { prog.emit_instr(*instr); });
+        prog.set_pure(true);
+        Vm::with_natives(natives).execute(&prog, &mut BufferIo::default()).map_err(|err| err.kind)
+    };
+
+    let func = TypeDesc::Func(Box::new(TypeDesc::Num), Box::new(TypeDesc::Num));
+    assert!(matches!(run(TypeDesc::Num, Value::Number(1.0)), Ok(Some(Value::Number(x))) if x == 1.0));
+    // Functions that point outside of the program must never be called
+    assert!(matches!(
+        run(func.clone(), Value::Func(Rc::new((0x1000, Vec::new())))),
+        Err(RuntimeErrorKind::Native(_, _)),
+    ));
+    assert!(matches!(
+        run(TypeDesc::List(Box::new(func)), Value::make_list(std::iter::once(Value::Func(Rc::new((0, vec![Value::Func(Rc::new((7, Vec::new())))])))))),
+        Err(RuntimeErrorKind::Native(_, _)),
+    ));
+    // Values must have the shape of the native's return type
+    assert!(matches!(run(TypeDesc::Num, Value::Boolean(true)), Err(RuntimeErrorKind::Native(_, _))));
+    assert!(matches!(
+        run(TypeDesc::Tuple(vec![TypeDesc::Num, TypeDesc::Char]), Value::make_list(std::iter::once(Value::Number(1.0)))),
+        Err(RuntimeErrorKind::Native(_, _)),
+    ));
+}
+
+#[test]
+fn buffered_io() {
+    let mut prog = Program::default();
+    [
+        Instr::Char('h'),
+        Instr::Intrinsic(mir::Intrinsic::Out),
+        Instr::Intrinsic(mir::Intrinsic::In),
+        Instr::Return(0),
+    ].iter().for_each(|instr| { prog.emit_instr(*instr); });
+
+    let mut io = BufferIo::new("ab");
+    assert!(matches!(Vm::default().execute(&prog, &mut io), Ok(None)));
+    assert_eq!(io.output(), "h");
+    assert_eq!(io.remaining_input(), "b");
+}
+
+#[test]
+fn limits() {
+    let run = |code: &[Instr], limits: Limits| {
+        let mut prog = Program::default();
+        code.iter().for_each(|instr| { prog.emit_instr(*instr); });
+        prog.set_pure(true);
+        let mut vm = Vm::default().with_limits(limits);
+        let res = vm.execute(&prog, &mut BufferIo::default()).map_err(|err| err.kind);
+        (vm, prog, res)
+    };
+
+    // Running out of fuel suspends execution, which may then be resumed
+    let (mut vm, prog, res) = run(
+        &[Instr::Integer(1), Instr::Integer(2), Instr::AddNum, Instr::Return(0)],
+        Limits { fuel: Some(2), ..Limits::default() },
+    );
+    assert_eq!(res.unwrap_err(), RuntimeErrorKind::OutOfFuel);
+    assert!(vm.is_suspended());
+    let val = vm.resume(&prog, 10, &mut BufferIo::default()).unwrap().unwrap();
+    assert!(matches!(val, Value::Number(x) if x == 3.0));
+    assert_eq!(vm.remaining_fuel(), Some(8));
+
+    let (_, _, res) = run(&[Instr::Jump(0)], Limits { fuel: Some(1000), ..Limits::default() });
+    assert_eq!(res.unwrap_err(), RuntimeErrorKind::OutOfFuel);
+    let (_, _, res) = run(&[Instr::Call(0), Instr::Return(0)], Limits { max_call_depth: Some(64), ..Limits::default() });
+    assert_eq!(res.unwrap_err(), RuntimeErrorKind::CallDepthExceeded);
+    let (_, _, res) = run(&[Instr::Integer(1), Instr::PushLocal, Instr::Jump(0)], Limits { max_locals: Some(64), ..Limits::default() });
+    assert_eq!(res.unwrap_err(), RuntimeErrorKind::LocalsExceeded);
+    let (_, _, res) = run(
+        &[Instr::Integer(1), Instr::MakeList(1), Instr::Pop, Instr::Jump(0)],
+        Limits { max_list_elements: Some(64), ..Limits::default() },
+    );
+    assert_eq!(res.unwrap_err(), RuntimeErrorKind::MemoryExceeded);
+    // Closure environments and copies of shared lists count towards the limit too
+    let (_, _, res) = run(
+        &[Instr::Integer(1), Instr::MakeFunc(1, 0), Instr::Pop, Instr::Jump(0)],
+        Limits { max_list_elements: Some(64), ..Limits::default() },
+    );
+    assert_eq!(res.unwrap_err(), RuntimeErrorKind::MemoryExceeded);
+    let (_, _, res) = run(
+        &[
+            Instr::Integer(1),
+            Instr::MakeList(1),
+            Instr::Dup,
+            Instr::Integer(2),
+            Instr::SetList(0),
+            Instr::Pop,
+            Instr::Jump(2),
+        ],
+        Limits { max_list_elements: Some(64), ..Limits::default() },
+    );
+    assert_eq!(res.unwrap_err(), RuntimeErrorKind::MemoryExceeded);
+}